@@ -0,0 +1,98 @@
+#![no_main]
+
+use std::collections::HashMap;
+
+use evm_dev_rs::{AccountOverride, Address, EvmBuilder, SpecId, StateOverride};
+use libfuzzer_sys::fuzz_target;
+use primitive_types::U256;
+
+/// Accounts holding the fuzzed code, so that calls and creates run nested frames.
+const ACCOUNTS: [Address; 3] = [
+    Address([0x11; 20]),
+    Address([0x22; 20]),
+    Address([0x33; 20]),
+];
+const CALLER: Address = Address([0xca; 20]);
+/// The gas of a mainnet block, which bounds the work of the fuzzed code.
+const GAS_LIMIT: u64 = 30_000_000;
+
+/// Picks a balance or a value from two bits, up to `U256::MAX` so that transfers can overflow.
+fn balance(bits: u8) -> U256 {
+    match bits & 0b11 {
+        0 => U256::zero(),
+        1 => U256::one(),
+        2 => U256::from(u64::MAX),
+        _ => U256::MAX,
+    }
+}
+
+/// Picks a nonce from two bits, up to `usize::MAX` so that incrementing it can overflow.
+fn nonce(bits: u8) -> usize {
+    match bits & 0b11 {
+        0 => 0,
+        1 => 1,
+        2 => usize::MAX - 1,
+        _ => usize::MAX,
+    }
+}
+
+// Runs arbitrary code on arbitrary balances and nonces, which must never panic whatever the
+// values it takes from the stack.
+//
+// The first four bytes pick the spec and the length of the calldata, the balances, the nonces
+// and the state overrides, the rest being the calldata then the code. The caller and the
+// seeded accounts each take two bits of the balance and nonce bytes, down to zero or up to
+// `U256::MAX` and `usize::MAX`. The low four bits of the override byte pick the accounts
+// overridden, the high ones their balance and nonce, along with the value of the transaction.
+//
+// The code runs at the first of the seeded accounts, which all hold it, so that it can call
+// itself and the others, and create contracts, up to the depth limit. The transaction has the
+// gas of a block, so code looping forever runs out of gas rather than timing out.
+fuzz_target!(|data: &[u8]| {
+    let Some((&[selector, balances, nonces, overrides], input)) = data.split_first_chunk() else {
+        return;
+    };
+    let spec = if selector & 1 == 0 {
+        SpecId::default()
+    } else {
+        SpecId::PragueEof
+    };
+    let calldata_len = usize::from(selector >> 1).min(input.len());
+    let (calldata, code) = input.split_at(calldata_len);
+
+    let addresses = [CALLER, ACCOUNTS[0], ACCOUNTS[1], ACCOUNTS[2]];
+    let mut state_override = StateOverride::new();
+    for (index, address) in addresses.into_iter().enumerate() {
+        if overrides >> index & 1 == 0 {
+            continue;
+        }
+        let account = AccountOverride {
+            balance: Some(balance(overrides >> 4 >> index)),
+            nonce: Some(nonce(overrides >> 6 >> index)),
+            state_diff: Some(HashMap::from([(U256::zero(), U256::MAX)])),
+            ..AccountOverride::default()
+        };
+        state_override.insert(address, account);
+    }
+
+    let mut builder = EvmBuilder::new()
+        .to(ACCOUNTS[0])
+        .caller(CALLER)
+        .origin(CALLER)
+        .value(balance(overrides >> 4))
+        .calldata(calldata)
+        .gas_limit(GAS_LIMIT)
+        .spec(spec)
+        .account(CALLER, nonce(nonces), balance(balances), vec![])
+        .state_override(state_override);
+    for (index, address) in ACCOUNTS.into_iter().enumerate() {
+        let shift = 2 * (index + 1);
+        builder = builder.account(
+            address,
+            nonce(nonces >> shift),
+            balance(balances >> shift),
+            code,
+        );
+    }
+    let _ = builder.run();
+});
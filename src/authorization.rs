@@ -0,0 +1,378 @@
+use primitive_types::U256;
+
+use crate::{
+    db::DatabaseError,
+    primitives::Address,
+    rlp::{encode_bytes, encode_list, encode_u256},
+    spec::SpecId,
+    state::State,
+    utils::{recover_address, sha3_hash},
+};
+
+/// Prefix of the code of an account delegating to another one (EIP-7702).
+pub const DELEGATION_PREFIX: [u8; 3] = [0xef, 0x01, 0x00];
+
+/// Magic byte prepended to authorizations before signing them.
+const AUTHORIZATION_MAGIC: u8 = 0x05;
+
+/// Half of the order of the secp256k1 curve, the highest `s` an authorization may use.
+const SECP256K1N_HALF: [u8; 32] = [
+    0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0x5d, 0x57, 0x6e, 0x73, 0x57, 0xa4, 0x50, 0x1d, 0xdf, 0xe9, 0x2f, 0x46, 0x68, 0x1b, 0x20, 0xa0,
+];
+
+/// A signed authorization of a set-code transaction (EIP-7702).
+///
+/// The signer of the authorization (the authority) delegates its code to `address`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Authorization {
+    /// The chain the authorization is valid on, zero for any chain.
+    pub chain_id: U256,
+    pub address: Address,
+    pub nonce: u64,
+    pub y_parity: u8,
+    pub r: U256,
+    pub s: U256,
+}
+
+impl Authorization {
+    /// Returns the hash signed by the authority: `keccak(0x05 || rlp([chain_id, address, nonce]))`.
+    pub fn signature_hash(&self) -> [u8; 32] {
+        let payload = encode_list(&[
+            encode_u256(self.chain_id),
+            encode_bytes(&self.address.0),
+            encode_u256(self.nonce.into()),
+        ]);
+        sha3_hash(&[vec![AUTHORIZATION_MAGIC], payload].concat())
+    }
+
+    /// Recovers the authority that signed the authorization.
+    pub fn authority(&self) -> Option<Address> {
+        if self.y_parity > 1 || self.s > U256::from_big_endian(&SECP256K1N_HALF) {
+            return None;
+        }
+        let address = recover_address(
+            &self.signature_hash(),
+            self.y_parity,
+            &self.r.to_big_endian(),
+            &self.s.to_big_endian(),
+        )?;
+        Some(Address(address))
+    }
+}
+
+/// Returns the code designating `address` as the delegate of an account.
+pub fn delegation_designator(address: Address) -> Vec<u8> {
+    [&DELEGATION_PREFIX[..], &address.0[..]].concat()
+}
+
+/// Returns the delegate of an account if its code is a delegation designator.
+pub fn delegated_address(code: &[u8]) -> Option<Address> {
+    if code.len() == DELEGATION_PREFIX.len() + 20 && code.starts_with(&DELEGATION_PREFIX) {
+        return Address::from_slice(&code[DELEGATION_PREFIX.len()..]);
+    }
+    None
+}
+
+/// Returns the code run by a call to `address`, which since Prague is the code of its delegate
+/// if the account delegates its code. Delegations are not followed any further.
+pub fn load_code(
+    state: &mut State,
+    address: Address,
+    spec: SpecId,
+) -> Result<Vec<u8>, DatabaseError> {
+    let code = state.get_code(address)?;
+    match delegated_address(&code) {
+        Some(delegate) if spec.is_enabled_in(SpecId::Prague) => state.get_code(delegate),
+        _ => Ok(code),
+    }
+}
+
+/// Applies the authorization list of a set-code transaction to the state.
+///
+/// Invalid authorizations are skipped without failing the transaction. Delegating to the
+/// zero address clears the code of the authority. Returns the number of authorities that
+/// already existed, whose authorization is partly refunded.
+pub fn apply_authorizations(
+    state: &mut State,
+    authorization_list: &[Authorization],
+    chain_id: U256,
+) -> Result<usize, DatabaseError> {
+    let mut existing_authorities = 0;
+    for authorization in authorization_list {
+        if !authorization.chain_id.is_zero() && authorization.chain_id != chain_id {
+            continue;
+        }
+        if authorization.nonce == u64::MAX {
+            continue;
+        }
+        let Some(authority) = authorization.authority() else {
+            continue;
+        };
+        state.access(authority);
+
+        // only accounts without code, or already delegating, may delegate.
+        let code = state.get_code(authority)?;
+        if !code.is_empty() && delegated_address(&code).is_none() {
+            continue;
+        }
+        if state.get_nonce(authority)? as u64 != authorization.nonce {
+            continue;
+        }
+
+        if state
+            .account(authority)?
+            .is_some_and(|account| !account.is_empty())
+        {
+            existing_authorities += 1;
+        }
+
+        if !state.increment_nonce(authority)? {
+            continue;
+        }
+        let code = if authorization.address.is_zero() {
+            vec![]
+        } else {
+            delegation_designator(authorization.address)
+        };
+        state.set_code(authority, code)?;
+    }
+    Ok(existing_authorities)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use hex_literal::hex;
+    use k256::ecdsa::SigningKey;
+
+    use super::*;
+    use crate::{
+        block::BlockEnv, db::InMemoryDB, outcome::Change, tx::TxEnv, EvmBuilder, HaltReason,
+        Session,
+    };
+
+    /// The address of the private key 1.
+    const AUTHORITY: Address = Address(hex!("7e5f4552091a69125d5dfcb7b8c2659029395bdf"));
+    const DELEGATE: Address = Address(hex!("00000000000000000000000000000000000000de"));
+
+    /// Returns an authorization signed with the private key 1.
+    fn sign(chain_id: u64, address: Address, nonce: u64) -> Authorization {
+        let mut authorization = Authorization {
+            chain_id: chain_id.into(),
+            address,
+            nonce,
+            y_parity: 0,
+            r: U256::zero(),
+            s: U256::zero(),
+        };
+        let key = SigningKey::from_slice(&U256::one().to_big_endian()).unwrap();
+        let (signature, recovery_id) = key
+            .sign_prehash_recoverable(&authorization.signature_hash())
+            .unwrap();
+        authorization.r = U256::from_big_endian(&signature.r().to_bytes());
+        authorization.s = U256::from_big_endian(&signature.s().to_bytes());
+        authorization.y_parity = recovery_id.to_byte();
+        authorization
+    }
+
+    /// PUSH1 1 PUSH0 SSTORE STOP
+    const DELEGATE_CODE: [u8; 5] = [0x60, 0x01, 0x5f, 0x55, 0x00];
+
+    fn state_with(authority_nonce: usize, authority_code: Vec<u8>) -> State {
+        let mut db = InMemoryDB::new();
+        db.insert_account(AUTHORITY, authority_nonce, U256::zero(), authority_code);
+        State::new(Arc::new(db))
+    }
+
+    #[test]
+    fn recovers_the_authority() {
+        assert_eq!(sign(1, DELEGATE, 0).authority(), Some(AUTHORITY));
+
+        // the malleable signature with the high `s` is rejected.
+        let mut authorization = sign(1, DELEGATE, 0);
+        let order = U256::from_big_endian(&hex!(
+            "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141"
+        ));
+        authorization.s = order - authorization.s;
+        authorization.y_parity ^= 1;
+        assert_eq!(authorization.authority(), None);
+
+        let mut authorization = sign(1, DELEGATE, 0);
+        authorization.y_parity = 2;
+        assert_eq!(authorization.authority(), None);
+    }
+
+    #[test]
+    fn valid_authorization_delegates_the_authority() {
+        let mut state = state_with(3, vec![]);
+        apply_authorizations(&mut state, &[sign(1, DELEGATE, 3)], 1.into()).unwrap();
+        assert_eq!(
+            state.get_code(AUTHORITY).unwrap(),
+            delegation_designator(DELEGATE)
+        );
+        assert_eq!(state.get_nonce(AUTHORITY).unwrap(), 4);
+
+        // a chain id of zero is valid on any chain, and the zero address clears the delegation.
+        apply_authorizations(&mut state, &[sign(0, Address::ZERO, 4)], 1.into()).unwrap();
+        assert!(state.get_code(AUTHORITY).unwrap().is_empty());
+        assert_eq!(state.get_nonce(AUTHORITY).unwrap(), 5);
+    }
+
+    #[test]
+    fn invalid_authorizations_are_skipped() {
+        let invalid = [
+            // another chain.
+            sign(2, DELEGATE, 0),
+            // another nonce.
+            sign(1, DELEGATE, 1),
+            sign(1, DELEGATE, u64::MAX),
+        ];
+        for authorization in invalid {
+            let mut state = state_with(0, vec![]);
+            apply_authorizations(&mut state, &[authorization], 1.into()).unwrap();
+            assert!(state.get_code(AUTHORITY).unwrap().is_empty());
+            assert_eq!(state.get_nonce(AUTHORITY).unwrap(), 0);
+        }
+
+        // an authority holding code that isn't a delegation.
+        let mut state = state_with(0, vec![0x00]);
+        apply_authorizations(&mut state, &[sign(1, DELEGATE, 0)], 1.into()).unwrap();
+        assert_eq!(state.get_code(AUTHORITY).unwrap(), [0x00]);
+        assert_eq!(state.get_nonce(AUTHORITY).unwrap(), 0);
+    }
+
+    #[test]
+    fn later_authorizations_see_the_earlier_ones() {
+        let mut state = state_with(0, vec![]);
+        let other = Address::from(0xdf);
+        let authorizations = [
+            sign(1, DELEGATE, 0),
+            sign(1, other, 1),
+            sign(1, DELEGATE, 1),
+        ];
+        apply_authorizations(&mut state, &authorizations, 1.into()).unwrap();
+        assert_eq!(
+            state.get_code(AUTHORITY).unwrap(),
+            delegation_designator(other)
+        );
+        assert_eq!(state.get_nonce(AUTHORITY).unwrap(), 2);
+    }
+
+    #[test]
+    fn load_code_follows_delegation_since_prague() {
+        let authority = Address::from(1);
+        let delegate = Address::from(2);
+        let mut db = InMemoryDB::new();
+        db.insert_account(authority, 0, U256::zero(), delegation_designator(delegate));
+        db.insert_account(delegate, 0, U256::zero(), vec![0x60, 0x01]);
+        let mut state = State::new(Arc::new(db));
+
+        assert_eq!(
+            load_code(&mut state, authority, SpecId::Prague).unwrap(),
+            [0x60, 0x01]
+        );
+        assert_eq!(
+            load_code(&mut state, authority, SpecId::Cancun).unwrap(),
+            delegation_designator(delegate)
+        );
+    }
+
+    #[test]
+    fn deployed_designator_is_not_followed() {
+        // the delegate would stop successfully, the designator itself starts with 0xef.
+        let delegate = Address::from(2);
+        let result = EvmBuilder::new()
+            .spec(SpecId::Prague)
+            .account(delegate, 0, U256::zero(), [0x00])
+            .deploy(delegation_designator(delegate))
+            .run()
+            .unwrap();
+
+        assert_eq!(
+            result.outcome.halt_reason(),
+            Some(HaltReason::OpcodeNotFound)
+        );
+    }
+
+    #[test]
+    fn self_sponsored_authorization_carries_the_incremented_nonce() {
+        let run = |nonce| {
+            EvmBuilder::new()
+                .spec(SpecId::Prague)
+                .chain_id(1)
+                .caller(AUTHORITY)
+                .to(AUTHORITY)
+                .account(DELEGATE, 0, U256::zero(), DELEGATE_CODE)
+                .authorization_list(vec![sign(1, DELEGATE, nonce)])
+                .run()
+                .unwrap()
+        };
+
+        // the nonce of the sender is incremented first, so the delegated code runs.
+        let result = run(1);
+        assert!(result.success);
+        let diff = &result.state_diff[&AUTHORITY];
+        assert_eq!(
+            diff.nonce,
+            Some(Change {
+                before: 0,
+                after: 2
+            })
+        );
+        assert_eq!(
+            diff.code,
+            Some(Change {
+                before: vec![],
+                after: delegation_designator(DELEGATE),
+            })
+        );
+        assert_eq!(diff.storage[&U256::zero()].after, U256::one());
+
+        // the nonce of the sender before the transaction is stale.
+        let result = run(0);
+        assert!(result.success);
+        let diff = &result.state_diff[&AUTHORITY];
+        assert_eq!(
+            diff.nonce,
+            Some(Change {
+                before: 0,
+                after: 1
+            })
+        );
+        assert_eq!(diff.code, None);
+        assert!(diff.storage.is_empty());
+    }
+
+    #[test]
+    fn session_runs_set_code_transactions() {
+        let mut db = InMemoryDB::new();
+        db.insert_account(DELEGATE, 0, U256::zero(), DELEGATE_CODE.to_vec());
+        let block_env = BlockEnv {
+            chain_id: 1,
+            ..BlockEnv::default()
+        };
+        let mut session = Session::new(db, block_env, SpecId::Prague);
+        let tx_env = TxEnv {
+            to: AUTHORITY,
+            caller: AUTHORITY,
+            origin: AUTHORITY,
+            ..TxEnv::default()
+        };
+
+        let result = session
+            .call_with_authorizations(tx_env, &[sign(1, DELEGATE, 1)])
+            .unwrap();
+        assert!(result.success);
+        assert_eq!(
+            session.code(AUTHORITY).unwrap(),
+            delegation_designator(DELEGATE)
+        );
+        assert_eq!(session.nonce(AUTHORITY).unwrap(), 2);
+        assert_eq!(
+            session.storage(AUTHORITY, U256::zero()).unwrap(),
+            U256::one()
+        );
+    }
+}
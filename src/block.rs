@@ -1,29 +1,65 @@
-/// Block data.
-#[derive(Default)]
-pub struct BlockData {
-    pub basefee: Vec<u8>,
-    pub coinbase: Vec<u8>,
-    pub timestamp: Vec<u8>,
-    pub number: Vec<u8>,
-    pub difficulty: Vec<u8>,
-    pub gaslimit: Vec<u8>,
-    pub chainid: Vec<u8>,
+use primitive_types::U256;
+
+use crate::{
+    builder::{word, BuilderError},
+    primitives::{Address, B256},
+    utils::saturating_u64,
+};
+
+/// The block environment of an execution.
+#[derive(Debug, Default, Clone)]
+pub struct BlockEnv {
+    pub basefee: U256,
+    pub coinbase: Address,
+    pub timestamp: u64,
+    pub number: u64,
+    pub difficulty: U256,
+    pub gas_limit: u64,
+    pub chain_id: u64,
+    /// The beacon chain randomness (EIP-4399), replacing `difficulty` since the Merge.
+    pub prevrandao: B256,
 }
 
-impl BlockData {
-    pub fn new(block_data: Vec<Vec<u8>>) -> BlockData {
-        if !block_data.is_empty() {
-            return Self {
-                basefee: block_data[0].clone(),
-                coinbase: block_data[1].clone(),
-                timestamp: block_data[2].clone(),
-                number: block_data[3].clone(),
-                difficulty: block_data[4].clone(),
-                gaslimit: block_data[5].clone(),
-                chainid: block_data[6].clone(),
-            };
+impl BlockEnv {
+    /// Reads the positional fields
+    /// `[basefee, coinbase, timestamp, number, difficulty, gaslimit, chainid, prevrandao]` as
+    /// big-endian words, the missing ones being left to zero. Numbers too large for a `u64`
+    /// saturate, words longer than 32 bytes are rejected.
+    ///
+    /// Blocks from before the Merge don't provide the prevrandao, which then falls back to the
+    /// difficulty it replaced.
+    pub fn new(block_data: Vec<Vec<u8>>) -> Result<BlockEnv, BuilderError> {
+        const FIELDS: [&str; 8] = [
+            "basefee",
+            "coinbase",
+            "timestamp",
+            "number",
+            "difficulty",
+            "gaslimit",
+            "chainid",
+            "prevrandao",
+        ];
+        let mut words = [U256::zero(); 8];
+        for ((word_slot, field), bytes) in words.iter_mut().zip(FIELDS).zip(&block_data) {
+            *word_slot = word(field, bytes)?;
         }
+        let [basefee, coinbase, timestamp, number, difficulty, gas_limit, chain_id, prevrandao] =
+            words;
+        let prevrandao = if block_data.len() < FIELDS.len() {
+            difficulty
+        } else {
+            prevrandao
+        };
 
-        Self::default()
+        Ok(BlockEnv {
+            basefee,
+            coinbase: Address::from_word(coinbase),
+            timestamp: saturating_u64(timestamp),
+            number: saturating_u64(number),
+            difficulty,
+            gas_limit: saturating_u64(gas_limit),
+            chain_id: saturating_u64(chain_id),
+            prevrandao: B256::from_word(prevrandao),
+        })
     }
 }
@@ -0,0 +1,334 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc, sync::Arc};
+
+use primitive_types::U256;
+use thiserror::Error;
+
+use crate::{
+    authorization::Authorization,
+    block::BlockEnv,
+    db::{Database, DatabaseError, EmptyDB, SharedDatabase},
+    errors::TransactionError,
+    inspector::{Inspector, SharedInspector},
+    overrides::{apply_overrides, StateOverride},
+    precompile::Precompiles,
+    primitives::{Address, B256},
+    run,
+    spec::SpecId,
+    state::{AddressData, State},
+    storage::Storage,
+    system::{apply_system_calls, SystemCallError, SystemCalls},
+    tx::TxEnv,
+    EvmResult,
+};
+
+/// An error in the configuration of an [`EvmBuilder`].
+#[derive(Debug, Error)]
+pub enum BuilderError {
+    #[error("`{field}` is {len} bytes long, a word is at most 32 bytes")]
+    InvalidWordLength { field: &'static str, len: usize },
+    #[error(transparent)]
+    Transaction(#[from] TransactionError),
+    #[error(transparent)]
+    Database(#[from] DatabaseError),
+    #[error(transparent)]
+    SystemCall(#[from] SystemCallError),
+}
+
+/// Configures and runs a single execution, replacing the positional arguments of [`evm`].
+///
+/// Every field is optional: addresses and words default to zero, the gas limit to unlimited
+/// and the spec to [`SpecId::default`]. When no code is given, the code of the `to` account
+/// of the pre-state runs.
+///
+/// [`evm`]: crate::evm
+#[derive(Clone, Default)]
+pub struct EvmBuilder {
+    code: Option<Vec<u8>>,
+    to: Address,
+    caller: Address,
+    origin: Address,
+    gas_price: U256,
+    value: U256,
+    calldata: Vec<u8>,
+    gas_limit: Option<u64>,
+    basefee: U256,
+    coinbase: Address,
+    timestamp: u64,
+    number: u64,
+    difficulty: U256,
+    prevrandao: B256,
+    block_gas_limit: u64,
+    chain_id: u64,
+    spec: SpecId,
+    precompiles: Option<Precompiles>,
+    db: Option<SharedDatabase>,
+    accounts: HashMap<Address, (usize, U256, Vec<u8>)>,
+    storage: Vec<(Address, U256, U256)>,
+    authorization_list: Vec<Authorization>,
+    system_calls: SystemCalls,
+    state_override: StateOverride,
+    inspector: Option<SharedInspector>,
+    create: bool,
+    mint_missing_value: bool,
+    unmetered: bool,
+}
+
+impl EvmBuilder {
+    pub fn new() -> EvmBuilder {
+        EvmBuilder::default()
+    }
+
+    /// Sets the code to run, instead of the code of the `to` account.
+    pub fn code(mut self, code: impl AsRef<[u8]>) -> Self {
+        self.code = Some(code.as_ref().to_vec());
+        self
+    }
+
+    /// Deploys `initcode` at the address derived from the caller and its nonce, instead of
+    /// running code at `to`. The output of the initcode becomes the code of the contract.
+    pub fn deploy(mut self, initcode: impl AsRef<[u8]>) -> Self {
+        self.code = Some(initcode.as_ref().to_vec());
+        self.create = true;
+        self
+    }
+
+    /// Sets the account executing the code, returned by `ADDRESS`.
+    pub fn to(mut self, address: impl Into<Address>) -> Self {
+        self.to = address.into();
+        self
+    }
+
+    /// Sets the sender of the message, returned by `CALLER`.
+    pub fn caller(mut self, address: impl Into<Address>) -> Self {
+        self.caller = address.into();
+        self
+    }
+
+    /// Sets the sender of the transaction, returned by `ORIGIN`.
+    pub fn origin(mut self, address: impl Into<Address>) -> Self {
+        self.origin = address.into();
+        self
+    }
+
+    pub fn gas_price(mut self, gas_price: U256) -> Self {
+        self.gas_price = gas_price;
+        self
+    }
+
+    pub fn value(mut self, value: U256) -> Self {
+        self.value = value;
+        self
+    }
+
+    pub fn calldata(mut self, calldata: impl AsRef<[u8]>) -> Self {
+        self.calldata = calldata.as_ref().to_vec();
+        self
+    }
+
+    /// Sets the gas limit of the transaction, unlimited by default.
+    pub fn gas_limit(mut self, gas_limit: u64) -> Self {
+        self.gas_limit = Some(gas_limit);
+        self
+    }
+
+    pub fn basefee(mut self, basefee: U256) -> Self {
+        self.basefee = basefee;
+        self
+    }
+
+    pub fn coinbase(mut self, address: impl Into<Address>) -> Self {
+        self.coinbase = address.into();
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn number(mut self, number: u64) -> Self {
+        self.number = number;
+        self
+    }
+
+    pub fn difficulty(mut self, difficulty: U256) -> Self {
+        self.difficulty = difficulty;
+        self
+    }
+
+    /// Sets the beacon chain randomness, returned by `PREVRANDAO` since the Merge.
+    pub fn prevrandao(mut self, prevrandao: B256) -> Self {
+        self.prevrandao = prevrandao;
+        self
+    }
+
+    /// Sets the gas limit of the block, returned by `GASLIMIT`.
+    pub fn block_gas_limit(mut self, gas_limit: u64) -> Self {
+        self.block_gas_limit = gas_limit;
+        self
+    }
+
+    pub fn chain_id(mut self, chain_id: u64) -> Self {
+        self.chain_id = chain_id;
+        self
+    }
+
+    pub fn spec(mut self, spec: SpecId) -> Self {
+        self.spec = spec;
+        self
+    }
+
+    /// Replaces the precompiles of the spec.
+    pub fn precompiles(mut self, precompiles: Precompiles) -> Self {
+        self.precompiles = Some(precompiles);
+        self
+    }
+
+    /// Sets the database holding the pre-state, empty by default.
+    pub fn database(mut self, db: impl Database + 'static) -> Self {
+        self.db = Some(Arc::new(db));
+        self
+    }
+
+    /// Adds an account to the pre-state, replacing any account at the same address, or the
+    /// one of the database.
+    pub fn account(
+        mut self,
+        address: impl Into<Address>,
+        nonce: usize,
+        balance: U256,
+        code: impl AsRef<[u8]>,
+    ) -> Self {
+        self.accounts
+            .insert(address.into(), (nonce, balance, code.as_ref().to_vec()));
+        self
+    }
+
+    /// Sets a storage slot of an account in the pre-state, over the value of the database.
+    pub fn storage(mut self, address: impl Into<Address>, slot: U256, value: U256) -> Self {
+        self.storage.push((address.into(), slot, value));
+        self
+    }
+
+    /// Sets the authorizations of an EIP-7702 set-code transaction, applied since Prague.
+    pub fn authorization_list(mut self, authorization_list: Vec<Authorization>) -> Self {
+        self.authorization_list = authorization_list;
+        self
+    }
+
+    /// Sets the inputs of the system calls run before the code (EIP-4788 and EIP-2935). A
+    /// failing system call makes the block invalid, and is returned as
+    /// [`BuilderError::SystemCall`] without running the code.
+    pub fn system_calls(mut self, system_calls: SystemCalls) -> Self {
+        self.system_calls = system_calls;
+        self
+    }
+
+    /// Overrides accounts of the pre-state, over the accounts set with [`EvmBuilder::account`]
+    /// and [`EvmBuilder::storage`].
+    pub fn state_override(mut self, state_override: StateOverride) -> Self {
+        self.state_override = state_override;
+        self
+    }
+
+    /// Sets the inspector observing the execution, which the caller can read once it ran.
+    pub fn inspector(mut self, inspector: Rc<RefCell<impl Inspector + 'static>>) -> Self {
+        self.inspector = Some(inspector);
+        self
+    }
+
+    /// Lets the transaction and its frames send value their sender doesn't hold, the sender
+    /// sending all it holds and the rest being created, as code predating balance checks
+    /// expects. Off by default: a caller holding less than the value makes the transaction
+    /// invalid, and a `CALL` or `CREATE` sending more than its sender holds fails.
+    pub fn mint_missing_value(mut self, mint_missing_value: bool) -> Self {
+        self.mint_missing_value = mint_missing_value;
+        self
+    }
+
+    /// Runs the code without charging any gas, `GAS` pushing the largest word, as code
+    /// predating gas metering expects. Off by default: the transaction pays its intrinsic gas
+    /// and every instruction, and the result reports the gas used.
+    pub fn unmetered(mut self, unmetered: bool) -> Self {
+        self.unmetered = unmetered;
+        self
+    }
+
+    /// Validates the configuration and runs the code.
+    pub fn run(self) -> Result<EvmResult, BuilderError> {
+        let tx_env = TxEnv {
+            to: self.to,
+            caller: self.caller,
+            origin: self.origin,
+            gas_price: self.gas_price,
+            value: self.value,
+            data: self.calldata,
+            gas_limit: self.gas_limit.unwrap_or(u64::MAX),
+        };
+        let block_env = BlockEnv {
+            basefee: self.basefee,
+            coinbase: self.coinbase,
+            timestamp: self.timestamp,
+            number: self.number,
+            difficulty: self.difficulty,
+            gas_limit: self.block_gas_limit,
+            chain_id: self.chain_id,
+            prevrandao: self.prevrandao,
+        };
+
+        let db = self.db.unwrap_or_else(|| Arc::new(EmptyDB));
+        let mut state = State::new(db.clone());
+        state.mint_missing_value = self.mint_missing_value;
+        for (account, (nonce, balance, code)) in self.accounts {
+            let data = AddressData {
+                nonce,
+                balance,
+                code,
+            };
+            state.set_account(account, data)?;
+        }
+        let mut storage = Storage::new(db);
+        for (account, slot, value) in self.storage {
+            storage.set_constract_slot(account, slot, value)?;
+        }
+        apply_overrides(&mut state, &mut storage, &self.state_override)?;
+
+        let spec = self.spec;
+        let precompiles = self.precompiles.unwrap_or_else(|| Precompiles::new(spec));
+
+        apply_system_calls(
+            &mut state,
+            &mut storage,
+            &block_env,
+            &self.system_calls,
+            spec,
+            &precompiles,
+        )?;
+
+        let result = run(
+            self.code,
+            &self.authorization_list,
+            tx_env,
+            block_env,
+            &mut state,
+            &mut storage,
+            spec,
+            precompiles,
+            self.create,
+            self.inspector,
+            self.unmetered,
+        )?;
+        Ok(result)
+    }
+}
+
+/// Reads a big-endian word, which fails rather than panicking if it is longer than 32 bytes.
+pub(crate) fn word(field: &'static str, bytes: &[u8]) -> Result<U256, BuilderError> {
+    if bytes.len() > 32 {
+        return Err(BuilderError::InvalidWordLength {
+            field,
+            len: bytes.len(),
+        });
+    }
+    Ok(U256::from_big_endian(bytes))
+}
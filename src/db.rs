@@ -0,0 +1,219 @@
+use std::{collections::HashMap, error::Error, sync::Arc};
+
+use primitive_types::U256;
+use thiserror::Error;
+
+use crate::{
+    primitives::{Address, B256},
+    utils::sha3_hash,
+};
+
+/// The hash of empty code, held by the accounts without code.
+pub const KECCAK_EMPTY: B256 = B256([
+    0xc5, 0xd2, 0x46, 0x01, 0x86, 0xf7, 0x23, 0x3c, 0x92, 0x7e, 0x7d, 0xb2, 0xdc, 0xc7, 0x03, 0xc0,
+    0xe5, 0x00, 0xb6, 0x53, 0xca, 0x82, 0x27, 0x3b, 0x7b, 0xfa, 0xd8, 0x04, 0x5d, 0x85, 0xa4, 0x70,
+]);
+
+/// An account as stored by a database, its code being looked up by hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountInfo {
+    pub nonce: usize,
+    pub balance: U256,
+    /// The hash of the code of the account, [`KECCAK_EMPTY`] for accounts without code.
+    pub code_hash: B256,
+}
+
+impl Default for AccountInfo {
+    fn default() -> AccountInfo {
+        AccountInfo {
+            nonce: 0,
+            balance: U256::zero(),
+            code_hash: KECCAK_EMPTY,
+        }
+    }
+}
+
+/// An error of a [`Database`] backend failing to read its state, such as a remote or on-disk
+/// store being unavailable. It aborts the transaction reading it.
+#[derive(Debug, Error)]
+#[error("database error: {0}")]
+pub struct DatabaseError(Box<dyn Error + Send + Sync>);
+
+impl DatabaseError {
+    pub fn new(error: impl Into<Box<dyn Error + Send + Sync>>) -> DatabaseError {
+        DatabaseError(error.into())
+    }
+}
+
+/// A backend holding the state the EVM runs on.
+///
+/// The EVM reads the backend through a cache holding the changes of the execution, and never
+/// writes to it. Backends are shared between threads, and report the reads they fail.
+pub trait Database: Send + Sync {
+    /// Returns the account at `address`, or `None` if there is none.
+    fn basic(&self, address: Address) -> Result<Option<AccountInfo>, DatabaseError>;
+
+    /// Returns the code whose hash is `code_hash`.
+    fn code_by_hash(&self, code_hash: B256) -> Result<Vec<u8>, DatabaseError>;
+
+    /// Returns the value of a storage slot of an account, zero if it was never set.
+    fn storage(&self, address: Address, slot: U256) -> Result<U256, DatabaseError>;
+
+    /// Returns the hash of the block `number`, zero if it is unknown.
+    fn block_hash(&self, number: u64) -> Result<B256, DatabaseError>;
+}
+
+/// A database shared by the frames of an execution, and by the threads holding its state.
+pub type SharedDatabase = Arc<dyn Database + Send + Sync>;
+
+/// Returns the hash identifying `code` in a database.
+pub fn code_hash(code: &[u8]) -> B256 {
+    B256(sha3_hash(code))
+}
+
+/// A database without accounts.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EmptyDB;
+
+impl Database for EmptyDB {
+    fn basic(&self, _address: Address) -> Result<Option<AccountInfo>, DatabaseError> {
+        Ok(None)
+    }
+
+    fn code_by_hash(&self, _code_hash: B256) -> Result<Vec<u8>, DatabaseError> {
+        Ok(vec![])
+    }
+
+    fn storage(&self, _address: Address, _slot: U256) -> Result<U256, DatabaseError> {
+        Ok(U256::zero())
+    }
+
+    fn block_hash(&self, _number: u64) -> Result<B256, DatabaseError> {
+        Ok(B256::ZERO)
+    }
+}
+
+/// A database held in memory.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryDB {
+    accounts: HashMap<Address, AccountInfo>,
+    contracts: HashMap<B256, Vec<u8>>,
+    storage: HashMap<Address, HashMap<U256, U256>>,
+    block_hashes: HashMap<u64, B256>,
+}
+
+impl InMemoryDB {
+    pub fn new() -> InMemoryDB {
+        InMemoryDB::default()
+    }
+
+    /// Inserts an account, replacing any account at the same address.
+    pub fn insert_account(&mut self, address: Address, nonce: usize, balance: U256, code: Vec<u8>) {
+        let code_hash = code_hash(&code);
+        if !code.is_empty() {
+            self.contracts.insert(code_hash, code);
+        }
+        self.accounts.insert(
+            address,
+            AccountInfo {
+                nonce,
+                balance,
+                code_hash,
+            },
+        );
+    }
+
+    pub fn insert_storage(&mut self, address: Address, slot: U256, value: U256) {
+        self.storage.entry(address).or_default().insert(slot, value);
+    }
+
+    pub fn insert_block_hash(&mut self, number: u64, hash: B256) {
+        self.block_hashes.insert(number, hash);
+    }
+}
+
+impl Database for InMemoryDB {
+    fn basic(&self, address: Address) -> Result<Option<AccountInfo>, DatabaseError> {
+        Ok(self.accounts.get(&address).cloned())
+    }
+
+    fn code_by_hash(&self, code_hash: B256) -> Result<Vec<u8>, DatabaseError> {
+        Ok(self.contracts.get(&code_hash).cloned().unwrap_or_default())
+    }
+
+    fn storage(&self, address: Address, slot: U256) -> Result<U256, DatabaseError> {
+        Ok(self
+            .storage
+            .get(&address)
+            .and_then(|slots| slots.get(&slot))
+            .copied()
+            .unwrap_or_default())
+    }
+
+    fn block_hash(&self, number: u64) -> Result<B256, DatabaseError> {
+        Ok(self.block_hashes.get(&number).copied().unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        block::BlockEnv, errors::TransactionError, session::Session, spec::SpecId, state::State,
+        storage::Storage, tx::TxEnv,
+    };
+
+    /// A database whose storage can't be read.
+    struct FailingStorageDB;
+
+    impl Database for FailingStorageDB {
+        fn basic(&self, _address: Address) -> Result<Option<AccountInfo>, DatabaseError> {
+            Ok(None)
+        }
+
+        fn code_by_hash(&self, _code_hash: B256) -> Result<Vec<u8>, DatabaseError> {
+            Ok(vec![])
+        }
+
+        fn storage(&self, _address: Address, _slot: U256) -> Result<U256, DatabaseError> {
+            Err(DatabaseError::new("storage unavailable"))
+        }
+
+        fn block_hash(&self, _number: u64) -> Result<B256, DatabaseError> {
+            Ok(B256::ZERO)
+        }
+    }
+
+    #[test]
+    fn state_is_shared_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<State>();
+        assert_send_sync::<Storage>();
+    }
+
+    #[test]
+    fn failed_read_in_a_child_frame_aborts_the_transaction() {
+        let mut session = Session::new(FailingStorageDB, BlockEnv::default(), SpecId::Cancun);
+        let caller = Address::from(0x1111);
+        let (outer, inner) = (Address::from(0x2222), Address::from(0x3333));
+        // CALL 0x3333 with no value nor data.
+        let call = [
+            0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x61, 0x33, 0x33, 0x5a,
+            0xf1,
+        ];
+        session.set_account(outer, 0, U256::zero(), call).unwrap();
+        // PUSH1 0 SLOAD
+        session
+            .set_account(inner, 0, U256::zero(), [0x60, 0x00, 0x54])
+            .unwrap();
+        let tx_env = TxEnv {
+            to: outer,
+            caller,
+            ..TxEnv::default()
+        };
+
+        let result = session.call(tx_env);
+        assert!(matches!(result, Err(TransactionError::Database(_))));
+        assert_eq!(session.nonce(caller).unwrap(), 0);
+    }
+}
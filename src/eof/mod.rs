@@ -0,0 +1,394 @@
+mod validation;
+
+use thiserror::Error;
+
+pub use validation::{instruction_size, relative_jump_targets, validate_eof, ContainerKind};
+
+/// Magic prefix of EOF containers (EIP-3540).
+pub const EOF_MAGIC: [u8; 2] = [0xef, 0x00];
+
+const EOF_VERSION: u8 = 0x01;
+
+const KIND_TYPES: u8 = 0x01;
+const KIND_CODE: u8 = 0x02;
+const KIND_CONTAINER: u8 = 0x03;
+const KIND_DATA: u8 = 0x04;
+const TERMINATOR: u8 = 0x00;
+
+const MAX_CODE_SECTIONS: usize = 1024;
+const MAX_CONTAINER_SECTIONS: usize = 256;
+
+/// Marker of the outputs of a code section that never returns.
+pub const NON_RETURNING: u8 = 0x80;
+
+/// Errors of the parsing and validation of EOF containers.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum EofError {
+    #[error("missing EOF magic")]
+    InvalidMagic,
+    #[error("unsupported EOF version {0}")]
+    InvalidVersion(u8),
+    #[error("missing {0} section header")]
+    MissingSectionHeader(&'static str),
+    #[error("header ended before the section sizes")]
+    TruncatedHeader,
+    #[error("missing header terminator")]
+    MissingTerminator,
+    #[error("invalid number of code sections")]
+    InvalidCodeSectionCount,
+    #[error("invalid number of container sections")]
+    InvalidContainerSectionCount,
+    #[error("types section size does not match the number of code sections")]
+    InvalidTypesSize,
+    #[error("empty section")]
+    EmptySection,
+    #[error("body is shorter than declared in the header")]
+    TruncatedBody,
+    #[error("body is longer than declared in the header")]
+    TrailingBytes,
+    #[error("first code section must take no inputs and never return")]
+    InvalidFirstSectionType,
+    #[error("invalid type of code section {section}")]
+    InvalidSectionType { section: usize },
+    #[error("undefined instruction 0x{opcode:02x} at {pc} in code section {section}")]
+    UndefinedInstruction {
+        section: usize,
+        pc: usize,
+        opcode: u8,
+    },
+    #[error("truncated immediate at {pc} in code section {section}")]
+    TruncatedImmediate { section: usize, pc: usize },
+    #[error("invalid relative jump at {pc} in code section {section}")]
+    InvalidRelativeJump { section: usize, pc: usize },
+    #[error("invalid code section index at {pc} in code section {section}")]
+    InvalidCodeSectionIndex { section: usize, pc: usize },
+    #[error("invalid container section index at {pc} in code section {section}")]
+    InvalidContainerSectionIndex { section: usize, pc: usize },
+    #[error("data offset out of bounds at {pc} in code section {section}")]
+    InvalidDataOffset { section: usize, pc: usize },
+    #[error("code section {section} does not end with a terminating instruction")]
+    MissingTerminatingInstruction { section: usize },
+    #[error("unreachable code at {pc} in code section {section}")]
+    UnreachableCode { section: usize, pc: usize },
+    #[error("code section {section} is never called")]
+    UnreachableCodeSection { section: usize },
+    #[error("stack underflow at {pc} in code section {section}")]
+    StackUnderflow { section: usize, pc: usize },
+    #[error("stack overflow at {pc} in code section {section}")]
+    StackOverflow { section: usize, pc: usize },
+    #[error("inconsistent stack height at {pc} in code section {section}")]
+    StackHeightMismatch { section: usize, pc: usize },
+    #[error(
+        "code section {section} declares a max stack increase of {declared}, computed {computed}"
+    )]
+    MaxStackMismatch {
+        section: usize,
+        declared: u16,
+        computed: usize,
+    },
+    #[error("invalid returning behaviour at {pc} in code section {section}")]
+    InvalidReturningBehaviour { section: usize, pc: usize },
+    #[error("code section {section} is declared returning but never returns")]
+    MissingReturn { section: usize },
+    #[error("container section {container} is used both as initcode and runtime code")]
+    ConflictingContainerKind { container: usize },
+    #[error("container section {container} is never referenced")]
+    UnreferencedContainer { container: usize },
+    #[error("invalid container section {container}: {error}")]
+    InvalidContainer {
+        container: usize,
+        error: Box<EofError>,
+    },
+    #[error("container sections are nested too deep")]
+    ContainerTooDeep,
+}
+
+/// Type of a code section: its inputs, outputs and maximum stack increase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TypeSection {
+    pub inputs: u8,
+    pub outputs: u8,
+    pub max_stack_increase: u16,
+}
+
+impl TypeSection {
+    pub fn is_non_returning(&self) -> bool {
+        self.outputs == NON_RETURNING
+    }
+}
+
+/// A decoded EOF container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Eof {
+    pub types: Vec<TypeSection>,
+    pub code_sections: Vec<Vec<u8>>,
+    pub container_sections: Vec<Vec<u8>>,
+    pub data: Vec<u8>,
+    /// The size of the data section declared in the header. The data of containers
+    /// deployed by `RETURNCONTRACT` may be shorter, auxiliary data being appended on deploy.
+    pub data_size: u16,
+    /// The encoded container.
+    pub raw: Vec<u8>,
+}
+
+impl Eof {
+    /// Returns true if the code is an EOF container, which does not mean it is valid.
+    pub fn is_eof(code: &[u8]) -> bool {
+        code.starts_with(&EOF_MAGIC)
+    }
+
+    /// Returns the encoding of the container with `aux_data` appended to its data section, as
+    /// deployed by `RETURNCONTRACT`, or `None` if the data ends up shorter than declared or
+    /// longer than a data section can be.
+    pub fn with_aux_data(&self, aux_data: &[u8]) -> Option<Vec<u8>> {
+        let data_size = self.data.len() + aux_data.len();
+        if data_size < self.data_size as usize {
+            return None;
+        }
+        let data_size = u16::try_from(data_size).ok()?;
+
+        // magic, version, types header, code header and optional container header.
+        let mut position = 2 + 1 + 3 + 3 + 2 * self.code_sections.len();
+        if !self.container_sections.is_empty() {
+            position += 3 + 4 * self.container_sections.len();
+        }
+        // skip the data section kind.
+        position += 1;
+
+        let mut raw = self.raw.clone();
+        raw[position..position + 2].copy_from_slice(&data_size.to_be_bytes());
+        raw.extend_from_slice(aux_data);
+        Some(raw)
+    }
+
+    /// Decodes a container, requiring its data section to be complete.
+    pub fn decode(bytes: &[u8]) -> Result<Eof, EofError> {
+        let eof = Eof::decode_partial(bytes)?;
+        if eof.data.len() < eof.data_size as usize {
+            return Err(EofError::TruncatedBody);
+        }
+        Ok(eof)
+    }
+
+    /// Decodes a container whose data section may be truncated.
+    pub fn decode_partial(bytes: &[u8]) -> Result<Eof, EofError> {
+        let mut reader = Reader { bytes, position: 0 };
+
+        if reader.read_bytes(2) != Some(&EOF_MAGIC[..]) {
+            return Err(EofError::InvalidMagic);
+        }
+        let version = reader.read_u8().ok_or(EofError::InvalidVersion(0))?;
+        if version != EOF_VERSION {
+            return Err(EofError::InvalidVersion(version));
+        }
+
+        // header
+        reader.expect_kind(KIND_TYPES, "types")?;
+        let types_size = reader.read_u16().ok_or(EofError::TruncatedHeader)? as usize;
+
+        reader.expect_kind(KIND_CODE, "code")?;
+        let code_sizes = reader.read_sizes(MAX_CODE_SECTIONS, 2)?;
+        if types_size != code_sizes.len() * 4 {
+            return Err(EofError::InvalidTypesSize);
+        }
+
+        let container_sizes = if reader.peek() == Some(KIND_CONTAINER) {
+            reader.position += 1;
+            reader
+                .read_sizes(MAX_CONTAINER_SECTIONS, 4)
+                .map_err(|error| match error {
+                    EofError::InvalidCodeSectionCount => EofError::InvalidContainerSectionCount,
+                    error => error,
+                })?
+        } else {
+            vec![]
+        };
+
+        reader.expect_kind(KIND_DATA, "data")?;
+        let data_size = reader.read_u16().ok_or(EofError::TruncatedHeader)?;
+
+        if reader.read_u8() != Some(TERMINATOR) {
+            return Err(EofError::MissingTerminator);
+        }
+
+        // body
+        let mut types = vec![];
+        for _ in 0..code_sizes.len() {
+            let section = reader.read_bytes(4).ok_or(EofError::TruncatedBody)?;
+            types.push(TypeSection {
+                inputs: section[0],
+                outputs: section[1],
+                max_stack_increase: u16::from_be_bytes([section[2], section[3]]),
+            });
+        }
+
+        let mut code_sections = vec![];
+        for size in code_sizes {
+            let code = reader.read_bytes(size).ok_or(EofError::TruncatedBody)?;
+            code_sections.push(code.to_vec());
+        }
+
+        let mut container_sections = vec![];
+        for size in container_sizes {
+            let container = reader.read_bytes(size).ok_or(EofError::TruncatedBody)?;
+            container_sections.push(container.to_vec());
+        }
+
+        let data = reader.bytes[reader.position..].to_vec();
+        if data.len() > data_size as usize {
+            return Err(EofError::TrailingBytes);
+        }
+
+        Ok(Eof {
+            types,
+            code_sections,
+            container_sections,
+            data,
+            data_size,
+            raw: bytes.to_vec(),
+        })
+    }
+}
+
+/// Cursor over the bytes of a container.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.position).copied()
+    }
+
+    fn read_bytes(&mut self, size: usize) -> Option<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position.checked_add(size)?)?;
+        self.position += size;
+        Some(bytes)
+    }
+
+    fn read_u8(&mut self) -> Option<u8> {
+        self.read_bytes(1).map(|bytes| bytes[0])
+    }
+
+    fn read_u16(&mut self) -> Option<u16> {
+        self.read_bytes(2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn expect_kind(&mut self, kind: u8, name: &'static str) -> Result<(), EofError> {
+        if self.read_u8() != Some(kind) {
+            return Err(EofError::MissingSectionHeader(name));
+        }
+        Ok(())
+    }
+
+    /// Reads a section count followed by the size of each section, encoded on `width` bytes.
+    fn read_sizes(&mut self, max_count: usize, width: usize) -> Result<Vec<usize>, EofError> {
+        let count = self.read_u16().ok_or(EofError::TruncatedHeader)? as usize;
+        if count == 0 || count > max_count {
+            return Err(EofError::InvalidCodeSectionCount);
+        }
+        let mut sizes = vec![];
+        for _ in 0..count {
+            let size = self.read_bytes(width).ok_or(EofError::TruncatedHeader)?;
+            let size = size
+                .iter()
+                .fold(0, |size, byte| (size << 8) | *byte as usize);
+            if size == 0 {
+                return Err(EofError::EmptySection);
+            }
+            sizes.push(size);
+        }
+        Ok(sizes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    /// A container with one non-returning code section made of `STOP` and two bytes of data.
+    const STOP_WITH_DATA: [u8; 22] = hex!(
+        "ef0001" "010004" "0200010001" "040002" "00"
+        "00800000"
+        "00"
+        "aabb"
+    );
+
+    #[test]
+    fn decodes_the_sections() {
+        let eof = Eof::decode(&STOP_WITH_DATA).unwrap();
+        assert_eq!(
+            eof.types,
+            [TypeSection {
+                inputs: 0,
+                outputs: NON_RETURNING,
+                max_stack_increase: 0,
+            }]
+        );
+        assert_eq!(eof.code_sections, [vec![0x00]]);
+        assert!(eof.container_sections.is_empty());
+        assert_eq!(eof.data, [0xaa, 0xbb]);
+        assert_eq!(eof.data_size, 2);
+        assert_eq!(eof.raw, STOP_WITH_DATA);
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        let with = |position: usize, byte: u8| {
+            let mut bytes = STOP_WITH_DATA;
+            bytes[position] = byte;
+            Eof::decode(&bytes)
+        };
+        assert_eq!(with(1, 0x01), Err(EofError::InvalidMagic));
+        assert_eq!(with(2, 0x02), Err(EofError::InvalidVersion(2)));
+        assert_eq!(with(3, 0x02), Err(EofError::MissingSectionHeader("types")));
+        assert_eq!(with(5, 0x08), Err(EofError::InvalidTypesSize));
+        assert_eq!(with(8, 0x00), Err(EofError::InvalidCodeSectionCount));
+        assert_eq!(with(10, 0x00), Err(EofError::EmptySection));
+        assert_eq!(with(11, 0x05), Err(EofError::MissingSectionHeader("data")));
+        assert_eq!(with(14, 0x01), Err(EofError::MissingTerminator));
+        assert_eq!(
+            Eof::decode(&STOP_WITH_DATA[..12]),
+            Err(EofError::TruncatedHeader)
+        );
+    }
+
+    #[test]
+    fn body_must_match_the_header() {
+        assert_eq!(
+            Eof::decode(&[&STOP_WITH_DATA[..], &[0xcc]].concat()),
+            Err(EofError::TrailingBytes)
+        );
+        assert_eq!(
+            Eof::decode(&STOP_WITH_DATA[..21]),
+            Err(EofError::TruncatedBody)
+        );
+        assert_eq!(
+            Eof::decode(&STOP_WITH_DATA[..18]),
+            Err(EofError::TruncatedBody)
+        );
+    }
+
+    #[test]
+    fn aux_data_completes_a_truncated_data_section() {
+        let eof = Eof::decode_partial(&STOP_WITH_DATA[..20]).unwrap();
+        assert!(eof.data.is_empty());
+        assert_eq!(eof.with_aux_data(&[0xaa]), None);
+        assert_eq!(
+            eof.with_aux_data(&[0xaa, 0xbb]).as_deref(),
+            Some(&STOP_WITH_DATA[..])
+        );
+
+        // appended data past the declared size grows the section.
+        let eof = Eof::decode(&STOP_WITH_DATA).unwrap();
+        let deployed = Eof::decode(&eof.with_aux_data(&[0xcc]).unwrap()).unwrap();
+        assert_eq!(deployed.data, [0xaa, 0xbb, 0xcc]);
+        assert_eq!(deployed.data_size, 3);
+    }
+}
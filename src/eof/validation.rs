@@ -0,0 +1,590 @@
+use crate::opcode::OpCode;
+
+use super::{Eof, EofError, NON_RETURNING};
+
+const STACK_LIMIT: usize = 1024;
+const MAX_SECTION_INPUTS: u8 = 127;
+const MAX_STACK_INCREASE: u16 = 1023;
+/// The deepest nesting of container sections, which bounds the recursion of the validation
+/// whatever the size of the container.
+const MAX_CONTAINER_DEPTH: usize = 256;
+
+/// Whether a container runs as initcode or as deployed code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerKind {
+    /// Initcode, deploying one of its container sections with `RETURNCONTRACT`.
+    Initcode,
+    /// Deployed code, ending with `STOP` or `RETURN`.
+    Runtime,
+}
+
+/// Validates the code sections of a container, then its container sections recursively.
+///
+/// This covers the instruction validation of EIP-3670, the code sections rules of EIP-4750
+/// and EIP-6206 and the stack validation of EIP-5450. Containers nested deeper than
+/// `MAX_CONTAINER_DEPTH` are invalid.
+pub fn validate_eof(eof: &Eof, kind: ContainerKind) -> Result<(), EofError> {
+    validate_container(eof, kind, 0)
+}
+
+/// Validates a container nested in `depth` containers.
+fn validate_container(eof: &Eof, kind: ContainerKind, depth: usize) -> Result<(), EofError> {
+    if depth > MAX_CONTAINER_DEPTH {
+        return Err(EofError::ContainerTooDeep);
+    }
+    validate_types(eof)?;
+
+    let mut container_kinds = vec![None; eof.container_sections.len()];
+
+    // every code section must be reachable from the first one through `CALLF` and `JUMPF`.
+    let mut reached = vec![false; eof.code_sections.len()];
+    reached[0] = true;
+    let mut sections = vec![0];
+    while let Some(section) = sections.pop() {
+        let called_sections = validate_instructions(eof, section, kind, &mut container_kinds)?;
+        validate_stack(eof, section)?;
+
+        for called in called_sections {
+            if !reached[called] {
+                reached[called] = true;
+                sections.push(called);
+            }
+        }
+    }
+    if let Some(section) = reached.iter().position(|reached| !reached) {
+        return Err(EofError::UnreachableCodeSection { section });
+    }
+
+    for (container, kind) in container_kinds.into_iter().enumerate() {
+        let Some(kind) = kind else {
+            return Err(EofError::UnreferencedContainer { container });
+        };
+        let invalid_container = |error| EofError::InvalidContainer {
+            container,
+            error: Box::new(error),
+        };
+
+        // the data of deployed containers is completed by `RETURNCONTRACT`.
+        let bytes = &eof.container_sections[container];
+        let sub_container = match kind {
+            ContainerKind::Initcode => Eof::decode(bytes),
+            ContainerKind::Runtime => Eof::decode_partial(bytes),
+        }
+        .map_err(invalid_container)?;
+        validate_container(&sub_container, kind, depth + 1).map_err(invalid_container)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the size of the instruction at `pc`, its opcode and immediates included.
+pub fn instruction_size(code: &[u8], pc: usize, opcode: OpCode) -> usize {
+    match opcode {
+        OpCode::Rjumpv => {
+            let max_index = code.get(pc + 1).copied().unwrap_or_default() as usize;
+            2 + (max_index + 1) * 2
+        }
+        _ => 1 + opcode.immediate_size(),
+    }
+}
+
+/// Returns the targets of the relative jump at `pc`, which may be out of the code bounds.
+pub fn relative_jump_targets(code: &[u8], pc: usize, opcode: OpCode) -> Vec<isize> {
+    let next = (pc + instruction_size(code, pc, opcode)) as isize;
+    let offsets = match opcode {
+        OpCode::Rjump | OpCode::Rjumpi => &code[pc + 1..pc + 3],
+        OpCode::Rjumpv => &code[pc + 2..pc + instruction_size(code, pc, opcode)],
+        _ => return vec![],
+    };
+    offsets
+        .chunks(2)
+        .map(|offset| next + i16::from_be_bytes([offset[0], offset[1]]) as isize)
+        .collect()
+}
+
+fn validate_types(eof: &Eof) -> Result<(), EofError> {
+    let first = eof.types[0];
+    if first.inputs != 0 || !first.is_non_returning() {
+        return Err(EofError::InvalidFirstSectionType);
+    }
+
+    for (section, types) in eof.types.iter().enumerate() {
+        if types.inputs > MAX_SECTION_INPUTS
+            || (types.outputs > MAX_SECTION_INPUTS && types.outputs != NON_RETURNING)
+            || types.max_stack_increase > MAX_STACK_INCREASE
+        {
+            return Err(EofError::InvalidSectionType { section });
+        }
+    }
+    Ok(())
+}
+
+/// Validates the instructions of a code section, returning the sections it calls.
+fn validate_instructions(
+    eof: &Eof,
+    section: usize,
+    kind: ContainerKind,
+    container_kinds: &mut [Option<ContainerKind>],
+) -> Result<Vec<usize>, EofError> {
+    let code = &eof.code_sections[section];
+    let types = eof.types[section];
+
+    let mut is_instruction_start = vec![false; code.len()];
+    let mut jumps = vec![];
+    let mut called_sections = vec![];
+    let mut returns = false;
+    let mut last_opcode = None;
+
+    let mut pc = 0;
+    while pc < code.len() {
+        let undefined = EofError::UndefinedInstruction {
+            section,
+            pc,
+            opcode: code[pc],
+        };
+        let opcode = match OpCode::new(code[pc]) {
+            Some(opcode) if !opcode.is_legacy_only() => opcode,
+            _ => return Err(undefined),
+        };
+
+        // `RJUMPV` needs its table length to know the size of its immediates.
+        let size = instruction_size(code, pc, opcode);
+        if pc + size > code.len() || (opcode == OpCode::Rjumpv && pc + 1 >= code.len()) {
+            return Err(EofError::TruncatedImmediate { section, pc });
+        }
+        let immediate = |offset: usize| {
+            u16::from_be_bytes([code[pc + 1 + offset], code[pc + 2 + offset]]) as usize
+        };
+
+        is_instruction_start[pc] = true;
+        match opcode {
+            OpCode::Rjump | OpCode::Rjumpi | OpCode::Rjumpv => jumps.push(pc),
+            OpCode::Callf | OpCode::Jumpf => {
+                let target = immediate(0);
+                let Some(target_types) = eof.types.get(target) else {
+                    return Err(EofError::InvalidCodeSectionIndex { section, pc });
+                };
+
+                // non-returning sections are only reached with `JUMPF`, and returning sections
+                // can't be jumped to from a non-returning one.
+                let invalid = match opcode {
+                    OpCode::Callf => target_types.is_non_returning(),
+                    _ => types.is_non_returning() && !target_types.is_non_returning(),
+                };
+                if invalid {
+                    return Err(EofError::InvalidReturningBehaviour { section, pc });
+                }
+                if opcode == OpCode::Jumpf && !target_types.is_non_returning() {
+                    returns = true;
+                }
+                called_sections.push(target);
+            }
+            OpCode::Retf => {
+                if types.is_non_returning() {
+                    return Err(EofError::InvalidReturningBehaviour { section, pc });
+                }
+                returns = true;
+            }
+            OpCode::Dataloadn if immediate(0) + 32 > eof.data_size as usize => {
+                return Err(EofError::InvalidDataOffset { section, pc });
+            }
+            OpCode::Eofcreate | OpCode::Returncontract => {
+                let container = code[pc + 1] as usize;
+                let container_kind = match opcode {
+                    OpCode::Eofcreate => ContainerKind::Initcode,
+                    _ if kind == ContainerKind::Runtime => return Err(undefined),
+                    _ => ContainerKind::Runtime,
+                };
+                match container_kinds.get_mut(container) {
+                    None => return Err(EofError::InvalidContainerSectionIndex { section, pc }),
+                    Some(Some(existing)) if *existing != container_kind => {
+                        return Err(EofError::ConflictingContainerKind { container })
+                    }
+                    Some(existing) => *existing = Some(container_kind),
+                }
+            }
+            // initcode ends with `RETURNCONTRACT`.
+            OpCode::Stop | OpCode::Return if kind == ContainerKind::Initcode => {
+                return Err(undefined)
+            }
+            _ => {}
+        }
+
+        last_opcode = Some(opcode);
+        pc += size;
+    }
+
+    match last_opcode {
+        Some(opcode) if opcode.is_terminating() || opcode == OpCode::Rjump => {}
+        _ => return Err(EofError::MissingTerminatingInstruction { section }),
+    }
+
+    for pc in jumps {
+        let opcode = OpCode::new(code[pc]).ok_or(EofError::InvalidRelativeJump { section, pc })?;
+        for target in relative_jump_targets(code, pc, opcode) {
+            let is_valid = usize::try_from(target)
+                .ok()
+                .and_then(|target| is_instruction_start.get(target).copied())
+                .unwrap_or(false);
+            if !is_valid {
+                return Err(EofError::InvalidRelativeJump { section, pc });
+            }
+        }
+    }
+
+    if !types.is_non_returning() && !returns {
+        return Err(EofError::MissingReturn { section });
+    }
+
+    Ok(called_sections)
+}
+
+/// Computes the bounds of the stack height before each instruction of a code section in a
+/// single forward pass, checking for underflows, overflows and the declared max stack increase.
+fn validate_stack(eof: &Eof, section: usize) -> Result<(), EofError> {
+    let code = &eof.code_sections[section];
+    let types = eof.types[section];
+    let section_inputs = types.inputs as usize;
+
+    let mut heights: Vec<Option<(usize, usize)>> = vec![None; code.len()];
+    heights[0] = Some((section_inputs, section_inputs));
+    let mut max_height = section_inputs;
+
+    let mut pc = 0;
+    while pc < code.len() {
+        let Some((min, max)) = heights[pc] else {
+            return Err(EofError::UnreachableCode { section, pc });
+        };
+        let opcode = OpCode::new(code[pc]).ok_or(EofError::UndefinedInstruction {
+            section,
+            pc,
+            opcode: code[pc],
+        })?;
+        let immediate = code.get(pc + 1).copied().unwrap_or_default() as usize;
+        let target_types = || eof.types[(immediate << 8) | code[pc + 2] as usize];
+
+        let (inputs, outputs) = match opcode {
+            OpCode::Callf => {
+                let target = target_types();
+                if max + target.max_stack_increase as usize > STACK_LIMIT {
+                    return Err(EofError::StackOverflow { section, pc });
+                }
+                (target.inputs as usize, target.outputs as usize)
+            }
+            OpCode::Retf => {
+                if min != max || min != types.outputs as usize {
+                    return Err(EofError::StackHeightMismatch { section, pc });
+                }
+                (0, 0)
+            }
+            OpCode::Jumpf => {
+                let target = target_types();
+                if max + target.max_stack_increase as usize > STACK_LIMIT {
+                    return Err(EofError::StackOverflow { section, pc });
+                }
+                if target.is_non_returning() {
+                    (target.inputs as usize, 0)
+                } else {
+                    // the outputs of the current section are made of the outputs of the target.
+                    let expected = (types.outputs as usize + target.inputs as usize)
+                        .checked_sub(target.outputs as usize)
+                        .ok_or(EofError::InvalidReturningBehaviour { section, pc })?;
+                    if min != max || min != expected {
+                        return Err(EofError::StackHeightMismatch { section, pc });
+                    }
+                    (0, 0)
+                }
+            }
+            OpCode::Dupn => (immediate + 1, immediate + 2),
+            OpCode::Swapn => (immediate + 2, immediate + 2),
+            OpCode::Exchange => {
+                let items = (immediate >> 4) + (immediate & 0x0f) + 3;
+                (items, items)
+            }
+            _ => opcode.stack_io(),
+        };
+
+        if min < inputs {
+            return Err(EofError::StackUnderflow { section, pc });
+        }
+        let (min, max) = (min - inputs + outputs, max - inputs + outputs);
+        if max > STACK_LIMIT {
+            return Err(EofError::StackOverflow { section, pc });
+        }
+        max_height = max_height.max(max);
+
+        let next = pc + instruction_size(code, pc, opcode);
+        let mut successors: Vec<usize> = relative_jump_targets(code, pc, opcode)
+            .into_iter()
+            .map(|target| target as usize)
+            .collect();
+        if !opcode.is_terminating() && opcode != OpCode::Rjump {
+            successors.push(next);
+        }
+
+        for successor in successors {
+            let Some(height) = heights.get_mut(successor) else {
+                continue;
+            };
+            match height {
+                // forward jumps widen the bounds, backward jumps must match them exactly.
+                Some((successor_min, successor_max)) if successor > pc => {
+                    *successor_min = (*successor_min).min(min);
+                    *successor_max = (*successor_max).max(max);
+                }
+                Some(bounds) => {
+                    if *bounds != (min, max) {
+                        return Err(EofError::StackHeightMismatch { section, pc });
+                    }
+                }
+                None if successor > pc => *height = Some((min, max)),
+                None => return Err(EofError::StackHeightMismatch { section, pc }),
+            }
+        }
+
+        pc = next;
+    }
+
+    let computed = max_height - section_inputs;
+    if computed != types.max_stack_increase as usize {
+        return Err(EofError::MaxStackMismatch {
+            section,
+            declared: types.max_stack_increase,
+            computed,
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A code section: its inputs, outputs, max stack increase and code.
+    type Section<'a> = (u8, u8, u16, &'a [u8]);
+
+    /// Encodes a container of the code and container sections, without data.
+    fn container(sections: &[Section], containers: &[&[u8]]) -> Vec<u8> {
+        let mut bytes = vec![0xef, 0x00, 0x01, 0x01];
+        bytes.extend_from_slice(&(sections.len() as u16 * 4).to_be_bytes());
+        bytes.push(0x02);
+        bytes.extend_from_slice(&(sections.len() as u16).to_be_bytes());
+        for (_, _, _, code) in sections {
+            bytes.extend_from_slice(&(code.len() as u16).to_be_bytes());
+        }
+        if !containers.is_empty() {
+            bytes.push(0x03);
+            bytes.extend_from_slice(&(containers.len() as u16).to_be_bytes());
+            for container in containers {
+                bytes.extend_from_slice(&(container.len() as u32).to_be_bytes());
+            }
+        }
+        bytes.extend_from_slice(&[0x04, 0x00, 0x00, 0x00]);
+        for (inputs, outputs, max_stack_increase, _) in sections {
+            bytes.extend_from_slice(&[*inputs, *outputs]);
+            bytes.extend_from_slice(&max_stack_increase.to_be_bytes());
+        }
+        for (_, _, _, code) in sections {
+            bytes.extend_from_slice(code);
+        }
+        for container in containers {
+            bytes.extend_from_slice(container);
+        }
+        bytes
+    }
+
+    fn validate(bytes: &[u8], kind: ContainerKind) -> Result<(), EofError> {
+        validate_eof(&Eof::decode(bytes)?, kind)
+    }
+
+    /// Validates deployed code made of a single non-returning section.
+    fn validate_code(max_stack_increase: u16, code: &[u8]) -> Result<(), EofError> {
+        let bytes = container(&[(0, NON_RETURNING, max_stack_increase, code)], &[]);
+        validate(&bytes, ContainerKind::Runtime)
+    }
+
+    #[test]
+    fn valid_code_sections() {
+        // STOP
+        assert_eq!(validate_code(0, &[0x00]), Ok(()));
+        // PUSH0 PUSH0 RETURN
+        assert_eq!(validate_code(2, &[0x5f, 0x5f, 0xf3]), Ok(()));
+        // PUSH0 RJUMPI(1) STOP STOP, both branches reaching a STOP.
+        assert_eq!(
+            validate_code(1, &[0x5f, 0xe1, 0x00, 0x01, 0x00, 0x00]),
+            Ok(())
+        );
+        // RJUMP(-3), looping forever.
+        assert_eq!(validate_code(0, &[0xe0, 0xff, 0xfd]), Ok(()));
+    }
+
+    #[test]
+    fn max_stack_increase_must_be_exact() {
+        assert_eq!(
+            validate_code(1, &[0x5f, 0x5f, 0xf3]),
+            Err(EofError::MaxStackMismatch {
+                section: 0,
+                declared: 1,
+                computed: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_instructions() {
+        // JUMP is a legacy instruction.
+        assert_eq!(
+            validate_code(1, &[0x5f, 0x56, 0x00]),
+            Err(EofError::UndefinedInstruction {
+                section: 0,
+                pc: 1,
+                opcode: 0x56,
+            })
+        );
+        assert_eq!(
+            validate_code(0, &[0x60]),
+            Err(EofError::TruncatedImmediate { section: 0, pc: 0 })
+        );
+        assert_eq!(
+            validate_code(1, &[0x5f]),
+            Err(EofError::MissingTerminatingInstruction { section: 0 })
+        );
+        // RJUMP(1) into the immediate of PUSH1.
+        assert_eq!(
+            validate_code(1, &[0xe0, 0x00, 0x01, 0x60, 0x00, 0x00]),
+            Err(EofError::InvalidRelativeJump { section: 0, pc: 0 })
+        );
+        // DATALOADN(0) reading past an empty data section.
+        assert_eq!(
+            validate_code(1, &[0xd1, 0x00, 0x00, 0x00]),
+            Err(EofError::InvalidDataOffset { section: 0, pc: 0 })
+        );
+    }
+
+    #[test]
+    fn invalid_stacks() {
+        // ADD
+        assert_eq!(
+            validate_code(0, &[0x01, 0x00]),
+            Err(EofError::StackUnderflow { section: 0, pc: 0 })
+        );
+        assert_eq!(
+            validate_code(0, &[0x00, 0x00]),
+            Err(EofError::UnreachableCode { section: 0, pc: 1 })
+        );
+        // PUSH0 RJUMP(-4), growing the stack on each iteration.
+        assert_eq!(
+            validate_code(1, &[0x5f, 0xe0, 0xff, 0xfc]),
+            Err(EofError::StackHeightMismatch { section: 0, pc: 1 })
+        );
+    }
+
+    #[test]
+    fn code_sections_must_be_typed_and_reachable() {
+        let bytes = container(&[(0, 0, 0, &[0x00])], &[]);
+        assert_eq!(
+            validate(&bytes, ContainerKind::Runtime),
+            Err(EofError::InvalidFirstSectionType)
+        );
+
+        let stop: Section = (0, NON_RETURNING, 0, &[0x00]);
+        let bytes = container(&[stop, stop], &[]);
+        assert_eq!(
+            validate(&bytes, ContainerKind::Runtime),
+            Err(EofError::UnreachableCodeSection { section: 1 })
+        );
+
+        // CALLF(1) STOP, calling RETF.
+        let bytes = container(
+            &[
+                (0, NON_RETURNING, 0, &[0xe3, 0x00, 0x01, 0x00]),
+                (0, 0, 0, &[0xe4]),
+            ],
+            &[],
+        );
+        assert_eq!(validate(&bytes, ContainerKind::Runtime), Ok(()));
+
+        // CALLF(1) to a non-returning section.
+        let bytes = container(
+            &[(0, NON_RETURNING, 0, &[0xe3, 0x00, 0x01, 0x00]), stop],
+            &[],
+        );
+        assert_eq!(
+            validate(&bytes, ContainerKind::Runtime),
+            Err(EofError::InvalidReturningBehaviour { section: 0, pc: 0 })
+        );
+
+        // JUMPF(1) to a non-returning section.
+        let bytes = container(&[(0, NON_RETURNING, 0, &[0xe5, 0x00, 0x01]), stop], &[]);
+        assert_eq!(validate(&bytes, ContainerKind::Runtime), Ok(()));
+    }
+
+    #[test]
+    fn initcode_returns_a_runtime_container() {
+        let runtime = container(&[(0, NON_RETURNING, 0, &[0x00])], &[]);
+        // PUSH0 PUSH0 RETURNCONTRACT(0)
+        let returncontract: Section = (0, NON_RETURNING, 2, &[0x5f, 0x5f, 0xee, 0x00]);
+        let initcode = container(&[returncontract], &[&runtime]);
+        assert_eq!(validate(&initcode, ContainerKind::Initcode), Ok(()));
+
+        // initcode can't end with STOP or RETURN, nor runtime code with RETURNCONTRACT.
+        assert_eq!(
+            validate(&runtime, ContainerKind::Initcode),
+            Err(EofError::UndefinedInstruction {
+                section: 0,
+                pc: 0,
+                opcode: 0x00,
+            })
+        );
+        assert_eq!(
+            validate(&initcode, ContainerKind::Runtime),
+            Err(EofError::UndefinedInstruction {
+                section: 0,
+                pc: 2,
+                opcode: 0xee,
+            })
+        );
+
+        // the deployed container is validated as runtime code.
+        let initcode = container(&[returncontract], &[&initcode]);
+        assert!(matches!(
+            validate(&initcode, ContainerKind::Initcode),
+            Err(EofError::InvalidContainer { container: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn container_sections_must_be_referenced() {
+        let runtime = container(&[(0, NON_RETURNING, 0, &[0x00])], &[]);
+        let bytes = container(&[(0, NON_RETURNING, 0, &[0x00])], &[&runtime]);
+        assert_eq!(
+            validate(&bytes, ContainerKind::Runtime),
+            Err(EofError::UnreferencedContainer { container: 0 })
+        );
+    }
+
+    #[test]
+    fn container_sections_nest_up_to_the_depth_limit() {
+        // PUSH0 PUSH0 REVERT, nested in initcode running PUSH0 x4 EOFCREATE(0) POP before it.
+        let innermost: Section = (0, NON_RETURNING, 2, &[0x5f, 0x5f, 0xfd]);
+        let eofcreate: Section = (
+            0,
+            NON_RETURNING,
+            4,
+            &[0x5f, 0x5f, 0x5f, 0x5f, 0xec, 0x00, 0x50, 0x5f, 0x5f, 0xfd],
+        );
+        let mut bytes = container(&[innermost], &[]);
+        for _ in 0..MAX_CONTAINER_DEPTH {
+            bytes = container(&[eofcreate], &[&bytes]);
+        }
+        assert_eq!(validate(&bytes, ContainerKind::Initcode), Ok(()));
+
+        let bytes = container(&[eofcreate], &[&bytes]);
+        let mut error = validate(&bytes, ContainerKind::Initcode).unwrap_err();
+        while let EofError::InvalidContainer { error: inner, .. } = error {
+            error = *inner;
+        }
+        assert_eq!(error, EofError::ContainerTooDeep);
+    }
+}
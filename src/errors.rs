@@ -1,21 +1,122 @@
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::{db::DatabaseError, overrides::OverrideError};
+
 #[derive(Debug, Error)]
 pub enum ExecutionError {
     #[error("invalid opcode")]
     InvalidOpcode,
+    #[error("designated invalid opcode")]
+    InvalidFEOpcode,
     #[error("invalid jump destination")]
     InvalidJumpDestination,
     #[error("execution halt")]
     Halt,
+    #[error("return opcode")]
+    Return,
+    #[error("selfdestruct opcode")]
+    SelfDestruct,
     #[error("stack underflow")]
     StackUnderflow,
+    #[error("stack overflow")]
+    StackOverflow,
     #[error("integer underflow")]
     IntegerOverflow,
+    #[error("out of gas")]
+    OutOfGas,
     #[error("revert opcode")]
     Revert,
     #[error("execution is read only")]
     ReadOnly,
-    #[error("contract address already exists")]
-    ContractAddressCollision,
+    #[error("invalid EOF container")]
+    InvalidEof,
+    #[error("call target is not a valid address")]
+    InvalidExtCallTarget,
+    #[error("EOF container data is shorter than declared")]
+    EofAuxDataTooSmall,
+    #[error("balance overflow")]
+    BalanceOverflow,
+    #[error(transparent)]
+    Database(#[from] DatabaseError),
+}
+
+/// Errors making a transaction invalid, which then runs none of its code.
+#[derive(Debug, Error)]
+pub enum TransactionError {
+    #[error("the caller holds less than the value of the transaction")]
+    InsufficientBalance,
+    #[error("the value of the transaction overflows the balance of the recipient")]
+    BalanceOverflow,
+    #[error("the nonce of the caller is at its maximum")]
+    NonceOverflow,
+    #[error("the gas limit {gas_limit} is below the {intrinsic_gas} gas the transaction costs")]
+    IntrinsicGasTooLow { gas_limit: u64, intrinsic_gas: u64 },
+    #[error(transparent)]
+    Override(#[from] OverrideError),
+    #[error(transparent)]
+    Database(#[from] DatabaseError),
+}
+
+/// The reason of an exceptional halt.
+///
+/// Unlike `REVERT`, an exceptional halt consumes all the gas of the frame and
+/// discards its return data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HaltReason {
+    /// An undefined opcode was encountered.
+    OpcodeNotFound,
+    /// The designated `INVALID` (0xFE) opcode was executed.
+    InvalidFEOpcode,
+    /// A jump targeted something other than a `JUMPDEST`.
+    InvalidJump,
+    /// An instruction required more items than the stack holds.
+    StackUnderflow,
+    /// The stack or the EOF return stack grew past its limit.
+    StackOverflow,
+    /// The frame ran out of gas.
+    OutOfGas,
+    /// A state modifying instruction was executed inside a static call.
+    StateChangeDuringStaticCall,
+    /// A contract already exists at the address of a `CREATE`.
+    CreateCollision,
+    /// The code is an EOF container that does not pass validation.
+    InvalidEof,
+    /// An `EXTCALL` family instruction targeted a value with its 12 high bytes set.
+    InvalidExtCallTarget,
+    /// The container deployed by `RETURNCONTRACT` has less data than it declares.
+    EofAuxDataTooSmall,
+    /// A precompile rejected its input.
+    PrecompileError,
+    /// `SELFDESTRUCT` sent a balance overflowing the one of the beneficiary, which only
+    /// balances set past the total supply can do.
+    BalanceOverflow,
+}
+
+impl ExecutionError {
+    /// Returns the reason of the exceptional halt caused by this error, or `None` for the
+    /// errors that end execution normally (`STOP`, `RETURN`, `SELFDESTRUCT` and `REVERT`) and
+    /// the database errors aborting it.
+    pub fn halt_reason(&self) -> Option<HaltReason> {
+        let reason = match self {
+            ExecutionError::Halt
+            | ExecutionError::Return
+            | ExecutionError::SelfDestruct
+            | ExecutionError::Revert
+            | ExecutionError::Database(_) => return None,
+            ExecutionError::InvalidOpcode => HaltReason::OpcodeNotFound,
+            ExecutionError::InvalidFEOpcode => HaltReason::InvalidFEOpcode,
+            ExecutionError::InvalidJumpDestination => HaltReason::InvalidJump,
+            ExecutionError::StackUnderflow => HaltReason::StackUnderflow,
+            ExecutionError::StackOverflow => HaltReason::StackOverflow,
+            // memory offsets overflowing `usize` could never be paid for.
+            ExecutionError::IntegerOverflow | ExecutionError::OutOfGas => HaltReason::OutOfGas,
+            ExecutionError::ReadOnly => HaltReason::StateChangeDuringStaticCall,
+            ExecutionError::InvalidEof => HaltReason::InvalidEof,
+            ExecutionError::InvalidExtCallTarget => HaltReason::InvalidExtCallTarget,
+            ExecutionError::EofAuxDataTooSmall => HaltReason::EofAuxDataTooSmall,
+            ExecutionError::BalanceOverflow => HaltReason::BalanceOverflow,
+        };
+        Some(reason)
+    }
 }
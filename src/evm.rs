@@ -1,69 +1,259 @@
 use primitive_types::U256;
 
 use crate::{
-    block::BlockData, errors::ExecutionError, jumpdest::is_valid_jumpdest, log::Log,
-    memory::Memory, opcode::OpCode, state::State, storage::Storage, tx::TxData, utils::sha3_hash,
+    authorization::{delegated_address, load_code},
+    block::BlockEnv,
+    db::DatabaseError,
+    eof::{instruction_size, relative_jump_targets, validate_eof, ContainerKind, Eof},
+    errors::{ExecutionError, HaltReason},
+    gas::{
+        memory_cost, sstore_cost, static_cost, words, Gas, CALL_STIPEND, CALL_VALUE_COST,
+        CODE_DEPOSIT_COST, COLD_ACCOUNT_ACCESS_COST, COLD_SLOAD_COST, COPY_WORD_COST,
+        INITCODE_WORD_COST, KECCAK_WORD_COST, LOG_DATA_COST, LOG_TOPIC_COST, NEW_ACCOUNT_COST,
+        SELFDESTRUCT_REFUND, WARM_STORAGE_READ_COST,
+    },
+    inspector::{CallInputs, CallKind, CreateInputs, CreateKind, SharedInspector, Step},
+    jumpdest::is_valid_jumpdest,
+    log::Log,
+    memory::Memory,
+    opcode::OpCode,
+    outcome::{Outcome, SuccessReason},
+    precompile::{Precompile, PrecompileError, Precompiles},
+    primitives::Address,
+    rlp,
+    spec::SpecId,
+    state::{Checkpoint, State},
+    storage::Storage,
+    tx::TxEnv,
+    utils::sha3_hash,
 };
 
+/// Maximum depth of the EOF return stack.
+const RETURN_STACK_LIMIT: usize = 1024;
+const STACK_LIMIT: usize = 1024;
+/// Maximum depth of nested call and create frames.
+const CALL_DEPTH_LIMIT: usize = 1024;
+
+/// Child frames recurse on the host stack, which is grown by `FRAME_STACK_SIZE` bytes when
+/// less than `FRAME_RED_ZONE` bytes are left, so that frames nested up to the depth limit
+/// can't overflow it, whatever the build and the stack of the calling thread.
+const FRAME_RED_ZONE: usize = 256 * 1024;
+const FRAME_STACK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Status pushed by the `EXTCALL` family when the callee reverts or can't be called.
+const EXT_CALL_REVERT: u8 = 1;
+/// Status pushed by the `EXTCALL` family when the callee halts exceptionally.
+const EXT_CALL_FAILURE: u8 = 2;
+/// The least gas the `EXTCALL` family keeps in the caller.
+const EXT_CALL_MIN_RETAINED_GAS: u64 = 5000;
+/// The least gas the `EXTCALL` family calls with, failing without calling otherwise.
+const EXT_CALL_MIN_CALLEE_GAS: u64 = 2300;
+
+/// Execution state of an EOF container.
+pub struct EofFrame {
+    pub container: Eof,
+    /// The code section being executed.
+    pub section: usize,
+    /// The code sections and program counters `RETF` returns to.
+    pub return_stack: Vec<(usize, usize)>,
+}
+
+impl EofFrame {
+    pub fn new(container: Eof) -> EofFrame {
+        EofFrame {
+            container,
+            section: 0,
+            return_stack: vec![],
+        }
+    }
+
+    fn code_section(&self) -> Box<[u8]> {
+        Box::from(self.container.code_sections[self.section].as_slice())
+    }
+}
+
 pub struct Evm {
     pub code: Box<[u8]>,
     pub stack: Vec<U256>,
     pub memory: Memory,
-    pub tx_data: TxData,
-    pub block_data: BlockData,
+    pub tx_env: TxEnv,
+    pub block_env: BlockEnv,
     pub state: State,
     pub storage: Storage,
     pub logs: Vec<Log>,
     pub return_data: Vec<u8>,
     pub last_return_data: Vec<u8>,
+    pub gas: Gas,
+    pub spec: SpecId,
+    /// The container being executed, for EOF code.
+    pub eof: Option<EofFrame>,
+    pub precompiles: Precompiles,
+    /// The inspector observing the execution, if any.
+    pub inspector: Option<SharedInspector>,
+    /// The number of frames this frame is nested in, zero for the frame of the transaction.
+    pub depth: usize,
+    /// The code is initcode, whose EOF container is validated as such.
+    pub initcode: bool,
     read_only: bool,
 }
 
 impl Evm {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         code: Box<[u8]>,
         stack: Vec<U256>,
-        tx_data: TxData,
-        block_data: BlockData,
+        tx_env: TxEnv,
+        block_env: BlockEnv,
         state: State,
         storage: Storage,
         logs: Vec<Log>,
         return_data: Vec<u8>,
         last_return_data: Vec<u8>,
+        spec: SpecId,
+        precompiles: Precompiles,
         read_only: bool,
     ) -> Self {
+        let gas = Gas::new(tx_env.gas_limit);
         Self {
             code,
             stack,
             memory: Memory::new(),
-            tx_data,
-            block_data,
+            tx_env,
+            block_env,
             state,
             storage,
             logs,
             return_data,
             last_return_data,
+            gas,
+            spec,
+            eof: None,
+            precompiles,
+            inspector: None,
+            depth: 0,
+            initcode: false,
             read_only,
         }
     }
 
-    pub fn execute(&mut self) -> ExecutionResult {
+    pub fn execute(&mut self) -> Result<ExecutionResult, DatabaseError> {
+        // frames nested past the depth limit fail without running, like a revert.
+        if self.depth > CALL_DEPTH_LIMIT {
+            return Ok(ExecutionResult::Revert);
+        }
+
+        let result = self.execute_code()?;
+        // the code deployed by initcode is paid for by its frame, which runs out of gas if it
+        // can't afford it, or deploys no code before Homestead.
+        if self.initcode && matches!(result, ExecutionResult::Success(_)) {
+            let cost = CODE_DEPOSIT_COST * self.return_data.len() as u64;
+            if self.gas.record_cost(cost).is_err() {
+                if !self.spec.is_enabled_in(SpecId::Homestead) {
+                    self.return_data.clear();
+                    return Ok(result);
+                }
+                return Ok(self.exceptional_halt(HaltReason::OutOfGas));
+            }
+        }
+        Ok(result)
+    }
+
+    fn execute_code(&mut self) -> Result<ExecutionResult, DatabaseError> {
+        // EOF code is validated before running, the initcode of CREATE and EOFCREATE being
+        // validated by its creator.
+        if self.eof.is_none()
+            && Eof::is_eof(&self.code)
+            && self.spec.is_enabled_in(SpecId::PragueEof)
+        {
+            let kind = if self.initcode {
+                ContainerKind::Initcode
+            } else {
+                ContainerKind::Runtime
+            };
+            let container =
+                Eof::decode(&self.code).and_then(|eof| validate_eof(&eof, kind).map(|_| eof));
+            match container {
+                Ok(container) => self.eof = Some(EofFrame::new(container)),
+                Err(_) => return Ok(self.exceptional_halt(HaltReason::InvalidEof)),
+            }
+        }
+        if let Some(frame) = &self.eof {
+            self.code = frame.code_section();
+        }
+
         let mut pc = 0;
         while pc < self.code.len() {
-            if let Some(opcode) = OpCode::new(self.code[pc]) {
-                match self.transact(&mut pc, opcode) {
+            let byte = self.code[pc];
+            self.inspect_step(pc, byte, false);
+            let opcode =
+                OpCode::new(byte).filter(|opcode| self.eof.is_none() || !opcode.is_legacy_only());
+            if let Some(opcode) = opcode {
+                let step_pc = pc;
+                let result = self
+                    .gas
+                    .record_cost(static_cost(opcode, self.spec))
+                    .and_then(|_| self.transact(&mut pc, opcode));
+                self.inspect_step(step_pc, byte, true);
+                match result {
                     Ok(_) => {
-                        // move the pc to the next instruction
-                        pc += 1;
+                        if self.stack.len() > STACK_LIMIT {
+                            return Ok(self.exceptional_halt(HaltReason::StackOverflow));
+                        }
+                        // move the pc to the next instruction, relative jumps leaving it right
+                        // before their target.
+                        pc = pc.wrapping_add(1);
+                    }
+                    Err(ExecutionError::Halt) => {
+                        return Ok(ExecutionResult::Success(SuccessReason::Stop))
+                    }
+                    Err(ExecutionError::Return) => {
+                        return Ok(ExecutionResult::Success(SuccessReason::Return))
+                    }
+                    Err(ExecutionError::SelfDestruct) => {
+                        return Ok(ExecutionResult::Success(SuccessReason::SelfDestruct))
+                    }
+                    Err(ExecutionError::Revert) => return Ok(ExecutionResult::Revert),
+                    // failed reads of the database abort the whole transaction.
+                    Err(ExecutionError::Database(error)) => return Err(error),
+                    Err(error) => {
+                        return Ok(match error.halt_reason() {
+                            Some(reason) => self.exceptional_halt(reason),
+                            None => ExecutionResult::Success(SuccessReason::Stop),
+                        })
                     }
-                    Err(ExecutionError::Halt) => return ExecutionResult::Halt,
-                    Err(_) => return ExecutionResult::Revert,
                 }
             } else {
-                return ExecutionResult::Revert;
+                return Ok(self.exceptional_halt(HaltReason::OpcodeNotFound));
             }
         }
-        ExecutionResult::Success
+        Ok(ExecutionResult::Success(SuccessReason::Stop))
+    }
+
+    /// Calls the `step` hook of the inspector, or `step_end` once the instruction is executed.
+    fn inspect_step(&self, pc: usize, opcode: u8, end: bool) {
+        let Some(inspector) = &self.inspector else {
+            return;
+        };
+        let step = Step {
+            pc,
+            opcode,
+            stack: &self.stack,
+            memory: self.memory.store(),
+            gas_remaining: self.gas.remaining(),
+        };
+        let mut inspector = inspector.borrow_mut();
+        if end {
+            inspector.step_end(&step);
+        } else {
+            inspector.step(&step);
+        }
+    }
+
+    /// Ends the execution exceptionally, consuming all the gas and discarding the return data.
+    pub(crate) fn exceptional_halt(&mut self, reason: HaltReason) -> ExecutionResult {
+        self.gas.spend_all();
+        self.return_data.clear();
+        ExecutionResult::Exception(reason)
     }
 
     pub fn transact(&mut self, pc: &mut usize, opcode: OpCode) -> Result<(), ExecutionError> {
@@ -151,7 +341,7 @@ impl Evm {
                 Ok(())
             }
             OpCode::Exp => {
-                exp(&mut self.stack)?;
+                exp(&mut self.stack, &mut self.gas, self.spec)?;
                 Ok(())
             }
             OpCode::Signextend => {
@@ -278,45 +468,50 @@ impl Evm {
                 Ok(())
             }
             OpCode::Sha3 => {
-                sha3(&mut self.stack, &mut self.memory)?;
+                sha3(&mut self.stack, &mut self.memory, &mut self.gas)?;
                 Ok(())
             }
             OpCode::Address => {
-                let value = U256::from_big_endian(&self.tx_data.to);
+                let value = self.tx_env.to.to_word();
                 self.stack.push(value);
 
                 Ok(())
             }
             OpCode::Balance => {
-                balance(&mut self.stack, &self.state)?;
+                balance(&mut self.stack, &mut self.state, &mut self.gas, self.spec)?;
 
                 Ok(())
             }
             OpCode::Caller => {
-                let value = U256::from_big_endian(&self.tx_data.from);
+                let value = self.tx_env.caller.to_word();
                 self.stack.push(value);
 
                 Ok(())
             }
             OpCode::Callvalue => {
-                let value = U256::from_big_endian(&self.tx_data.value);
+                let value = self.tx_env.value;
                 self.stack.push(value);
 
                 Ok(())
             }
             OpCode::Calldataload => {
-                calldataload(&mut self.stack, &self.tx_data.data)?;
+                calldataload(&mut self.stack, &self.tx_env.data)?;
 
                 Ok(())
             }
             OpCode::Calldatasize => {
-                let size = self.tx_data.data.len();
+                let size = self.tx_env.data.len();
                 self.stack.push(size.into());
 
                 Ok(())
             }
             OpCode::Calldatacopy => {
-                copy_data_to_memory(&mut self.stack, &mut self.memory, &self.tx_data.data)?;
+                copy_data_to_memory(
+                    &mut self.stack,
+                    &mut self.memory,
+                    &mut self.gas,
+                    &self.tx_env.data,
+                )?;
 
                 Ok(())
             }
@@ -327,28 +522,31 @@ impl Evm {
                 Ok(())
             }
             OpCode::Codecopy => {
-                copy_data_to_memory(&mut self.stack, &mut self.memory, &self.code)?;
+                copy_data_to_memory(&mut self.stack, &mut self.memory, &mut self.gas, &self.code)?;
 
                 Ok(())
             }
             OpCode::Gasprice => {
-                let value = U256::from_big_endian(&self.tx_data.gasprice);
+                let value = self.tx_env.gas_price;
                 self.stack.push(value);
 
                 Ok(())
             }
+            // EXTCODE* operate on delegation designators as they are, without following them.
             OpCode::Extcodesize => {
-                let address = pop(&mut self.stack)?;
-                let code = self.state.get_code(address);
+                let address = Address::from_word(pop(&mut self.stack)?);
+                access_account(&mut self.state, &mut self.gas, address, self.spec)?;
+                let code = self.state.get_code(address)?;
                 let size = code.len().into();
                 self.stack.push(size);
 
                 Ok(())
             }
             OpCode::Extcodecopy => {
-                let address = pop(&mut self.stack)?;
-                let code = self.state.get_code(address);
-                copy_data_to_memory(&mut self.stack, &mut self.memory, &code)?;
+                let address = Address::from_word(pop(&mut self.stack)?);
+                access_account(&mut self.state, &mut self.gas, address, self.spec)?;
+                let code = self.state.get_code(address)?;
+                copy_data_to_memory(&mut self.stack, &mut self.memory, &mut self.gas, &code)?;
 
                 Ok(())
             }
@@ -359,108 +557,146 @@ impl Evm {
                 Ok(())
             }
             OpCode::Returndatacopy => {
-                copy_data_to_memory(&mut self.stack, &mut self.memory, &self.last_return_data)?;
+                copy_data_to_memory(
+                    &mut self.stack,
+                    &mut self.memory,
+                    &mut self.gas,
+                    &self.last_return_data,
+                )?;
 
                 Ok(())
             }
             OpCode::Extcodehash => {
-                let address = pop(&mut self.stack)?;
-                let code = self.state.get_code(address);
-                let result = U256::from_big_endian(&sha3_hash(&code));
+                let address = Address::from_word(pop(&mut self.stack)?);
+                access_account(&mut self.state, &mut self.gas, address, self.spec)?;
+                // Accounts that don't exist hash to zero, and existing ones without code to
+                // the hash of the empty code (EIP-1052).
+                let result = match self.state.account(address)? {
+                    Some(account) if !account.is_empty() => {
+                        U256::from_big_endian(&sha3_hash(&account.code))
+                    }
+                    _ => U256::zero(),
+                };
                 self.stack.push(result);
 
                 Ok(())
             }
             OpCode::Coinbase => {
-                let value = U256::from_big_endian(&self.block_data.coinbase);
+                let value = self.block_env.coinbase.to_word();
                 self.stack.push(value);
 
                 Ok(())
             }
             OpCode::Timestamp => {
-                let value = U256::from_big_endian(&self.block_data.timestamp);
+                let value = self.block_env.timestamp.into();
                 self.stack.push(value);
 
                 Ok(())
             }
             OpCode::Number => {
-                let value = U256::from_big_endian(&self.block_data.number);
+                let value = self.block_env.number.into();
                 self.stack.push(value);
 
                 Ok(())
             }
             OpCode::Difficulty => {
-                let value = U256::from_big_endian(&self.block_data.difficulty);
+                // since the Merge the opcode returns PREVRANDAO instead (EIP-4399).
+                let value = if self.spec.is_enabled_in(SpecId::Merge) {
+                    self.block_env.prevrandao.to_word()
+                } else {
+                    self.block_env.difficulty
+                };
                 self.stack.push(value);
 
                 Ok(())
             }
 
             OpCode::Gaslimit => {
-                let value = U256::from_big_endian(&self.block_data.gaslimit);
+                let value = self.block_env.gas_limit.into();
                 self.stack.push(value);
 
                 Ok(())
             }
             OpCode::Chainid => {
-                let value = U256::from_big_endian(&self.block_data.chainid);
+                let value = self.block_env.chain_id.into();
                 self.stack.push(value);
 
                 Ok(())
             }
             OpCode::Selfbalance => {
-                let balance = self
-                    .state
-                    .get_balance(U256::from_big_endian(&self.tx_data.to));
+                let balance = self.state.get_balance(self.tx_env.to)?;
                 self.stack.push(balance);
 
                 Ok(())
             }
             OpCode::Blockhash => {
-                // Not used in this test suite, can return 0
+                let number = pop(&mut self.stack)?;
+                // only the hashes of the 256 blocks before the current one are available.
+                let current = self.block_env.number;
+                let hash =
+                    if number < current.into() && number >= current.saturating_sub(256).into() {
+                        self.state.block_hash(number.as_u64())?.to_word()
+                    } else {
+                        U256::zero()
+                    };
+                self.stack.push(hash);
+
                 Ok(())
             }
             OpCode::Basefee => {
-                let value = U256::from_big_endian(&self.block_data.basefee);
+                let value = self.block_env.basefee;
                 self.stack.push(value);
 
                 Ok(())
             }
             OpCode::Origin => {
-                let value = U256::from_big_endian(&self.tx_data.origin);
+                let value = self.tx_env.origin.to_word();
                 self.stack.push(value);
 
                 Ok(())
             }
             OpCode::Gas => {
-                // not supported and return always max U256
-                self.stack.push(U256::max_value());
+                // unmetered code is given all the gas it could ask for.
+                let value = if self.gas.is_unmetered() {
+                    U256::max_value()
+                } else {
+                    self.gas.remaining().into()
+                };
+                self.stack.push(value);
                 Ok(())
             }
             OpCode::Jumpdest => Ok(()),
             OpCode::Mstore => {
-                mstore(&mut self.stack, &mut self.memory)?;
+                mstore(&mut self.stack, &mut self.memory, &mut self.gas)?;
                 Ok(())
             }
             OpCode::Mstore8 => {
-                mstore8(&mut self.stack, &mut self.memory)?;
+                mstore8(&mut self.stack, &mut self.memory, &mut self.gas)?;
                 Ok(())
             }
             OpCode::Sload => {
-                sload(&mut self.stack, &mut self.storage, &self.tx_data.to)?;
+                sload(
+                    &mut self.stack,
+                    &mut self.storage,
+                    self.tx_env.to,
+                    &mut self.gas,
+                    self.spec,
+                )?;
                 Ok(())
             }
             OpCode::Sstore => {
                 sstore(
                     &mut self.stack,
                     &mut self.storage,
-                    &self.tx_data.to,
+                    self.tx_env.to,
+                    &mut self.gas,
+                    self.spec,
                     self.read_only,
                 )?;
                 Ok(())
             }
             OpCode::Mload => {
-                mload(&mut self.stack, &mut self.memory)?;
+                mload(&mut self.stack, &mut self.memory, &mut self.gas)?;
                 Ok(())
             }
             OpCode::Log0 | OpCode::Log1 | OpCode::Log2 | OpCode::Log3 | OpCode::Log4 => {
@@ -469,8 +705,10 @@ impl Evm {
                     x,
                     &mut self.stack,
                     &mut self.memory,
-                    &self.tx_data.to,
+                    &mut self.gas,
+                    self.tx_env.to,
                     &mut self.logs,
+                    &self.inspector,
                     self.read_only,
                 )?;
                 Ok(())
@@ -481,9 +719,15 @@ impl Evm {
                     &mut self.memory,
                     &mut self.state,
                     &mut self.storage,
-                    &self.tx_data.to,
-                    &self.tx_data.origin,
+                    &self.block_env,
+                    self.tx_env.to,
+                    self.tx_env.origin,
                     &mut self.last_return_data,
+                    &mut self.gas,
+                    self.spec,
+                    &self.precompiles,
+                    &self.inspector,
+                    self.depth,
                     self.read_only,
                 )?;
 
@@ -495,18 +739,29 @@ impl Evm {
                     &mut self.memory,
                     &mut self.state,
                     &mut self.storage,
-                    &self.tx_data.to,
-                    &self.tx_data.origin,
+                    &self.block_env,
+                    self.tx_env.to,
+                    self.tx_env.origin,
                     &mut self.last_return_data,
+                    &mut self.gas,
+                    self.spec,
+                    &self.precompiles,
+                    &self.inspector,
+                    self.depth,
                     self.read_only,
                 )?;
 
                 Ok(())
             }
             OpCode::Return => {
-                return_func(&mut self.stack, &mut self.memory, &mut self.return_data)?;
+                return_func(
+                    &mut self.stack,
+                    &mut self.memory,
+                    &mut self.gas,
+                    &mut self.return_data,
+                )?;
 
-                Ok(())
+                Err(ExecutionError::Return)
             }
             OpCode::Delegatecall => {
                 delegatecall(
@@ -514,11 +769,17 @@ impl Evm {
                     &mut self.memory,
                     &mut self.state,
                     &mut self.storage,
-                    &self.tx_data.to,
-                    &self.tx_data.from,
-                    &self.tx_data.origin,
-                    &self.tx_data.value,
+                    &self.block_env,
+                    self.tx_env.to,
+                    self.tx_env.caller,
+                    self.tx_env.origin,
+                    self.tx_env.value,
                     &mut self.last_return_data,
+                    &mut self.gas,
+                    self.spec,
+                    &self.precompiles,
+                    &self.inspector,
+                    self.depth,
                 )?;
 
                 Ok(())
@@ -529,27 +790,209 @@ impl Evm {
                     &mut self.memory,
                     &mut self.state,
                     &mut self.storage,
-                    &self.tx_data.to,
-                    &self.tx_data.origin,
-                    &self.tx_data.data,
+                    &self.block_env,
+                    self.tx_env.to,
+                    self.tx_env.origin,
                     &mut self.last_return_data,
+                    &mut self.gas,
+                    self.spec,
+                    &self.precompiles,
+                    &self.inspector,
+                    self.depth,
                 )?;
                 Ok(())
             }
             OpCode::Revert => {
-                return_func(&mut self.stack, &mut self.memory, &mut self.return_data)?;
+                return_func(
+                    &mut self.stack,
+                    &mut self.memory,
+                    &mut self.gas,
+                    &mut self.return_data,
+                )?;
                 self.stack.clear();
 
                 Err(ExecutionError::Revert)
             }
+            OpCode::Invalid => Err(ExecutionError::InvalidFEOpcode),
+            OpCode::Rjump | OpCode::Rjumpi | OpCode::Rjumpv => {
+                if self.eof.is_none() {
+                    return Err(ExecutionError::InvalidOpcode);
+                }
+                let targets = relative_jump_targets(&self.code, *pc, opcode);
+                let taken = match opcode {
+                    OpCode::Rjump => Some(0),
+                    OpCode::Rjumpi => (!pop(&mut self.stack)?.is_zero()).then_some(0),
+                    _ => {
+                        let case = pop(&mut self.stack)?;
+                        (case < targets.len().into()).then(|| case.as_usize())
+                    }
+                };
+                match taken {
+                    Some(index) => relative_jump(pc, targets[index] as usize),
+                    None => *pc += instruction_size(&self.code, *pc, opcode) - 1,
+                }
+                Ok(())
+            }
+            OpCode::Callf | OpCode::Jumpf => {
+                let frame = self.eof.as_mut().ok_or(ExecutionError::InvalidOpcode)?;
+                let section = u16::from_be_bytes([self.code[*pc + 1], self.code[*pc + 2]]) as usize;
+                let types = frame.container.types[section];
+
+                if self.stack.len() + types.max_stack_increase as usize > STACK_LIMIT {
+                    return Err(ExecutionError::StackOverflow);
+                }
+                if opcode == OpCode::Callf {
+                    if frame.return_stack.len() >= RETURN_STACK_LIMIT {
+                        return Err(ExecutionError::StackOverflow);
+                    }
+                    frame.return_stack.push((frame.section, *pc + 2));
+                }
+
+                frame.section = section;
+                self.code = frame.code_section();
+                relative_jump(pc, 0);
+                Ok(())
+            }
+            OpCode::Retf => {
+                let frame = self.eof.as_mut().ok_or(ExecutionError::InvalidOpcode)?;
+                let (section, return_pc) = frame
+                    .return_stack
+                    .pop()
+                    .ok_or(ExecutionError::InvalidOpcode)?;
+
+                frame.section = section;
+                self.code = frame.code_section();
+                *pc = return_pc;
+                Ok(())
+            }
+            OpCode::Dataload | OpCode::Dataloadn => {
+                let frame = self.eof.as_ref().ok_or(ExecutionError::InvalidOpcode)?;
+                let offset = if opcode == OpCode::Dataloadn {
+                    let offset = u16::from_be_bytes([self.code[*pc + 1], self.code[*pc + 2]]);
+                    *pc += 2;
+                    offset.into()
+                } else {
+                    pop(&mut self.stack)?
+                };
+                self.stack.push(load_word(&frame.container.data, offset));
+                Ok(())
+            }
+            OpCode::Datasize => {
+                let frame = self.eof.as_ref().ok_or(ExecutionError::InvalidOpcode)?;
+                self.stack.push(frame.container.data.len().into());
+                Ok(())
+            }
+            OpCode::Datacopy => {
+                let frame = self.eof.as_ref().ok_or(ExecutionError::InvalidOpcode)?;
+                copy_data_to_memory(
+                    &mut self.stack,
+                    &mut self.memory,
+                    &mut self.gas,
+                    &frame.container.data,
+                )?;
+                Ok(())
+            }
+            OpCode::Dupn | OpCode::Swapn | OpCode::Exchange => {
+                if self.eof.is_none() {
+                    return Err(ExecutionError::InvalidOpcode);
+                }
+                let immediate = self.code[*pc + 1] as usize;
+                match opcode {
+                    OpCode::Dupn => {
+                        duplicate(&mut self.stack, immediate + 1)?;
+                    }
+                    OpCode::Swapn => {
+                        swap(&mut self.stack, immediate + 1)?;
+                    }
+                    _ => exchange(
+                        &mut self.stack,
+                        (immediate >> 4) + 1,
+                        (immediate & 0x0f) + 1,
+                    )?,
+                }
+                *pc += 1;
+                Ok(())
+            }
+            OpCode::Returndataload => {
+                if self.eof.is_none() {
+                    return Err(ExecutionError::InvalidOpcode);
+                }
+                let offset = pop(&mut self.stack)?;
+                self.stack.push(load_word(&self.last_return_data, offset));
+                Ok(())
+            }
+            OpCode::Extcall | OpCode::Extdelegatecall | OpCode::Extstaticcall => {
+                if self.eof.is_none() {
+                    return Err(ExecutionError::InvalidOpcode);
+                }
+                ext_call(
+                    opcode,
+                    &mut self.stack,
+                    &mut self.memory,
+                    &mut self.state,
+                    &mut self.storage,
+                    &self.block_env,
+                    &self.tx_env,
+                    &mut self.last_return_data,
+                    &mut self.gas,
+                    self.spec,
+                    &self.precompiles,
+                    &self.inspector,
+                    self.depth,
+                    self.read_only,
+                )?;
+                Ok(())
+            }
+            OpCode::Eofcreate => {
+                let frame = self.eof.as_ref().ok_or(ExecutionError::InvalidOpcode)?;
+                let initcontainer =
+                    &frame.container.container_sections[self.code[*pc + 1] as usize];
+                eofcreate(
+                    initcontainer,
+                    &mut self.stack,
+                    &mut self.memory,
+                    &mut self.state,
+                    &mut self.storage,
+                    &self.block_env,
+                    self.tx_env.to,
+                    self.tx_env.origin,
+                    &mut self.last_return_data,
+                    &mut self.gas,
+                    self.spec,
+                    &self.precompiles,
+                    &self.inspector,
+                    self.depth,
+                    self.read_only,
+                )?;
+                *pc += 1;
+                Ok(())
+            }
+            OpCode::Returncontract => {
+                let frame = self.eof.as_ref().ok_or(ExecutionError::InvalidOpcode)?;
+                let container = &frame.container.container_sections[self.code[*pc + 1] as usize];
+                let container =
+                    Eof::decode_partial(container).map_err(|_| ExecutionError::InvalidEof)?;
+
+                let (offset, size) = pop_memory_range(&mut self.stack)?;
+                expand_memory(&mut self.memory, &mut self.gas, offset, size)?;
+                let aux_data = self.memory.get_bytes(offset, size)?;
+
+                self.return_data = container
+                    .with_aux_data(&aux_data)
+                    .ok_or(ExecutionError::EofAuxDataTooSmall)?;
+                Err(ExecutionError::Return)
+            }
             OpCode::Selfdestruct => {
                 selfdestruct(
                     &mut self.stack,
                     &mut self.state,
-                    &self.tx_data.to,
+                    self.tx_env.to,
+                    &mut self.gas,
+                    self.spec,
+                    &self.inspector,
                     self.read_only,
                 )?;
-                Ok(())
+                Err(ExecutionError::SelfDestruct)
             }
         }
     }
@@ -567,40 +1010,29 @@ impl Evm {
     pub fn return_data(&self) -> Vec<u8> {
         self.return_data.clone()
     }
-
-    pub fn state(&self) -> State {
-        self.state.clone()
-    }
-
-    pub fn storage(&self) -> Storage {
-        self.storage.clone()
-    }
 }
 
 #[derive(Debug)]
 pub enum ExecutionResult {
-    Success,
-    Halt,
+    Success(SuccessReason),
     Revert,
+    /// Exceptional halt, all the gas of the frame is consumed.
+    Exception(HaltReason),
 }
 
-fn push(
-    push_data_size: usize,
-    pc: &mut usize,
-    stack: &mut Vec<U256>,
-    code: &[u8],
-) -> ExecutionResult {
+fn push(push_data_size: usize, pc: &mut usize, stack: &mut Vec<U256>, code: &[u8]) {
     let start = *pc + 1;
-    let remaining_code = &code[start..];
-    let push_data = &remaining_code[..push_data_size];
-    let push_data = U256::from_big_endian(push_data);
+    let remaining_code = code.get(start..).unwrap_or_default();
+    // push data cut by the end of the code is padded with zeros.
+    let mut push_data = vec![0; push_data_size];
+    let available = push_data_size.min(remaining_code.len());
+    push_data[..available].copy_from_slice(&remaining_code[..available]);
+    let push_data = U256::from_big_endian(&push_data);
     stack.push(push_data);
-
-    ExecutionResult::Success
 }
 
 fn pop(stack: &mut Vec<U256>) -> Result<U256, ExecutionError> {
-    let item = stack.pop().ok_or_else(|| ExecutionError::StackUnderflow)?;
+    let item = stack.pop().ok_or(ExecutionError::StackUnderflow)?;
     Ok(item)
 }
 
@@ -679,10 +1111,18 @@ fn mulmod(stack: &mut Vec<U256>) -> Result<U256, ExecutionError> {
     }
 }
 
-fn exp(stack: &mut Vec<U256>) -> Result<U256, ExecutionError> {
+fn exp(stack: &mut Vec<U256>, gas: &mut Gas, spec: SpecId) -> Result<U256, ExecutionError> {
     let first = pop(stack)?;
     let second = pop(stack)?;
 
+    // each byte of the exponent costs more, repriced by EIP-160 (Spurious Dragon).
+    let byte_cost = if spec.is_enabled_in(SpecId::SpuriousDragon) {
+        50
+    } else {
+        10
+    };
+    gas.record_cost(byte_cost * second.bits().div_ceil(8) as u64)?;
+
     let (result, _) = first.overflowing_pow(second);
     stack.push(result);
     Ok(result)
@@ -692,16 +1132,22 @@ fn sign_extend(stack: &mut Vec<U256>) -> Result<U256, ExecutionError> {
     let first = pop(stack)?;
     let second = pop(stack)?;
 
-    let sign = second.byte(first.as_usize());
+    // the value is already extended from its highest byte.
+    if first >= U256::from(31) {
+        stack.push(second);
+        return Ok(second);
+    }
+    let first = first.as_usize();
+    let sign = second.byte(first);
 
     let mut data = second.to_little_endian();
 
-    for i in 0..32 {
-        if i as usize > first.as_usize() {
+    for (i, byte) in data.iter_mut().enumerate() {
+        if i > first {
             if sign > 0x7f {
-                data[i] = 0xFF;
+                *byte = 0xFF;
             } else {
-                data[i] = 0x00;
+                *byte = 0x00;
             }
         }
     }
@@ -798,7 +1244,7 @@ fn slt(stack: &mut Vec<U256>) -> Result<U256, ExecutionError> {
     let is_second_negative = second.bit(255);
 
     let result = match (is_first_negative, is_second_negative) {
-        (true, true) => !(first.overflowing_neg() <= second.overflowing_neg()),
+        (true, true) => first.overflowing_neg() > second.overflowing_neg(),
         (true, false) => true,
         (false, true) => false,
         (false, false) => first < second,
@@ -821,7 +1267,7 @@ fn sgt(stack: &mut Vec<U256>) -> Result<U256, ExecutionError> {
     let is_second_negative = second.bit(255);
 
     let result = match (is_first_negative, is_second_negative) {
-        (true, true) => !(first.overflowing_neg() >= second.overflowing_neg()),
+        (true, true) => first.overflowing_neg() < second.overflowing_neg(),
         (true, false) => false,
         (false, true) => true,
         (false, false) => first > second,
@@ -901,8 +1347,13 @@ fn xor(stack: &mut Vec<U256>) -> Result<U256, ExecutionError> {
     Ok(result)
 }
 
+/// Caps a shift to 256 bits, which already shifts all the bits out, so that it fits a `usize`.
+fn shift_amount(shift: U256) -> U256 {
+    shift.min(U256::from(256))
+}
+
 fn shl(stack: &mut Vec<U256>) -> Result<U256, ExecutionError> {
-    let first = pop(stack)?;
+    let first = shift_amount(pop(stack)?);
     let second = pop(stack)?;
 
     let result = second << first;
@@ -912,7 +1363,7 @@ fn shl(stack: &mut Vec<U256>) -> Result<U256, ExecutionError> {
 }
 
 fn shr(stack: &mut Vec<U256>) -> Result<U256, ExecutionError> {
-    let first = pop(stack)?;
+    let first = shift_amount(pop(stack)?);
     let second = pop(stack)?;
 
     let result = second >> first;
@@ -922,7 +1373,7 @@ fn shr(stack: &mut Vec<U256>) -> Result<U256, ExecutionError> {
 }
 
 fn sar(stack: &mut Vec<U256>) -> Result<U256, ExecutionError> {
-    let first = pop(stack)?;
+    let first = shift_amount(pop(stack)?);
     let second = pop(stack)?;
 
     let is_second_negative = second.bit(255);
@@ -1012,47 +1463,69 @@ fn swap(stack: &mut Vec<U256>, swap_data_index: usize) -> Result<U256, Execution
 }
 
 fn jump(counter: U256, code: &[u8], pc: &mut usize) -> Result<U256, ExecutionError> {
+    let destination =
+        usize::try_from(counter).map_err(|_| ExecutionError::InvalidJumpDestination)?;
     let is_valid = is_valid_jumpdest(counter, code)?;
     if is_valid {
-        *pc = counter.as_usize();
+        *pc = destination;
         Ok(counter)
     } else {
         Err(ExecutionError::InvalidJumpDestination)
     }
 }
 
-fn mstore(stack: &mut Vec<U256>, memory: &mut Memory) -> Result<U256, ExecutionError> {
-    let offset = pop(stack)?;
+fn mstore(
+    stack: &mut Vec<U256>,
+    memory: &mut Memory,
+    gas: &mut Gas,
+) -> Result<U256, ExecutionError> {
+    let offset = as_offset(pop(stack)?)?;
     let word = pop(stack)?;
 
-    memory.save_word(offset.as_usize(), word)
+    expand_memory(memory, gas, offset, 32)?;
+    memory.save_word(offset, word)
 }
 
-fn mload(stack: &mut Vec<U256>, memory: &mut Memory) -> Result<U256, ExecutionError> {
-    let offset = pop(stack)?;
-    let word = memory.get_word(offset.as_usize())?;
+fn mload(
+    stack: &mut Vec<U256>,
+    memory: &mut Memory,
+    gas: &mut Gas,
+) -> Result<U256, ExecutionError> {
+    let offset = as_offset(pop(stack)?)?;
+    expand_memory(memory, gas, offset, 32)?;
+    let word = memory.get_word(offset)?;
 
     stack.push(word);
-    Ok(word.into())
+    Ok(word)
 }
 
-fn mstore8(stack: &mut Vec<U256>, memory: &mut Memory) -> Result<U256, ExecutionError> {
-    let offset = pop(stack)?;
+fn mstore8(
+    stack: &mut Vec<U256>,
+    memory: &mut Memory,
+    gas: &mut Gas,
+) -> Result<U256, ExecutionError> {
+    let offset = as_offset(pop(stack)?)?;
     let value = pop(stack)?;
 
     let value_bytes = value.to_big_endian();
 
-    memory.save_byte(offset.as_usize(), value_bytes[31])?;
+    expand_memory(memory, gas, offset, 1)?;
+    memory.save_byte(offset, value_bytes[31])?;
     Ok(value)
 }
 
 fn sload(
     stack: &mut Vec<U256>,
     storage: &mut Storage,
-    address: &[u8],
+    address: Address,
+    gas: &mut Gas,
+    spec: SpecId,
 ) -> Result<U256, ExecutionError> {
     let key = pop(stack)?;
-    let value = storage.load_slot(U256::from_big_endian(address), key);
+    if storage.access(address, key) && spec.is_enabled_in(SpecId::Berlin) {
+        gas.record_cost(COLD_SLOAD_COST - WARM_STORAGE_READ_COST)?;
+    }
+    let value = storage.load_slot(address, key)?;
 
     stack.push(value);
     Ok(value)
@@ -1061,7 +1534,9 @@ fn sload(
 fn sstore(
     stack: &mut Vec<U256>,
     storage: &mut Storage,
-    address: &[u8],
+    address: Address,
+    gas: &mut Gas,
+    spec: SpecId,
     read_only: bool,
 ) -> Result<U256, ExecutionError> {
     if read_only {
@@ -1071,15 +1546,30 @@ fn sstore(
     let key = pop(stack)?;
     let value = pop(stack)?;
 
-    storage.set_constract_slot(U256::from_big_endian(address), key, value);
+    // a frame left with no more than the stipend of a call can't store (EIP-2200).
+    if spec.is_enabled_in(SpecId::Istanbul) && gas.remaining() <= CALL_STIPEND {
+        return Err(ExecutionError::OutOfGas);
+    }
+    let original = storage.original_value(address, key)?;
+    let current = storage.load_slot(address, key)?;
+    let (cost, refund) = sstore_cost(original, current, value, spec);
+    let cold_cost = if storage.access(address, key) && spec.is_enabled_in(SpecId::Berlin) {
+        COLD_SLOAD_COST
+    } else {
+        0
+    };
+    gas.record_cost(cost + cold_cost)?;
+    gas.record_refund(refund);
+
+    storage.set_constract_slot(address, key, value)?;
     Ok(value)
 }
 
-fn sha3(stack: &mut Vec<U256>, memory: &mut Memory) -> Result<U256, ExecutionError> {
-    let offset = pop(stack)?.as_usize();
-    let size = pop(stack)?.as_usize();
-
-    let value = &memory.store()[offset..(offset + size)];
+fn sha3(stack: &mut Vec<U256>, memory: &mut Memory, gas: &mut Gas) -> Result<U256, ExecutionError> {
+    let (offset, size) = pop_memory_range(stack)?;
+    gas.record_cost(KECCAK_WORD_COST * words(size))?;
+    expand_memory(memory, gas, offset, size)?;
+    let value = memory.get_bytes(offset, size)?;
 
     let result = U256::from_big_endian(&sha3_hash(&value));
 
@@ -1087,15 +1577,21 @@ fn sha3(stack: &mut Vec<U256>, memory: &mut Memory) -> Result<U256, ExecutionErr
     Ok(result)
 }
 
-fn balance(stack: &mut Vec<U256>, state: &State) -> Result<U256, ExecutionError> {
-    let address = pop(stack)?;
-    let balance = state.get_balance(address);
+fn balance(
+    stack: &mut Vec<U256>,
+    state: &mut State,
+    gas: &mut Gas,
+    spec: SpecId,
+) -> Result<U256, ExecutionError> {
+    let address = Address::from_word(pop(stack)?);
+    access_account(state, gas, address, spec)?;
+    let balance = state.get_balance(address)?;
 
     stack.push(balance);
     Ok(balance)
 }
 
-fn calldataload(stack: &mut Vec<U256>, data: &Vec<u8>) -> Result<U256, ExecutionError> {
+fn calldataload(stack: &mut Vec<U256>, data: &[u8]) -> Result<U256, ExecutionError> {
     let index = pop(stack)?;
 
     let mut copied_data = [0u8; 32];
@@ -1119,18 +1615,27 @@ fn calldataload(stack: &mut Vec<U256>, data: &Vec<u8>) -> Result<U256, Execution
 fn copy_data_to_memory(
     stack: &mut Vec<U256>,
     memory: &mut Memory,
+    gas: &mut Gas,
     data: &[u8],
 ) -> Result<(), ExecutionError> {
-    let dest = pop(stack)?.as_usize();
-    let offset = pop(stack)?.as_usize();
-    let size = pop(stack)?.as_usize();
+    let dest = pop(stack)?;
+    let offset = pop(stack)?;
+    let size = pop(stack)?;
+    if size.is_zero() {
+        return Ok(());
+    }
+    let dest = as_offset(dest)?;
+    let size = as_offset(size)?;
 
+    // the memory is expanded before allocating the copy, failing for sizes it can't hold.
+    gas.record_cost(COPY_WORD_COST * words(size))?;
+    expand_memory(memory, gas, dest, size)?;
     let mut copied_data = vec![0; size];
 
     // check if offset is within bounds of data
-    if offset < data.len() {
+    if offset < data.len().into() {
         // calculate the amount of data available to copy
-        let available_data = &data[offset..];
+        let available_data = &data[offset.as_usize()..];
 
         // calculate the actual copy size based on available data
         let copy_size = std::cmp::min(size, available_data.len());
@@ -1139,27 +1644,25 @@ fn copy_data_to_memory(
         copied_data[..copy_size].copy_from_slice(&available_data[..copy_size]);
     }
 
-    for (i, byte) in copied_data.iter().enumerate() {
-        memory.save_byte(dest + i, *byte)?;
-    }
-
-    Ok(())
+    memory.save_bytes(dest, &copied_data)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn logx(
     x: usize,
     stack: &mut Vec<U256>,
     memory: &mut Memory,
-    address: &[u8],
+    gas: &mut Gas,
+    address: Address,
     logs: &mut Vec<Log>,
+    inspector: &Option<SharedInspector>,
     read_only: bool,
 ) -> Result<(), ExecutionError> {
     if read_only {
         return Err(ExecutionError::ReadOnly);
     }
 
-    let offset = pop(stack)?.as_usize();
-    let size = pop(stack)?.as_usize();
+    let (offset, size) = pop_memory_range(stack)?;
     let mut topics = vec![];
 
     for _ in 0..x {
@@ -1167,9 +1670,14 @@ fn logx(
         topics.push(topic);
     }
 
+    gas.record_cost(LOG_TOPIC_COST * x as u64 + LOG_DATA_COST.saturating_mul(size as u64))?;
+    expand_memory(memory, gas, offset, size)?;
     let data = memory.get_bytes(offset, size)?;
 
-    let log = Log::new(U256::from_big_endian(address), data, topics);
+    let log = Log::new(address, data, topics);
+    if let Some(inspector) = inspector {
+        inspector.borrow_mut().log(&log);
+    }
     logs.push(log);
 
     Ok(())
@@ -1178,10 +1686,11 @@ fn logx(
 fn return_func(
     stack: &mut Vec<U256>,
     memory: &mut Memory,
+    gas: &mut Gas,
     return_data: &mut Vec<u8>,
 ) -> Result<(), ExecutionError> {
-    let offset = pop(stack)?.as_usize();
-    let size = pop(stack)?.as_usize();
+    let (offset, size) = pop_memory_range(stack)?;
+    expand_memory(memory, gas, offset, size)?;
 
     let data = memory.get_bytes(offset, size)?;
     *return_data = data;
@@ -1189,209 +1698,303 @@ fn return_func(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn call(
     stack: &mut Vec<U256>,
     memory: &mut Memory,
     state: &mut State,
     storage: &mut Storage,
-    tx_to: &[u8],
-    tx_origin: &[u8],
+    block_env: &BlockEnv,
+    tx_to: Address,
+    tx_origin: Address,
     last_ret_data: &mut Vec<u8>,
+    gas: &mut Gas,
+    spec: SpecId,
+    precompiles: &Precompiles,
+    inspector: &Option<SharedInspector>,
+    depth: usize,
     read_only: bool,
 ) -> Result<(), ExecutionError> {
-    let _gas = pop(stack)?;
-    let address = pop(stack)?;
+    let gas_limit = pop(stack)?;
+    let address = Address::from_word(pop(stack)?);
     let value = pop(stack)?;
 
     if read_only && !value.is_zero() {
         return Err(ExecutionError::ReadOnly);
     }
 
-    let args_offset = pop(stack)?.as_usize();
-    let args_size = pop(stack)?.as_usize();
-    let ret_offset = pop(stack)?.as_usize();
-    let _ret_size = pop(stack)?.as_usize();
+    let (args_offset, args_size) = pop_memory_range(stack)?;
+    let (ret_offset, ret_size) = pop_memory_range(stack)?;
 
-    let code = state.get_code(address);
+    expand_memory(memory, gas, args_offset, args_size)?;
+    expand_memory(memory, gas, ret_offset, ret_size)?;
     let calldata = memory.get_bytes(args_offset, args_size)?;
+    charge_call(state, gas, address, Some(value), spec)?;
+    let forwarded_gas = call_gas(gas_limit, gas, spec)?;
+    // a call sending value gives the callee a stipend, which the caller gets back unless the
+    // callee spends it.
+    let stipend = if value.is_zero() { 0 } else { CALL_STIPEND };
+    gas.erase_cost(stipend);
+    let child_gas_limit = forwarded_gas + stipend;
+    let inputs = || CallInputs {
+        kind: CallKind::Call,
+        target: address,
+        context: address,
+        caller: tx_to,
+        value,
+        input: calldata.clone(),
+        gas_limit: child_gas_limit,
+    };
+    let checkpoint = checkpoint(state, storage);
+    let (result, return_data) = inspect_call(inspector, inputs, || {
+        // a caller holding less than the value fails the call without running it.
+        if !state.transfer(tx_to, address, value)? {
+            return Ok((ExecutionResult::Revert, vec![]));
+        }
+        match precompiles.get(address) {
+            Some(precompile) => run_precompile(precompile, &calldata, child_gas_limit, gas),
+            None => {
+                let code = load_code(state, address, spec)?;
+
+                let tx_env = TxEnv {
+                    to: address,
+                    caller: tx_to,
+                    origin: tx_origin,
+                    gas_price: U256::zero(),
+                    value,
+                    data: calldata.clone(),
+                    gas_limit: child_gas_limit,
+                };
+
+                let mut new_evm = Evm::new(
+                    Box::from(code),
+                    vec![],
+                    tx_env,
+                    block_env.clone(),
+                    state.take(),
+                    storage.take(),
+                    vec![],
+                    vec![],
+                    vec![],
+                    spec,
+                    precompiles.clone(),
+                    false,
+                );
+                new_evm.inspector = inspector.clone();
+                new_evm.depth = depth + 1;
+                new_evm.memory = memory.child();
+
+                execute_frame(new_evm, state, storage, gas)
+            }
+        }
+    })?;
+    settle(state, storage, checkpoint, &result);
 
-    let to = address.to_big_endian();
-    let value_bytes = value.to_big_endian();
-    let tx_data = TxData::new(vec![
-        to.to_vec(),
-        tx_to.to_vec(),
-        tx_origin.to_vec(),
-        vec![],
-        value_bytes.to_vec(),
-        calldata,
-    ]);
-
-    let block_data = BlockData::new(vec![]);
-
-    let mut new_evm = Evm::new(
-        Box::from(code),
-        vec![],
-        tx_data,
-        block_data,
-        state.clone(),
-        storage.clone(),
-        vec![],
-        vec![],
-        vec![],
-        false,
-    );
-
-    let result = new_evm.execute();
-
-    memory.save_bytes(ret_offset, &new_evm.return_data())?;
-    *last_ret_data = new_evm.return_data();
+    let copy_size = return_data.len().min(ret_size);
+    memory.save_bytes(ret_offset, &return_data[..copy_size])?;
+    *last_ret_data = return_data;
 
     let res = match result {
-        ExecutionResult::Success | ExecutionResult::Halt => {
-            *state = new_evm.state();
-            *storage = new_evm.storage();
-            1.into()
-        }
-        ExecutionResult::Revert => 0.into(),
+        ExecutionResult::Success(_) => 1.into(),
+        ExecutionResult::Revert | ExecutionResult::Exception(_) => 0.into(),
     };
 
     stack.push(res);
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn delegatecall(
     stack: &mut Vec<U256>,
     memory: &mut Memory,
     state: &mut State,
     storage: &mut Storage,
-    tx_to: &[u8],
-    tx_from: &[u8],
-    tx_origin: &[u8],
-    value: &[u8],
+    block_env: &BlockEnv,
+    tx_to: Address,
+    tx_from: Address,
+    tx_origin: Address,
+    value: U256,
     last_ret_data: &mut Vec<u8>,
+    gas: &mut Gas,
+    spec: SpecId,
+    precompiles: &Precompiles,
+    inspector: &Option<SharedInspector>,
+    depth: usize,
 ) -> Result<(), ExecutionError> {
-    let _gas = pop(stack)?;
-    let address = pop(stack)?;
+    let gas_limit = pop(stack)?;
+    let address = Address::from_word(pop(stack)?);
 
-    let args_offset = pop(stack)?.as_usize();
-    let args_size = pop(stack)?.as_usize();
-    let ret_offset = pop(stack)?.as_usize();
-    let _ret_size = pop(stack)?.as_usize();
+    let (args_offset, args_size) = pop_memory_range(stack)?;
+    let (ret_offset, ret_size) = pop_memory_range(stack)?;
 
-    let code = state.get_code(address);
+    expand_memory(memory, gas, args_offset, args_size)?;
+    expand_memory(memory, gas, ret_offset, ret_size)?;
     let calldata = memory.get_bytes(args_offset, args_size)?;
-
-    let tx_data = TxData::new(vec![
-        tx_to.to_vec(),
-        tx_from.to_vec(),
-        tx_origin.to_vec(),
-        vec![],
-        value.to_vec(),
-        calldata,
-    ]);
-
-    let block_data = BlockData::new(vec![]);
-
-    let mut new_evm = Evm::new(
-        Box::from(code),
-        vec![],
-        tx_data,
-        block_data,
-        state.clone(),
-        storage.clone(),
-        vec![],
-        vec![],
-        vec![],
-        false,
-    );
-
-    let result = new_evm.execute();
-
-    memory.save_bytes(ret_offset, &new_evm.return_data())?;
-    *last_ret_data = new_evm.return_data();
+    charge_call(state, gas, address, None, spec)?;
+    let child_gas_limit = call_gas(gas_limit, gas, spec)?;
+    let inputs = || CallInputs {
+        kind: CallKind::DelegateCall,
+        target: address,
+        context: tx_to,
+        caller: tx_from,
+        value,
+        input: calldata.clone(),
+        gas_limit: child_gas_limit,
+    };
+    let checkpoint = checkpoint(state, storage);
+    let (result, return_data) =
+        inspect_call(inspector, inputs, || match precompiles.get(address) {
+            Some(precompile) => run_precompile(precompile, &calldata, child_gas_limit, gas),
+            None => {
+                let code = load_code(state, address, spec)?;
+
+                let tx_env = TxEnv {
+                    to: tx_to,
+                    caller: tx_from,
+                    origin: tx_origin,
+                    gas_price: U256::zero(),
+                    value,
+                    data: calldata.clone(),
+                    gas_limit: child_gas_limit,
+                };
+
+                let mut new_evm = Evm::new(
+                    Box::from(code),
+                    vec![],
+                    tx_env,
+                    block_env.clone(),
+                    state.take(),
+                    storage.take(),
+                    vec![],
+                    vec![],
+                    vec![],
+                    spec,
+                    precompiles.clone(),
+                    false,
+                );
+                new_evm.inspector = inspector.clone();
+                new_evm.depth = depth + 1;
+                new_evm.memory = memory.child();
+
+                execute_frame(new_evm, state, storage, gas)
+            }
+        })?;
+    settle(state, storage, checkpoint, &result);
+
+    let copy_size = return_data.len().min(ret_size);
+    memory.save_bytes(ret_offset, &return_data[..copy_size])?;
+    *last_ret_data = return_data;
 
     let res = match result {
-        ExecutionResult::Success | ExecutionResult::Halt => {
-            *state = new_evm.state();
-            *storage = new_evm.storage();
-            1.into()
-        }
-        ExecutionResult::Revert => 0.into(),
+        ExecutionResult::Success(_) => 1.into(),
+        ExecutionResult::Revert | ExecutionResult::Exception(_) => 0.into(),
     };
 
     stack.push(res);
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn staticcall(
     stack: &mut Vec<U256>,
     memory: &mut Memory,
     state: &mut State,
     storage: &mut Storage,
-    tx_to: &[u8],
-    tx_origin: &[u8],
-    tx_value: &[u8],
+    block_env: &BlockEnv,
+    tx_to: Address,
+    tx_origin: Address,
     last_ret_data: &mut Vec<u8>,
+    gas: &mut Gas,
+    spec: SpecId,
+    precompiles: &Precompiles,
+    inspector: &Option<SharedInspector>,
+    depth: usize,
 ) -> Result<U256, ExecutionError> {
-    let _gas = pop(stack)?;
-    let address = pop(stack)?;
-    let args_offset = pop(stack)?.as_usize();
-    let args_size = pop(stack)?.as_usize();
-    let ret_offset = pop(stack)?.as_usize();
-    let _ret_size = pop(stack)?.as_usize();
-
-    let code = state.get_code(address);
+    let gas_limit = pop(stack)?;
+    let address = Address::from_word(pop(stack)?);
+    let (args_offset, args_size) = pop_memory_range(stack)?;
+    let (ret_offset, ret_size) = pop_memory_range(stack)?;
+
+    expand_memory(memory, gas, args_offset, args_size)?;
+    expand_memory(memory, gas, ret_offset, ret_size)?;
     let calldata = memory.get_bytes(args_offset, args_size)?;
-    let to = address.to_big_endian();
-    let tx_data = TxData::new(vec![
-        to.to_vec(),
-        tx_to.to_vec(),
-        tx_origin.to_vec(),
-        vec![],
-        tx_value.to_vec(),
-        calldata,
-    ]);
-    let block_data = BlockData::new(vec![]);
-
-    let mut new_evm = Evm::new(
-        Box::from(code),
-        vec![],
-        tx_data,
-        block_data,
-        state.clone(),
-        storage.clone(),
-        vec![],
-        vec![],
-        vec![],
-        true,
-    );
-
-    let result = new_evm.execute();
-
-    memory.save_bytes(ret_offset, &new_evm.return_data())?;
-    *last_ret_data = new_evm.return_data();
+    charge_call(state, gas, address, None, spec)?;
+    let child_gas_limit = call_gas(gas_limit, gas, spec)?;
+    let inputs = || CallInputs {
+        kind: CallKind::StaticCall,
+        target: address,
+        context: address,
+        caller: tx_to,
+        value: U256::zero(),
+        input: calldata.clone(),
+        gas_limit: child_gas_limit,
+    };
+    let checkpoint = checkpoint(state, storage);
+    let (result, return_data) =
+        inspect_call(inspector, inputs, || match precompiles.get(address) {
+            Some(precompile) => run_precompile(precompile, &calldata, child_gas_limit, gas),
+            None => {
+                let code = load_code(state, address, spec)?;
+                let tx_env = TxEnv {
+                    to: address,
+                    caller: tx_to,
+                    origin: tx_origin,
+                    gas_price: U256::zero(),
+                    value: U256::zero(),
+                    data: calldata.clone(),
+                    gas_limit: child_gas_limit,
+                };
+                let mut new_evm = Evm::new(
+                    Box::from(code),
+                    vec![],
+                    tx_env,
+                    block_env.clone(),
+                    state.take(),
+                    storage.take(),
+                    vec![],
+                    vec![],
+                    vec![],
+                    spec,
+                    precompiles.clone(),
+                    true,
+                );
+                new_evm.inspector = inspector.clone();
+                new_evm.depth = depth + 1;
+                new_evm.memory = memory.child();
+
+                execute_frame(new_evm, state, storage, gas)
+            }
+        })?;
+    settle(state, storage, checkpoint, &result);
+
+    let copy_size = return_data.len().min(ret_size);
+    memory.save_bytes(ret_offset, &return_data[..copy_size])?;
+    *last_ret_data = return_data;
 
     let res = match result {
-        ExecutionResult::Success | ExecutionResult::Halt => {
-            *state = new_evm.state();
-            *storage = new_evm.storage();
-            1.into()
-        }
-        ExecutionResult::Revert => 0.into(),
+        ExecutionResult::Success(_) => 1.into(),
+        ExecutionResult::Revert | ExecutionResult::Exception(_) => 0.into(),
     };
 
     stack.push(res);
     Ok(res)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn create(
     stack: &mut Vec<U256>,
     memory: &mut Memory,
     state: &mut State,
     storage: &mut Storage,
-    tx_to: &[u8],
-    tx_origin: &[u8],
+    block_env: &BlockEnv,
+    tx_to: Address,
+    tx_origin: Address,
     last_ret_data: &mut Vec<u8>,
+    gas: &mut Gas,
+    spec: SpecId,
+    precompiles: &Precompiles,
+    inspector: &Option<SharedInspector>,
+    depth: usize,
     read_only: bool,
 ) -> Result<U256, ExecutionError> {
     if read_only {
@@ -1399,81 +2002,891 @@ fn create(
     }
 
     let value = pop(stack)?;
-    let offset = pop(stack)?.as_usize();
-    let size = pop(stack)?.as_usize();
+    let (offset, size) = pop_memory_range(stack)?;
 
+    expand_memory(memory, gas, offset, size)?;
+    // initcode is charged by the word since Shanghai (EIP-3860).
+    if spec.is_enabled_in(SpecId::Shanghai) {
+        gas.record_cost(INITCODE_WORD_COST * words(size))?;
+    }
     let code = memory.get_bytes(offset, size)?;
-    let address = U256::from_big_endian(tx_to);
-    let nonce = state.get_nonce(address);
 
+    // EOF initcode is refused until EOF is enabled, and must then be a valid container.
+    let mut initcontainer = None;
+    if Eof::is_eof(&code) {
+        let container = Eof::decode(&code)
+            .and_then(|eof| validate_eof(&eof, ContainerKind::Initcode).map(|_| eof));
+        match container {
+            Ok(container) if spec.is_enabled_in(SpecId::PragueEof) => {
+                initcontainer = Some(EofFrame::new(container))
+            }
+            _ => {
+                stack.push(0.into());
+                return Ok(0.into());
+            }
+        }
+    }
+
+    let nonce = state.get_nonce(tx_to)?;
     let contract_address = calculate_address(tx_to, nonce);
-    let contract_address_bytes = contract_address.to_big_endian();
 
-    let value_bytes = value.to_big_endian();
+    // a creator whose nonce can't be incremented fails the creation (EIP-2681). A collision
+    // consumes the gas forwarded to the creation like an exceptional halt, once the nonce of
+    // the creator is incremented, and the caller goes on with the gas it kept.
+    if !state.increment_nonce(tx_to)? {
+        stack.push(0.into());
+        return Ok(0.into());
+    }
+    let forwarded_gas = create_gas(gas, spec);
+    if state.collides(contract_address)? {
+        gas.record_cost(forwarded_gas)?;
+        stack.push(0.into());
+        return Ok(0.into());
+    }
 
-    let tx_data = TxData::new(vec![
-        contract_address_bytes.to_vec(),
-        tx_to.to_vec(),
-        tx_origin.to_vec(),
-        vec![],
-        value_bytes.to_vec(),
-        vec![],
-    ]);
+    let inputs = || CreateInputs {
+        kind: CreateKind::Create,
+        caller: tx_to,
+        address: contract_address,
+        value,
+        init_code: code.clone(),
+    };
+    let checkpoint = checkpoint(state, storage);
+    let (result, return_data) = inspect_create(inspector, inputs, || {
+        if !state.transfer(tx_to, contract_address, value)? {
+            return Ok((ExecutionResult::Revert, vec![]));
+        }
+
+        let tx_env = TxEnv {
+            to: contract_address,
+            caller: tx_to,
+            origin: tx_origin,
+            gas_price: U256::zero(),
+            value,
+            data: vec![],
+            gas_limit: forwarded_gas,
+        };
+
+        let mut new_evm = Evm::new(
+            Box::from(code.as_slice()),
+            vec![],
+            tx_env,
+            block_env.clone(),
+            state.take(),
+            storage.take(),
+            vec![],
+            vec![],
+            vec![],
+            spec,
+            precompiles.clone(),
+            false,
+        );
+        new_evm.eof = initcontainer;
+        new_evm.initcode = true;
+        new_evm.inspector = inspector.clone();
+        new_evm.depth = depth + 1;
+        new_evm.memory = memory.child();
+
+        let (result, output) = execute_frame(new_evm, state, storage, gas)?;
+        if matches!(result, ExecutionResult::Success(_)) {
+            state.set_code(contract_address, output.clone())?;
+        }
+        Ok((result, output))
+    })?;
+    settle(state, storage, checkpoint, &result);
 
-    let block_data = BlockData::new(vec![]);
+    let res = match result {
+        ExecutionResult::Success(_) => {
+            *last_ret_data = return_data;
+            contract_address.to_word()
+        }
+        ExecutionResult::Revert | ExecutionResult::Exception(_) => 0.into(),
+    };
 
-    let mut new_evm = Evm::new(
-        Box::from(code),
-        vec![],
-        tx_data,
-        block_data,
-        state.clone(),
-        storage.clone(),
-        vec![],
-        vec![],
-        vec![],
-        false,
-    );
+    stack.push(res);
+    Ok(res)
+}
 
-    let result = new_evm.execute();
+/// Converts a memory offset or size taken from the stack. Values past `usize::MAX` could never
+/// be paid for, so they run out of gas.
+fn as_offset(value: U256) -> Result<usize, ExecutionError> {
+    usize::try_from(value).map_err(|_| ExecutionError::IntegerOverflow)
+}
 
-    let res = match result {
-        ExecutionResult::Success | ExecutionResult::Halt => {
-            *state = new_evm.state();
-            *storage = new_evm.storage();
+/// Pops the offset and size of a memory range. Empty ranges don't touch the memory, so their
+/// offset is ignored, whatever its value.
+fn pop_memory_range(stack: &mut Vec<U256>) -> Result<(usize, usize), ExecutionError> {
+    let offset = pop(stack)?;
+    let size = pop(stack)?;
+    if size.is_zero() {
+        return Ok((0, 0));
+    }
+    Ok((as_offset(offset)?, as_offset(size)?))
+}
+
+/// Charges the expansion of the memory to hold `size` bytes at `offset`, and expands it. Empty
+/// ranges don't expand the memory.
+fn expand_memory(
+    memory: &mut Memory,
+    gas: &mut Gas,
+    offset: usize,
+    size: usize,
+) -> Result<(), ExecutionError> {
+    if size == 0 {
+        return Ok(());
+    }
+    let end = offset
+        .checked_add(size)
+        .ok_or(ExecutionError::IntegerOverflow)?;
+    let current_words = words(memory.size());
+    let new_words = words(end);
+    if new_words > current_words {
+        gas.record_cost(memory_cost(new_words) - memory_cost(current_words))?;
+        memory.resize(offset, size)?;
+    }
+    Ok(())
+}
+
+/// Marks the account at `address` as accessed, charging the first access of the transaction
+/// more since Berlin (EIP-2929). The instructions accessing an account already paid for a
+/// warm access.
+fn access_account(
+    state: &mut State,
+    gas: &mut Gas,
+    address: Address,
+    spec: SpecId,
+) -> Result<(), ExecutionError> {
+    if state.access(address) && spec.is_enabled_in(SpecId::Berlin) {
+        gas.record_cost(COLD_ACCOUNT_ACCESS_COST - WARM_STORAGE_READ_COST)?;
+    }
+    Ok(())
+}
+
+/// Charges the access to the delegate of the account at `address`, whose code a call runs
+/// since Prague (EIP-7702).
+fn access_delegate(
+    state: &mut State,
+    gas: &mut Gas,
+    address: Address,
+    spec: SpecId,
+) -> Result<(), ExecutionError> {
+    if !spec.is_enabled_in(SpecId::Prague) {
+        return Ok(());
+    }
+    if let Some(delegate) = delegated_address(&state.get_code(address)?) {
+        gas.record_cost(WARM_STORAGE_READ_COST)?;
+        access_account(state, gas, delegate, spec)?;
+    }
+    Ok(())
+}
 
-            state.save_code(contract_address, new_evm.return_data(), value)?;
-            *last_ret_data = new_evm.return_data();
-            contract_address
+/// Returns whether sending `value` to the account at `address` creates it, which calls and
+/// selfdestructs pay for: any call to an account that doesn't exist creates it, and since
+/// Spurious Dragon only sending value to an empty account does (EIP-161).
+fn creates_account(
+    state: &mut State,
+    address: Address,
+    value: U256,
+    spec: SpecId,
+) -> Result<bool, ExecutionError> {
+    let account = state.account(address)?;
+    if spec.is_enabled_in(SpecId::SpuriousDragon) {
+        Ok(!value.is_zero() && account.is_none_or(|account| account.is_empty()))
+    } else {
+        Ok(account.is_none())
+    }
+}
+
+/// Moves the pc to the `target` of a relative jump. The main loop moves the pc past the current
+/// instruction, so it is left right before the target, wrapping around for a target of zero.
+fn relative_jump(pc: &mut usize, target: usize) {
+    *pc = target.wrapping_sub(1);
+}
+
+/// Loads the 32 bytes of `data` at `offset`, padded with zeros past its end.
+fn load_word(data: &[u8], offset: U256) -> U256 {
+    let mut word = [0u8; 32];
+    if offset < data.len().into() {
+        let available_data = &data[offset.as_usize()..];
+        let size = std::cmp::min(32, available_data.len());
+        word[..size].copy_from_slice(&available_data[..size]);
+    }
+    U256::from_big_endian(&word)
+}
+
+/// Exchanges the `n + 1`th and `n + m + 1`th stack items.
+fn exchange(stack: &mut [U256], n: usize, m: usize) -> Result<(), ExecutionError> {
+    let top = stack
+        .len()
+        .checked_sub(1)
+        .ok_or(ExecutionError::StackUnderflow)?;
+    let first = top.checked_sub(n).ok_or(ExecutionError::StackUnderflow)?;
+    let second = first.checked_sub(m).ok_or(ExecutionError::StackUnderflow)?;
+    stack.swap(first, second);
+    Ok(())
+}
+
+/// `EXTCALL`, `EXTDELEGATECALL` and `EXTSTATICCALL`, the calls of EOF code. The callee gets all
+/// the gas left but what the caller keeps, and the status pushed is 0 on success, 1 on revert
+/// and 2 on failure.
+#[allow(clippy::too_many_arguments)]
+fn ext_call(
+    opcode: OpCode,
+    stack: &mut Vec<U256>,
+    memory: &mut Memory,
+    state: &mut State,
+    storage: &mut Storage,
+    block_env: &BlockEnv,
+    tx_env: &TxEnv,
+    last_ret_data: &mut Vec<u8>,
+    gas: &mut Gas,
+    spec: SpecId,
+    precompiles: &Precompiles,
+    inspector: &Option<SharedInspector>,
+    depth: usize,
+    read_only: bool,
+) -> Result<(), ExecutionError> {
+    let target = pop(stack)?;
+    let (args_offset, args_size) = pop_memory_range(stack)?;
+    let value = match opcode {
+        OpCode::Extcall => pop(stack)?,
+        _ => U256::zero(),
+    };
+
+    // addresses are 20 bytes long.
+    if target.bits() > 160 {
+        return Err(ExecutionError::InvalidExtCallTarget);
+    }
+    let address = Address::from_word(target);
+    if read_only && !value.is_zero() {
+        return Err(ExecutionError::ReadOnly);
+    }
+
+    expand_memory(memory, gas, args_offset, args_size)?;
+    let sent_value = (opcode == OpCode::Extcall).then_some(value);
+    charge_call(state, gas, address, sent_value, spec)?;
+    let code = load_code(state, address, spec)?;
+    let calldata = memory.get_bytes(args_offset, args_size)?;
+
+    // the caller keeps a 64th of its gas, and at least `EXT_CALL_MIN_RETAINED_GAS`.
+    let remaining = gas.remaining();
+    let child_gas_limit = remaining.saturating_sub((remaining / 64).max(EXT_CALL_MIN_RETAINED_GAS));
+
+    // delegating to legacy code, or giving the callee too little gas, is a light failure.
+    let starved = child_gas_limit < EXT_CALL_MIN_CALLEE_GAS && !gas.is_unmetered();
+    if starved || (opcode == OpCode::Extdelegatecall && !Eof::is_eof(&code)) {
+        last_ret_data.clear();
+        stack.push(EXT_CALL_REVERT.into());
+        return Ok(());
+    }
+
+    let (to, caller, child_value) = match opcode {
+        OpCode::Extdelegatecall => (tx_env.to, tx_env.caller, tx_env.value),
+        _ => (address, tx_env.to, value),
+    };
+    let inputs = || CallInputs {
+        kind: match opcode {
+            OpCode::Extdelegatecall => CallKind::ExtDelegateCall,
+            OpCode::Extstaticcall => CallKind::ExtStaticCall,
+            _ => CallKind::ExtCall,
+        },
+        target: address,
+        context: to,
+        caller,
+        value: child_value,
+        input: calldata.clone(),
+        gas_limit: child_gas_limit,
+    };
+    let checkpoint = checkpoint(state, storage);
+    let (result, return_data) = inspect_call(inspector, inputs, || {
+        if !state.transfer(tx_env.to, address, value)? {
+            return Ok((ExecutionResult::Revert, vec![]));
+        }
+        match precompiles.get(address) {
+            Some(precompile) => run_precompile(precompile, &calldata, child_gas_limit, gas),
+            None => {
+                let child_tx_env = TxEnv {
+                    to,
+                    caller,
+                    origin: tx_env.origin,
+                    gas_price: U256::zero(),
+                    value: child_value,
+                    data: calldata.clone(),
+                    gas_limit: child_gas_limit,
+                };
+
+                let mut new_evm = Evm::new(
+                    Box::from(code),
+                    vec![],
+                    child_tx_env,
+                    block_env.clone(),
+                    state.take(),
+                    storage.take(),
+                    vec![],
+                    vec![],
+                    vec![],
+                    spec,
+                    precompiles.clone(),
+                    read_only || opcode == OpCode::Extstaticcall,
+                );
+                new_evm.inspector = inspector.clone();
+                new_evm.depth = depth + 1;
+                new_evm.memory = memory.child();
+
+                execute_frame(new_evm, state, storage, gas)
+            }
+        }
+    })?;
+    settle(state, storage, checkpoint, &result);
+    *last_ret_data = return_data;
+
+    let status = match result {
+        ExecutionResult::Success(_) => 0,
+        ExecutionResult::Revert => EXT_CALL_REVERT,
+        ExecutionResult::Exception(_) => EXT_CALL_FAILURE,
+    };
+
+    stack.push(status.into());
+    Ok(())
+}
+
+/// `EOFCREATE`, deploying the container returned by the `initcontainer` with `RETURNCONTRACT`
+/// at an address derived from the sender, the salt and the initcontainer.
+#[allow(clippy::too_many_arguments)]
+fn eofcreate(
+    initcontainer: &[u8],
+    stack: &mut Vec<U256>,
+    memory: &mut Memory,
+    state: &mut State,
+    storage: &mut Storage,
+    block_env: &BlockEnv,
+    tx_to: Address,
+    tx_origin: Address,
+    last_ret_data: &mut Vec<u8>,
+    gas: &mut Gas,
+    spec: SpecId,
+    precompiles: &Precompiles,
+    inspector: &Option<SharedInspector>,
+    depth: usize,
+    read_only: bool,
+) -> Result<U256, ExecutionError> {
+    if read_only {
+        return Err(ExecutionError::ReadOnly);
+    }
+
+    let value = pop(stack)?;
+    let salt = pop(stack)?;
+    let (args_offset, args_size) = pop_memory_range(stack)?;
+
+    expand_memory(memory, gas, args_offset, args_size)?;
+    // the initcontainer is hashed to derive the address.
+    gas.record_cost(KECCAK_WORD_COST * words(initcontainer.len()))?;
+    let calldata = memory.get_bytes(args_offset, args_size)?;
+    // container sections are validated along with the container holding them.
+    let container = Eof::decode(initcontainer).map_err(|_| ExecutionError::InvalidEof)?;
+
+    let hash = sha3_hash(initcontainer);
+    let preimage = [&[0xff], &tx_to.0[..], &salt.to_big_endian()[..], &hash[..]].concat();
+    let contract_address = Address::from_word(U256::from_big_endian(&sha3_hash(&preimage)));
+
+    // a creator whose nonce can't be incremented fails the creation (EIP-2681). A collision
+    // consumes the gas forwarded to the creation like an exceptional halt, once the nonce of
+    // the creator is incremented, and the caller goes on with the gas it kept.
+    if !state.increment_nonce(tx_to)? {
+        stack.push(0.into());
+        return Ok(0.into());
+    }
+    let forwarded_gas = create_gas(gas, spec);
+    if state.collides(contract_address)? {
+        gas.record_cost(forwarded_gas)?;
+        stack.push(0.into());
+        return Ok(0.into());
+    }
+
+    let inputs = || CreateInputs {
+        kind: CreateKind::EofCreate,
+        caller: tx_to,
+        address: contract_address,
+        value,
+        init_code: initcontainer.to_vec(),
+    };
+    let checkpoint = checkpoint(state, storage);
+    let (result, return_data) = inspect_create(inspector, inputs, || {
+        if !state.transfer(tx_to, contract_address, value)? {
+            return Ok((ExecutionResult::Revert, vec![]));
+        }
+
+        let tx_env = TxEnv {
+            to: contract_address,
+            caller: tx_to,
+            origin: tx_origin,
+            gas_price: U256::zero(),
+            value,
+            data: calldata,
+            gas_limit: forwarded_gas,
+        };
+
+        let mut new_evm = Evm::new(
+            Box::from(initcontainer),
+            vec![],
+            tx_env,
+            block_env.clone(),
+            state.take(),
+            storage.take(),
+            vec![],
+            vec![],
+            vec![],
+            spec,
+            precompiles.clone(),
+            false,
+        );
+        new_evm.eof = Some(EofFrame::new(container));
+        new_evm.initcode = true;
+        new_evm.inspector = inspector.clone();
+        new_evm.depth = depth + 1;
+        new_evm.memory = memory.child();
+
+        let (result, output) = execute_frame(new_evm, state, storage, gas)?;
+        if matches!(result, ExecutionResult::Success(_)) {
+            state.set_code(contract_address, output.clone())?;
+        }
+        Ok((result, output))
+    })?;
+    settle(state, storage, checkpoint, &result);
+
+    let res = match result {
+        ExecutionResult::Success(_) => {
+            last_ret_data.clear();
+            contract_address.to_word()
+        }
+        ExecutionResult::Revert => {
+            *last_ret_data = return_data;
+            0.into()
+        }
+        ExecutionResult::Exception(_) => {
+            last_ret_data.clear();
+            0.into()
         }
-        ExecutionResult::Revert => 0.into(),
     };
 
     stack.push(res);
     Ok(res)
 }
 
-fn calculate_address(sender_address: &[u8], nonce: usize) -> U256 {
-    let result = sha3_hash(&[sender_address, &nonce.to_be_bytes()].concat()).to_vec();
-    let result = result.get(12..).unwrap_or(&[0_u8; 20]);
-    U256::from_big_endian(&result)
+/// Runs a call through the `call` and `call_end` hooks of the inspector, an outcome returned by
+/// `call` replacing the call. The caller keeps or discards the changes of the call by the
+/// outcome returned by `call_end`.
+pub(crate) fn inspect_call(
+    inspector: &Option<SharedInspector>,
+    inputs: impl FnOnce() -> CallInputs,
+    call: impl FnOnce() -> Result<(ExecutionResult, Vec<u8>), ExecutionError>,
+) -> Result<(ExecutionResult, Vec<u8>), ExecutionError> {
+    let Some(inspector) = inspector else {
+        return call();
+    };
+    let inputs = inputs();
+    let overridden = inspector.borrow_mut().call(&inputs);
+    let outcome = match overridden {
+        Some(outcome) => outcome,
+        None => {
+            let (result, output) = call()?;
+            Outcome::new(result, output)
+        }
+    };
+    let outcome = inspector.borrow_mut().call_end(&inputs, outcome);
+    Ok(outcome.into_result())
+}
+
+/// Runs a creation through the `create` and `create_end` hooks of the inspector, like
+/// [`inspect_call`].
+pub(crate) fn inspect_create(
+    inspector: &Option<SharedInspector>,
+    inputs: impl FnOnce() -> CreateInputs,
+    create: impl FnOnce() -> Result<(ExecutionResult, Vec<u8>), ExecutionError>,
+) -> Result<(ExecutionResult, Vec<u8>), ExecutionError> {
+    let Some(inspector) = inspector else {
+        return create();
+    };
+    let inputs = inputs();
+    let overridden = inspector.borrow_mut().create(&inputs);
+    let outcome = match overridden {
+        Some(outcome) => outcome,
+        None => {
+            let (result, output) = create()?;
+            Outcome::new(result, output)
+        }
+    };
+    let outcome = inspector.borrow_mut().create_end(&inputs, outcome);
+    Ok(outcome.into_result())
+}
+
+/// Charges the access to the account at `address` called and, for the calls sending value, the
+/// `value` sent.
+fn charge_call(
+    state: &mut State,
+    gas: &mut Gas,
+    address: Address,
+    value: Option<U256>,
+    spec: SpecId,
+) -> Result<(), ExecutionError> {
+    access_account(state, gas, address, spec)?;
+    access_delegate(state, gas, address, spec)?;
+    if let Some(value) = value {
+        if !value.is_zero() {
+            gas.record_cost(CALL_VALUE_COST)?;
+        }
+        if creates_account(state, address, value, spec)? {
+            gas.record_cost(NEW_ACCOUNT_COST)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the gas forwarded to a call: the gas `requested`, capped since EIP-150 (Tangerine
+/// Whistle) by all the gas left but one 64th, and before it failing if more than the gas left.
+fn call_gas(requested: U256, gas: &Gas, spec: SpecId) -> Result<u64, ExecutionError> {
+    let remaining = gas.remaining();
+    if spec.is_enabled_in(SpecId::TangerineWhistle) {
+        return Ok(requested.min((remaining - remaining / 64).into()).as_u64());
+    }
+    if requested > remaining.into() && !gas.is_unmetered() {
+        return Err(ExecutionError::OutOfGas);
+    }
+    Ok(requested.min(remaining.into()).as_u64())
+}
+
+/// Returns the gas forwarded to a creation: all the gas left, but one 64th since EIP-150
+/// (Tangerine Whistle).
+fn create_gas(gas: &Gas, spec: SpecId) -> u64 {
+    let remaining = gas.remaining();
+    if spec.is_enabled_in(SpecId::TangerineWhistle) {
+        remaining - remaining / 64
+    } else {
+        remaining
+    }
+}
+
+/// Executes a child frame on the state and storage moved into it, giving them back with its
+/// changes, and returns its result and output. The gas the frame spent is charged to `gas`,
+/// and the child of an unmetered frame is unmetered.
+pub fn execute_frame(
+    mut new_evm: Evm,
+    state: &mut State,
+    storage: &mut Storage,
+    gas: &mut Gas,
+) -> Result<(ExecutionResult, Vec<u8>), ExecutionError> {
+    if gas.is_unmetered() {
+        new_evm.gas = Gas::unmetered();
+    }
+    let result = stacker::maybe_grow(FRAME_RED_ZONE, FRAME_STACK_SIZE, || new_evm.execute());
+    *state = new_evm.state;
+    *storage = new_evm.storage;
+    let result = result?;
+
+    // the refunds of a frame are kept only if it succeeds.
+    gas.record_cost(new_evm.gas.spent())?;
+    if matches!(result, ExecutionResult::Success(_)) {
+        gas.record_refund(new_evm.gas.refunded());
+    }
+    Ok((result, new_evm.return_data))
+}
+
+/// Opens the checkpoints of the state and storage that a child frame is settled against.
+pub(crate) fn checkpoint(state: &mut State, storage: &mut Storage) -> (Checkpoint, Checkpoint) {
+    (state.checkpoint(), storage.checkpoint())
+}
+
+/// Keeps the changes made since the checkpoints if the final result of a child frame, as
+/// possibly overridden by the inspector, is a success, and reverts them otherwise.
+pub(crate) fn settle(
+    state: &mut State,
+    storage: &mut Storage,
+    (checkpoint, storage_checkpoint): (Checkpoint, Checkpoint),
+    result: &ExecutionResult,
+) {
+    if matches!(result, ExecutionResult::Success(_)) {
+        state.commit(checkpoint);
+        storage.commit(storage_checkpoint);
+    } else {
+        state.revert(checkpoint);
+        storage.revert(storage_checkpoint);
+    }
+}
+
+/// Runs a precompile with the `gas_limit` forwarded by the call, returning its result and
+/// output. Failing precompiles consume all that gas.
+fn run_precompile(
+    precompile: &Precompile,
+    input: &[u8],
+    gas_limit: u64,
+    gas: &mut Gas,
+) -> Result<(ExecutionResult, Vec<u8>), ExecutionError> {
+    match precompile(input, gas_limit) {
+        Ok(output) if output.gas_used <= gas_limit => {
+            gas.record_cost(output.gas_used)?;
+            Ok((
+                ExecutionResult::Success(SuccessReason::Return),
+                output.bytes,
+            ))
+        }
+        result => {
+            gas.record_cost(gas_limit)?;
+            let reason = match result {
+                Err(PrecompileError::InvalidInput(_)) => HaltReason::PrecompileError,
+                _ => HaltReason::OutOfGas,
+            };
+            Ok((ExecutionResult::Exception(reason), vec![]))
+        }
+    }
 }
 
+/// Returns the address of a contract created by CREATE, the hash of `rlp([sender, nonce])`.
+pub fn calculate_address(sender_address: Address, nonce: usize) -> Address {
+    let preimage = rlp::encode_list(&[
+        rlp::encode_bytes(&sender_address.0),
+        rlp::encode_u256(nonce.into()),
+    ]);
+    let result = sha3_hash(&preimage);
+    Address::from_word(U256::from_big_endian(&result))
+}
+
+#[allow(clippy::too_many_arguments)]
 fn selfdestruct(
     stack: &mut Vec<U256>,
     state: &mut State,
-    tx_to: &[u8],
+    tx_to: Address,
+    gas: &mut Gas,
+    spec: SpecId,
+    inspector: &Option<SharedInspector>,
     read_only: bool,
 ) -> Result<(), ExecutionError> {
     if read_only {
         return Err(ExecutionError::ReadOnly);
     }
 
-    let dest_address = pop(stack)?;
-    let src_address = U256::from_big_endian(tx_to);
+    let dest_address = Address::from_word(pop(stack)?);
+    let src_address = tx_to;
 
-    let balance = state.get_balance(src_address);
-    state.transfer_balance(balance, dest_address);
-    state.delete_account(src_address);
+    let balance = state.get_balance(src_address)?;
+    // the beneficiary is accessed without the cost of a warm access, and created by the
+    // balance sent since Tangerine Whistle.
+    if state.access(dest_address) && spec.is_enabled_in(SpecId::Berlin) {
+        gas.record_cost(COLD_ACCOUNT_ACCESS_COST)?;
+    }
+    if spec.is_enabled_in(SpecId::TangerineWhistle)
+        && creates_account(state, dest_address, balance, spec)?
+    {
+        gas.record_cost(NEW_ACCOUNT_COST)?;
+    }
+    // the refund was removed by EIP-3529 (London).
+    if !spec.is_enabled_in(SpecId::London) {
+        gas.record_refund(SELFDESTRUCT_REFUND);
+    }
+    if let Some(inspector) = inspector {
+        inspector
+            .borrow_mut()
+            .selfdestruct(src_address, dest_address, balance);
+    }
+    if !state.transfer(src_address, dest_address, balance)? {
+        return Err(ExecutionError::BalanceOverflow);
+    }
+    state.delete_account(src_address)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::calculate_address;
+    use crate::{
+        db::KECCAK_EMPTY, outcome::Change, primitives::Address, spec::SpecId, EvmBuilder, EvmResult,
+    };
+
+    /// A runtime container whose only code section stops.
+    const RUNTIME: [u8; 20] = hex!("ef000101000402000100010400000000800000" "00");
+
+    #[test]
+    fn deploys_eof_initcontainer() {
+        // RETURNCONTRACT deploys the runtime container, with no auxiliary data.
+        let initcode = [
+            &hex!("ef000101000402000100040300010000001404000000" "00800002" "5f5fee00")[..],
+            &RUNTIME,
+        ]
+        .concat();
+
+        let result = EvmBuilder::new()
+            .spec(SpecId::PragueEof)
+            .deploy(initcode)
+            .run()
+            .unwrap();
+
+        assert!(result.success, "{:?}", result.outcome);
+        let address = result.created_address.unwrap();
+        let code = result.state_diff[&address].code.as_ref().unwrap();
+        assert_eq!(code.after, RUNTIME);
+    }
+
+    #[test]
+    fn create_collision_fails_and_the_caller_continues() {
+        // CREATE twice, storing the addresses at slots 0 and 1.
+        let code = hex!("5f5f5ff05f55" "5f5f5ff060015500");
+        let creator = Address([0x11; 20]);
+        let existing = calculate_address(creator, 1);
+        let result = EvmBuilder::new()
+            .to(creator)
+            .account(creator, 1, 0.into(), code)
+            .account(existing, 1, 0.into(), [])
+            .run()
+            .unwrap();
+
+        // the first creation collides, the second one deploys at the next nonce.
+        assert!(result.success, "{:?}", result.outcome);
+        let diff = &result.state_diff[&creator];
+        assert_eq!(
+            diff.nonce,
+            Some(Change {
+                before: 1,
+                after: 3
+            })
+        );
+        assert!(!diff.storage.contains_key(&0.into()));
+        let created = calculate_address(creator, 2);
+        assert_eq!(diff.storage[&1.into()].after, created.to_word());
+        assert!(result.state_diff[&created].created);
+    }
+
+    #[test]
+    fn create_collision_consumes_only_the_forwarded_gas() {
+        // CREATE, then STATICCALL the ecrecover precompile, which costs 3000, storing its status
+        // at slot 0.
+        let code = hex!("5f5f5ff050" "5f5f5f5f600161fffffa5f5500");
+        let creator = Address([0x11; 20]);
+        let existing = calculate_address(creator, 1);
+        // the intrinsic gas, the three PUSH0 and the CREATE, then 64 times the 26000 gas the
+        // call and the store cost.
+        let result = EvmBuilder::new()
+            .to(creator)
+            .gas_limit(21000 + 32006 + 64 * 26000)
+            .account(creator, 1, 0.into(), code)
+            .account(existing, 1, 0.into(), [])
+            .run()
+            .unwrap();
+
+        // the collision consumes all but one 64th of the gas, which pays for the call.
+        assert!(result.success, "{:?}", result.outcome);
+        let diff = &result.state_diff[&creator];
+        assert_eq!(diff.storage[&0.into()].after, 1.into());
+    }
+
+    #[test]
+    fn created_address_matches_mainnet() {
+        let sender = Address(hex!("6ac7ea33f8831ea9dcc53393aaa88b25a785dbf0"));
+
+        assert_eq!(
+            calculate_address(sender, 0),
+            Address(hex!("cd234a471b72ba2f1ccf0a70fcaba648a5eecd8d"))
+        );
+        assert_eq!(
+            calculate_address(sender, 1),
+            Address(hex!("343c43a37d37dff08ae8c4a11544c718abb4fcf8"))
+        );
+    }
+
+    #[test]
+    fn extcodehash_tells_empty_code_from_missing_accounts() {
+        // EXTCODEHASH of 0x2222, which holds a balance but no code, then of 0x3333 which doesn't
+        // exist.
+        let code = hex!("6122223f" "6133333f");
+        let result = EvmBuilder::new()
+            .to([0x11; 20])
+            .account([0x11; 20], 0, 0.into(), code)
+            .account(Address::from(0x2222), 0, 1.into(), [])
+            .run()
+            .unwrap();
+
+        assert!(result.success, "{:?}", result.outcome);
+        assert_eq!(result.stack, [0.into(), KECCAK_EMPTY.to_word()]);
+    }
+
+    #[test]
+    fn nested_frames_stop_at_the_depth_limit() {
+        // a contract calling itself until the depth limit makes the innermost call fail.
+        let code = hex!("5f5f5f5f5f305af100");
+        let result = EvmBuilder::new()
+            .to([0x11; 20])
+            .account([0x11; 20], 0, 0.into(), code)
+            .run()
+            .unwrap();
+
+        assert!(result.success, "{:?}", result.outcome);
+    }
+
+    #[test]
+    fn runtime_container_is_not_deployable() {
+        let result = EvmBuilder::new()
+            .spec(SpecId::PragueEof)
+            .deploy(RUNTIME)
+            .run()
+            .unwrap();
+
+        assert_eq!(
+            result.outcome.halt_reason(),
+            Some(crate::HaltReason::InvalidEof)
+        );
+    }
+
+    /// Runs the container as the code of the account 0x11..11, next to the accounts given.
+    fn run_container(container: &[u8], accounts: &[(Address, Vec<u8>)]) -> EvmResult {
+        let mut builder = EvmBuilder::new()
+            .spec(SpecId::PragueEof)
+            .to([0x11; 20])
+            .account([0x11; 20], 0, 0.into(), container);
+        for (address, code) in accounts {
+            builder = builder.account(*address, 0, 0.into(), code);
+        }
+        builder.run().unwrap()
+    }
+
+    #[test]
+    fn dataloadn_reads_the_data_section() {
+        // DATALOADN(0), then return the word.
+        let data = [0x2a; 32];
+        let container = [
+            &hex!("ef000101000402000100090400200000800002" "d100005f5260205ff3")[..],
+            &data,
+        ]
+        .concat();
+
+        let result = run_container(&container, &[]);
+        assert!(result.success, "{:?}", result.outcome);
+        assert_eq!(result.ret, data);
+    }
+
+    #[test]
+    fn callf_returns_to_the_caller() {
+        // CALLF(1) then return the word it pushed, the section 1 pushing 0x2a.
+        let container = hex!(
+            "ef0001010008020002000900030400000000800002" "00010001"
+            "e300015f5260205ff3" "602ae4"
+        );
+
+        let result = run_container(&container, &[]);
+        assert!(result.success, "{:?}", result.outcome);
+        assert_eq!(result.ret[31], 0x2a);
+    }
+
+    #[test]
+    fn extcall_runs_legacy_code() {
+        // EXTCALL(0x22) then return its status, 0x22 storing 1 at slot 1.
+        let container =
+            hex!("ef000101000402000100" "0c" "0400000000800004" "5f5f5f6022f85f5260205ff3");
+        let callee = hex!("600160015500").to_vec();
+
+        let result = run_container(&container, &[(Address::from(0x22), callee)]);
+        assert!(result.success, "{:?}", result.outcome);
+        assert_eq!(result.ret, [0; 32]);
+        let storage = &result.state_diff[&Address::from(0x22)].storage;
+        assert_eq!(storage[&1.into()].after, 1.into());
+    }
+}
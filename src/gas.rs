@@ -0,0 +1,522 @@
+use primitive_types::U256;
+
+use crate::{errors::ExecutionError, opcode::OpCode, spec::SpecId};
+
+/// The cost of an access to an account or a slot already accessed by the transaction, since
+/// Berlin (EIP-2929).
+pub const WARM_STORAGE_READ_COST: u64 = 100;
+/// The cost of the first access to an account in a transaction, since Berlin.
+pub const COLD_ACCOUNT_ACCESS_COST: u64 = 2600;
+/// The cost of the first access to a slot in a transaction, since Berlin.
+pub const COLD_SLOAD_COST: u64 = 2100;
+/// The cost of a call sending value.
+pub const CALL_VALUE_COST: u64 = 9000;
+/// The gas given to the callee of a call sending value on top of the gas forwarded.
+pub const CALL_STIPEND: u64 = 2300;
+/// The cost of a call or a selfdestruct sending value to an account that doesn't exist.
+pub const NEW_ACCOUNT_COST: u64 = 25000;
+/// The cost per byte of the code deployed by a creation.
+pub const CODE_DEPOSIT_COST: u64 = 200;
+/// The cost per word of the data copied to the memory.
+pub const COPY_WORD_COST: u64 = 3;
+/// The cost per word of the data hashed by `SHA3` and the creations hashing their initcode.
+pub const KECCAK_WORD_COST: u64 = 6;
+/// The cost per topic of a log.
+pub const LOG_TOPIC_COST: u64 = 375;
+/// The cost per byte of the data of a log.
+pub const LOG_DATA_COST: u64 = 8;
+/// The cost per word of the initcode of a creation, since Shanghai (EIP-3860).
+pub const INITCODE_WORD_COST: u64 = 2;
+/// The cost of an `SSTORE` setting a slot from zero.
+pub const SSTORE_SET_COST: u64 = 20000;
+/// The refund of a `SELFDESTRUCT`, until London (EIP-3529).
+pub const SELFDESTRUCT_REFUND: i64 = 24000;
+/// The cost of a transaction, before its data.
+pub const TX_BASE_COST: u64 = 21000;
+/// The additional cost of a transaction deploying a contract, since Homestead.
+pub const TX_CREATE_COST: u64 = 32000;
+/// The cost of an authorization of a set-code transaction (EIP-7702).
+pub const AUTHORIZATION_COST: u64 = 25000;
+/// The part of [`AUTHORIZATION_COST`] refunded for an authority that already exists.
+pub const EXISTING_AUTHORITY_REFUND: i64 = 12500;
+
+/// Gas accounting of an execution frame.
+#[derive(Debug, Clone, Copy)]
+pub struct Gas {
+    /// The gas made available to the frame.
+    limit: u64,
+    /// The gas left to be spent.
+    remaining: u64,
+    /// The gas to refund at the end of the transaction, which is negative while the frame
+    /// undoes refunds given before it (EIP-2200).
+    refunded: i64,
+    /// Nothing is charged, as code predating gas metering expects.
+    unmetered: bool,
+}
+
+impl Gas {
+    pub fn new(limit: u64) -> Gas {
+        Gas {
+            limit,
+            remaining: limit,
+            refunded: 0,
+            unmetered: false,
+        }
+    }
+
+    /// Returns the gas of a frame charged for nothing, whose gas left stays at `u64::MAX`.
+    pub fn unmetered() -> Gas {
+        Gas {
+            unmetered: true,
+            ..Gas::new(u64::MAX)
+        }
+    }
+
+    pub fn is_unmetered(&self) -> bool {
+        self.unmetered
+    }
+
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
+
+    /// Returns the amount of gas spent so far.
+    pub fn spent(&self) -> u64 {
+        self.limit.saturating_sub(self.remaining)
+    }
+
+    /// Returns the gas to refund at the end of the transaction.
+    pub fn refunded(&self) -> i64 {
+        self.refunded
+    }
+
+    /// Consumes `cost` gas, failing with `OutOfGas` if not enough is left.
+    pub fn record_cost(&mut self, cost: u64) -> Result<(), ExecutionError> {
+        if self.unmetered {
+            return Ok(());
+        }
+        match self.remaining.checked_sub(cost) {
+            Some(remaining) => {
+                self.remaining = remaining;
+                Ok(())
+            }
+            None => Err(ExecutionError::OutOfGas),
+        }
+    }
+
+    /// Gives back `gas` not spent, such as the stipend of a call that its callee didn't use.
+    pub fn erase_cost(&mut self, gas: u64) {
+        if !self.unmetered {
+            self.remaining = self.remaining.saturating_add(gas);
+        }
+    }
+
+    /// Adds `refund` to the gas refunded, or removes it if it is negative.
+    pub fn record_refund(&mut self, refund: i64) {
+        if !self.unmetered {
+            self.refunded += refund;
+        }
+    }
+
+    /// Consumes all the remaining gas, as required on an exceptional halt.
+    pub fn spend_all(&mut self) {
+        if !self.unmetered {
+            self.remaining = 0;
+        }
+    }
+}
+
+/// Returns the number of 32-byte words holding `len` bytes.
+pub fn words(len: usize) -> u64 {
+    len.div_ceil(32) as u64
+}
+
+/// Returns the cost of a memory of `words` words, whose quadratic part makes large memories
+/// unaffordable.
+pub fn memory_cost(words: u64) -> u64 {
+    words
+        .saturating_mul(3)
+        .saturating_add(words.saturating_mul(words) / 512)
+}
+
+/// Returns the cost charged before running an instruction, as repriced by the forks. The
+/// costs depending on the operands, the memory expanded and the accesses are charged by the
+/// instructions, the accesses to an account or a slot costing [`WARM_STORAGE_READ_COST`] here
+/// since Berlin, and more when they are cold.
+pub fn static_cost(opcode: OpCode, spec: SpecId) -> u64 {
+    let berlin = spec.is_enabled_in(SpecId::Berlin);
+    let istanbul = spec.is_enabled_in(SpecId::Istanbul);
+    let tangerine_whistle = spec.is_enabled_in(SpecId::TangerineWhistle);
+    match opcode {
+        OpCode::Stop | OpCode::Return | OpCode::Revert | OpCode::Invalid => 0,
+        OpCode::Returncontract | OpCode::Sstore => 0,
+        OpCode::Jumpdest => 1,
+        OpCode::Address
+        | OpCode::Origin
+        | OpCode::Caller
+        | OpCode::Callvalue
+        | OpCode::Calldatasize
+        | OpCode::Codesize
+        | OpCode::Gasprice
+        | OpCode::Returndatasize
+        | OpCode::Coinbase
+        | OpCode::Timestamp
+        | OpCode::Number
+        | OpCode::Difficulty
+        | OpCode::Gaslimit
+        | OpCode::Chainid
+        | OpCode::Basefee
+        | OpCode::Pop
+        | OpCode::Pc
+        | OpCode::Msize
+        | OpCode::Gas
+        | OpCode::Push0
+        | OpCode::Rjump
+        | OpCode::Datasize => 2,
+        OpCode::Add
+        | OpCode::Sub
+        | OpCode::Lt
+        | OpCode::Gt
+        | OpCode::Slt
+        | OpCode::Sgt
+        | OpCode::Eq
+        | OpCode::Iszero
+        | OpCode::And
+        | OpCode::Or
+        | OpCode::Xor
+        | OpCode::Not
+        | OpCode::Byte
+        | OpCode::Shl
+        | OpCode::Shr
+        | OpCode::Sar
+        | OpCode::Calldataload
+        | OpCode::Calldatacopy
+        | OpCode::Codecopy
+        | OpCode::Returndatacopy
+        | OpCode::Mload
+        | OpCode::Mstore
+        | OpCode::Mstore8
+        | OpCode::Retf
+        | OpCode::Dataloadn
+        | OpCode::Datacopy
+        | OpCode::Dupn
+        | OpCode::Swapn
+        | OpCode::Exchange
+        | OpCode::Returndataload => 3,
+        opcode if opcode.is_push() || (OpCode::Dup1..=OpCode::Swap16).contains(&opcode) => 3,
+        OpCode::Rjumpi | OpCode::Rjumpv | OpCode::Dataload => 4,
+        OpCode::Mul
+        | OpCode::Div
+        | OpCode::Sdiv
+        | OpCode::Mod
+        | OpCode::Smod
+        | OpCode::Signextend
+        | OpCode::Selfbalance
+        | OpCode::Callf
+        | OpCode::Jumpf => 5,
+        OpCode::AddMod | OpCode::MulMod | OpCode::Jump => 8,
+        OpCode::Jumpi | OpCode::Exp => 10,
+        OpCode::Blockhash => 20,
+        OpCode::Sha3 => 30,
+        OpCode::Log0 | OpCode::Log1 | OpCode::Log2 | OpCode::Log3 | OpCode::Log4 => 375,
+        OpCode::Create | OpCode::Eofcreate => 32000,
+        OpCode::Extcall | OpCode::Extdelegatecall | OpCode::Extstaticcall => WARM_STORAGE_READ_COST,
+        OpCode::Balance if berlin => WARM_STORAGE_READ_COST,
+        OpCode::Balance if istanbul => 700,
+        OpCode::Balance if tangerine_whistle => 400,
+        OpCode::Balance => 20,
+        OpCode::Extcodesize | OpCode::Extcodecopy if berlin => WARM_STORAGE_READ_COST,
+        OpCode::Extcodesize | OpCode::Extcodecopy if tangerine_whistle => 700,
+        OpCode::Extcodesize | OpCode::Extcodecopy => 20,
+        OpCode::Extcodehash if berlin => WARM_STORAGE_READ_COST,
+        OpCode::Extcodehash if istanbul => 700,
+        OpCode::Extcodehash => 400,
+        OpCode::Sload if berlin => WARM_STORAGE_READ_COST,
+        OpCode::Sload if istanbul => 800,
+        OpCode::Sload if tangerine_whistle => 200,
+        OpCode::Sload => 50,
+        OpCode::Call | OpCode::Delegatecall | OpCode::Staticcall if berlin => {
+            WARM_STORAGE_READ_COST
+        }
+        OpCode::Call | OpCode::Delegatecall | OpCode::Staticcall if tangerine_whistle => 700,
+        OpCode::Call | OpCode::Delegatecall | OpCode::Staticcall => 40,
+        OpCode::Selfdestruct if tangerine_whistle => 5000,
+        OpCode::Selfdestruct => 0,
+        _ => unreachable!("every instruction is priced"),
+    }
+}
+
+/// Returns the cost of an `SSTORE` setting a slot from `current` to `new`, `original` being its
+/// value when the transaction started, and the gas it refunds, negative when it undoes an
+/// earlier refund. Since Istanbul, stores are priced by the original value (EIP-2200), and
+/// since London clearing a slot refunds less (EIP-3529). The cold access is charged apart.
+pub fn sstore_cost(original: U256, current: U256, new: U256, spec: SpecId) -> (u64, i64) {
+    let reset_cost = if spec.is_enabled_in(SpecId::Berlin) {
+        5000 - COLD_SLOAD_COST
+    } else {
+        5000
+    };
+    let clears_refund = if spec.is_enabled_in(SpecId::London) {
+        4800
+    } else {
+        15000
+    };
+    if !spec.is_enabled_in(SpecId::Istanbul) {
+        return if current.is_zero() && !new.is_zero() {
+            (SSTORE_SET_COST, 0)
+        } else if !current.is_zero() && new.is_zero() {
+            (reset_cost, clears_refund)
+        } else {
+            (reset_cost, 0)
+        };
+    }
+
+    let sload_cost = if spec.is_enabled_in(SpecId::Berlin) {
+        WARM_STORAGE_READ_COST
+    } else {
+        800
+    };
+    if current == new {
+        return (sload_cost, 0);
+    }
+    if original == current {
+        return if original.is_zero() {
+            (SSTORE_SET_COST, 0)
+        } else if new.is_zero() {
+            (reset_cost, clears_refund)
+        } else {
+            (reset_cost, 0)
+        };
+    }
+
+    // the slot was already changed by the transaction, which paid for it.
+    let mut refund = 0;
+    if !original.is_zero() {
+        if current.is_zero() {
+            refund -= clears_refund;
+        } else if new.is_zero() {
+            refund += clears_refund;
+        }
+    }
+    if original == new {
+        refund += if original.is_zero() {
+            (SSTORE_SET_COST - sload_cost) as i64
+        } else {
+            (reset_cost - sload_cost) as i64
+        };
+    }
+    (sload_cost, refund)
+}
+
+/// Returns the gas a transaction carrying `data` costs before running: a base cost and a cost
+/// per byte of data, plus a deployment and per-word cost when `create` deploys the data as
+/// initcode, and a cost per authorization.
+pub fn intrinsic_gas(data: &[u8], create: bool, authorizations: usize, spec: SpecId) -> u64 {
+    let zeros = data.iter().filter(|byte| **byte == 0).count() as u64;
+    let non_zeros = data.len() as u64 - zeros;
+    // non-zero bytes were repriced by EIP-2028.
+    let non_zero_cost = if spec.is_enabled_in(SpecId::Istanbul) {
+        16
+    } else {
+        68
+    };
+    let mut gas = TX_BASE_COST + zeros * 4 + non_zeros * non_zero_cost;
+    if create && spec.is_enabled_in(SpecId::Homestead) {
+        gas += TX_CREATE_COST;
+    }
+    if create && spec.is_enabled_in(SpecId::Shanghai) {
+        gas += INITCODE_WORD_COST * words(data.len());
+    }
+    gas + authorizations as u64 * AUTHORIZATION_COST
+}
+
+/// Returns the least gas a transaction carrying `data` uses since Prague, 10 per token of its
+/// data, zero bytes counting for one token and the others for four (EIP-7623).
+pub fn floor_gas(data: &[u8]) -> u64 {
+    let zeros = data.iter().filter(|byte| **byte == 0).count() as u64;
+    let tokens = zeros + (data.len() as u64 - zeros) * 4;
+    TX_BASE_COST + tokens * 10
+}
+
+/// Returns the largest share of the gas spent by a transaction that it gets refunded, half of
+/// it until London and a fifth since (EIP-3529).
+pub fn max_refund(spent: u64, spec: SpecId) -> u64 {
+    if spec.is_enabled_in(SpecId::London) {
+        spent / 5
+    } else {
+        spent / 2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+    use crate::{BuilderError, EvmBuilder, EvmResult, HaltReason, Outcome, TransactionError};
+
+    const CONTRACT: [u8; 20] = [0x11; 20];
+
+    fn run(code: &[u8], gas_limit: u64) -> Result<EvmResult, BuilderError> {
+        EvmBuilder::new()
+            .to(CONTRACT)
+            .account(CONTRACT, 0, 0.into(), code)
+            .storage(CONTRACT, 1.into(), 1.into())
+            .gas_limit(gas_limit)
+            .run()
+    }
+
+    #[test]
+    fn every_instruction_is_priced() {
+        for spec in [SpecId::Frontier, SpecId::Berlin, SpecId::PragueEof] {
+            for byte in 0..=u8::MAX {
+                if let Some(opcode) = OpCode::new(byte) {
+                    static_cost(opcode, spec);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sstore_prices_eip_2200_examples() {
+        // `(original, current, new, cost, refund)` at Istanbul, from the examples of EIP-2200
+        // where a slot is stored to twice.
+        let slot = |original: u64, first: u64, second: u64| {
+            let spec = SpecId::Istanbul;
+            let (first_cost, first_refund) =
+                sstore_cost(original.into(), original.into(), first.into(), spec);
+            let (second_cost, second_refund) =
+                sstore_cost(original.into(), first.into(), second.into(), spec);
+            (first_cost + second_cost, first_refund + second_refund)
+        };
+
+        assert_eq!(slot(0, 0, 0), (1600, 0));
+        assert_eq!(slot(0, 0, 1), (20800, 0));
+        assert_eq!(slot(0, 1, 0), (20800, 19200));
+        assert_eq!(slot(0, 1, 2), (20800, 0));
+        assert_eq!(slot(1, 0, 0), (5800, 15000));
+        assert_eq!(slot(1, 0, 1), (5800, 4200));
+        assert_eq!(slot(1, 2, 1), (5800, 4200));
+        assert_eq!(slot(1, 2, 0), (5800, 15000));
+    }
+
+    #[test]
+    fn clearing_a_slot_refunds_less_since_london() {
+        let clear = |spec| sstore_cost(1.into(), 1.into(), 0.into(), spec);
+
+        assert_eq!(clear(SpecId::Petersburg), (5000, 15000));
+        assert_eq!(clear(SpecId::Berlin), (2900, 15000));
+        assert_eq!(clear(SpecId::London), (2900, 4800));
+    }
+
+    #[test]
+    fn intrinsic_gas_prices_the_data() {
+        let data = [0, 1, 0, 2];
+
+        assert_eq!(intrinsic_gas(&data, false, 0, SpecId::Byzantium), 21144);
+        assert_eq!(intrinsic_gas(&data, false, 0, SpecId::Istanbul), 21040);
+        assert_eq!(intrinsic_gas(&data, true, 0, SpecId::Shanghai), 53042);
+        assert_eq!(intrinsic_gas(&data, false, 2, SpecId::Prague), 71040);
+        assert_eq!(floor_gas(&data), 21100);
+    }
+
+    #[test]
+    fn transaction_pays_intrinsic_and_instruction_gas() {
+        // PUSH1 1 PUSH0 SSTORE, setting the cold slot 0.
+        let result = run(&hex!("60015f55"), 100_000).unwrap();
+
+        assert!(result.success, "{:?}", result.outcome);
+        assert_eq!(result.gas_used, 21000 + 3 + 2 + 22100);
+        assert_eq!(result.gas_refunded, 0);
+    }
+
+    #[test]
+    fn clearing_a_slot_is_refunded() {
+        // PUSH0 PUSH1 1 SSTORE, clearing the cold slot 1.
+        let result = run(&hex!("5f600155"), 100_000).unwrap();
+
+        assert!(result.success, "{:?}", result.outcome);
+        assert_eq!(result.gas_refunded, 4800);
+        assert_eq!(result.gas_used, 21000 + 2 + 3 + 5000 - 4800);
+    }
+
+    #[test]
+    fn refunds_are_capped_by_the_gas_used() {
+        // PUSH0 PUSH1 1 SSTORE at Berlin, whose refund of 15000 is capped by half the gas used.
+        let result = EvmBuilder::new()
+            .to(CONTRACT)
+            .account(CONTRACT, 0, 0.into(), hex!("5f600155"))
+            .storage(CONTRACT, 1.into(), 1.into())
+            .spec(SpecId::Berlin)
+            .run()
+            .unwrap();
+
+        let spent = 21000 + 2 + 3 + 5000;
+        assert_eq!(result.gas_refunded, spent / 2);
+        assert_eq!(result.gas_used, spent - spent / 2);
+    }
+
+    #[test]
+    fn gas_pushes_the_gas_left() {
+        let result = run(&hex!("5a"), 100_000).unwrap();
+
+        assert_eq!(result.stack, [(100_000 - 21000 - 2).into()]);
+    }
+
+    #[test]
+    fn running_out_of_gas_halts_and_consumes_all_the_gas() {
+        // PUSH0 PUSH0, given the gas of one.
+        let result = run(&hex!("5f5f"), 21002).unwrap();
+
+        assert!(matches!(
+            result.outcome,
+            Outcome::Halt {
+                reason: HaltReason::OutOfGas
+            }
+        ));
+        assert_eq!(result.gas_used, 21002);
+    }
+
+    #[test]
+    fn gas_limit_below_the_intrinsic_gas_is_invalid() {
+        let result = run(&[], 20999);
+
+        assert!(matches!(
+            result,
+            Err(BuilderError::Transaction(
+                TransactionError::IntrinsicGasTooLow {
+                    gas_limit: 20999,
+                    intrinsic_gas: 21000
+                }
+            ))
+        ));
+    }
+
+    #[test]
+    fn memory_and_cold_accesses_are_charged() {
+        // PUSH0 PUSH0 MSTORE, expanding the memory by a word, then BALANCE of 0x2222 twice.
+        let code = hex!("5f5f52" "61222231" "61222231");
+        let result = run(&code, 100_000).unwrap();
+
+        assert!(result.success, "{:?}", result.outcome);
+        let memory = 2 + 2 + 3 + 3;
+        let balances = 3 + COLD_ACCOUNT_ACCESS_COST + 3 + WARM_STORAGE_READ_COST;
+        assert_eq!(result.gas_used, 21000 + memory + balances);
+    }
+
+    #[test]
+    fn calldata_floor_applies_since_prague() {
+        let calldata = [1; 100];
+        let run = |spec| {
+            EvmBuilder::new()
+                .to(CONTRACT)
+                .calldata(calldata)
+                .spec(spec)
+                .run()
+                .unwrap()
+        };
+
+        assert_eq!(run(SpecId::Cancun).gas_used, 21000 + 100 * 16);
+        assert_eq!(run(SpecId::Prague).gas_used, 21000 + 100 * 4 * 10);
+    }
+}
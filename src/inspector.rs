@@ -0,0 +1,267 @@
+use std::{cell::RefCell, rc::Rc};
+
+use primitive_types::U256;
+
+use crate::{log::Log, outcome::Outcome, primitives::Address};
+
+/// The state of a frame around the execution of an instruction.
+#[derive(Debug, Clone, Copy)]
+pub struct Step<'a> {
+    pub pc: usize,
+    pub opcode: u8,
+    /// The stack, its top being the last item.
+    pub stack: &'a [U256],
+    pub memory: &'a [u8],
+    pub gas_remaining: u64,
+}
+
+/// The instructions entering a new call frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallKind {
+    /// The call of the transaction, entering the first frame.
+    Transaction,
+    Call,
+    DelegateCall,
+    StaticCall,
+    ExtCall,
+    ExtDelegateCall,
+    ExtStaticCall,
+}
+
+/// The inputs of a call frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallInputs {
+    pub kind: CallKind,
+    /// The account whose code runs.
+    pub target: Address,
+    /// The account whose storage and balance the code runs with, the target itself except for
+    /// delegate calls.
+    pub context: Address,
+    pub caller: Address,
+    pub value: U256,
+    pub input: Vec<u8>,
+    pub gas_limit: u64,
+}
+
+/// The instructions deploying a contract.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CreateKind {
+    /// The deployment of the transaction.
+    Transaction,
+    Create,
+    EofCreate,
+}
+
+/// The inputs of a create frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreateInputs {
+    pub kind: CreateKind,
+    pub caller: Address,
+    /// The address the contract is deployed at.
+    pub address: Address,
+    pub value: U256,
+    pub init_code: Vec<u8>,
+}
+
+/// Observes an execution, the interpreter calling its hooks as it runs.
+///
+/// All the hooks do nothing by default.
+pub trait Inspector {
+    /// Called before each instruction.
+    fn step(&mut self, _step: &Step) {}
+
+    /// Called after each instruction, the pc being the one of the instruction.
+    fn step_end(&mut self, _step: &Step) {}
+
+    /// Called when entering a call frame. Returning an outcome skips the call, which ends
+    /// with that outcome instead.
+    fn call(&mut self, _inputs: &CallInputs) -> Option<Outcome> {
+        None
+    }
+
+    /// Called when a call frame ends, returning its outcome, possibly overridden. The changes
+    /// of the frame are kept only if the returned outcome is a success.
+    fn call_end(&mut self, _inputs: &CallInputs, outcome: Outcome) -> Outcome {
+        outcome
+    }
+
+    /// Called when entering a create frame. Returning an outcome skips the creation, which
+    /// ends with that outcome instead and deploys nothing.
+    fn create(&mut self, _inputs: &CreateInputs) -> Option<Outcome> {
+        None
+    }
+
+    /// Called when a create frame ends, returning its outcome, possibly overridden, like
+    /// [`Inspector::call_end`].
+    fn create_end(&mut self, _inputs: &CreateInputs, outcome: Outcome) -> Outcome {
+        outcome
+    }
+
+    /// Called for each log emitted.
+    fn log(&mut self, _log: &Log) {}
+
+    /// Called when `address` self-destructs, sending its `balance` to `target`.
+    fn selfdestruct(&mut self, _address: Address, _target: Address, _balance: U256) {}
+}
+
+/// An inspector shared by the frames of an execution.
+pub type SharedInspector = Rc<RefCell<dyn Inspector>>;
+
+#[cfg(test)]
+mod tests {
+    use primitive_types::U256;
+
+    use super::*;
+    use crate::{outcome::SuccessReason, EvmBuilder, EvmResult};
+
+    const CALLEE: u64 = 0x2222;
+
+    /// Calls the callee with no value nor data, leaving the result of the call on the stack.
+    const CALLER_CODE: [u8; 15] = [
+        0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x61, 0x22, 0x22, 0x5a, 0xf1,
+    ];
+
+    /// Replaces the outcome of the calls entering a new frame.
+    struct Override(Outcome);
+
+    impl Inspector for Override {
+        fn call_end(&mut self, inputs: &CallInputs, outcome: Outcome) -> Outcome {
+            match inputs.kind {
+                CallKind::Transaction => outcome,
+                _ => self.0.clone(),
+            }
+        }
+    }
+
+    fn run_overridden(callee_code: &[u8], outcome: Outcome) -> EvmResult {
+        EvmBuilder::new()
+            .code(CALLER_CODE)
+            .account(CALLEE, 0, U256::zero(), callee_code)
+            .inspector(Rc::new(RefCell::new(Override(outcome))))
+            .run()
+            .unwrap()
+    }
+
+    fn callee_slot(result: &EvmResult) -> Option<U256> {
+        let callee = Address::from(0x2222);
+        let account = result.state_diff.get(&callee)?;
+        Some(account.storage.get(&U256::zero())?.after)
+    }
+
+    #[test]
+    fn overridden_revert_discards_the_changes_of_the_call() {
+        // PUSH1 1 PUSH1 0 SSTORE STOP
+        let result = run_overridden(
+            &[0x60, 0x01, 0x60, 0x00, 0x55, 0x00],
+            Outcome::Revert { output: vec![] },
+        );
+
+        assert!(result.success);
+        assert_eq!(result.stack, [U256::zero()]);
+        assert_eq!(callee_slot(&result), None);
+    }
+
+    #[test]
+    fn overridden_success_keeps_the_changes_of_the_call() {
+        // PUSH1 1 PUSH1 0 SSTORE PUSH1 0 PUSH1 0 REVERT
+        let result = run_overridden(
+            &[0x60, 0x01, 0x60, 0x00, 0x55, 0x60, 0x00, 0x60, 0x00, 0xfd],
+            Outcome::Success {
+                reason: SuccessReason::Stop,
+                output: vec![],
+            },
+        );
+
+        assert!(result.success);
+        assert_eq!(result.stack, [U256::one()]);
+        assert_eq!(callee_slot(&result), Some(U256::one()));
+    }
+
+    /// Records the hooks called.
+    #[derive(Default)]
+    struct Recorder {
+        steps: Vec<(usize, u8)>,
+        gas_remaining: Vec<u64>,
+        calls: Vec<(CallKind, Address)>,
+        call_ends: usize,
+        logs: Vec<Log>,
+        skip_calls: bool,
+    }
+
+    impl Inspector for Recorder {
+        fn step(&mut self, step: &Step) {
+            self.steps.push((step.pc, step.opcode));
+            self.gas_remaining.push(step.gas_remaining);
+        }
+
+        fn call(&mut self, inputs: &CallInputs) -> Option<Outcome> {
+            self.calls.push((inputs.kind, inputs.target));
+            let skip = self.skip_calls && inputs.kind != CallKind::Transaction;
+            skip.then(|| Outcome::Revert { output: vec![] })
+        }
+
+        fn call_end(&mut self, _inputs: &CallInputs, outcome: Outcome) -> Outcome {
+            self.call_ends += 1;
+            outcome
+        }
+
+        fn log(&mut self, log: &Log) {
+            self.logs.push(log.clone());
+        }
+    }
+
+    fn run_recorded(skip_calls: bool) -> (EvmResult, Recorder) {
+        let recorder = Rc::new(RefCell::new(Recorder {
+            skip_calls,
+            ..Recorder::default()
+        }));
+        // LOG0 of no data, then STOP.
+        let result = EvmBuilder::new()
+            .code(CALLER_CODE)
+            .account(CALLEE, 0, U256::zero(), [0x5f, 0x5f, 0xa0, 0x00])
+            .inspector(recorder.clone())
+            .run()
+            .unwrap();
+        let recorder = Rc::try_unwrap(recorder).ok().unwrap().into_inner();
+        (result, recorder)
+    }
+
+    #[test]
+    fn hooks_follow_the_execution() {
+        let (result, recorder) = run_recorded(false);
+
+        assert_eq!(result.stack, [U256::one()]);
+        assert_eq!(
+            recorder.calls,
+            [
+                (CallKind::Transaction, Address::ZERO),
+                (CallKind::Call, Address::from(0x2222)),
+            ]
+        );
+        assert_eq!(recorder.call_ends, 2);
+        // the steps of the callee come between the CALL and the end of the caller.
+        let opcodes: Vec<u8> = recorder.steps.iter().map(|(_, opcode)| *opcode).collect();
+        assert_eq!(
+            opcodes,
+            [0x60, 0x60, 0x60, 0x60, 0x60, 0x61, 0x5a, 0xf1, 0x5f, 0x5f, 0xa0, 0x00]
+        );
+        assert_eq!(recorder.steps[7].0, 14);
+        // the transaction starts with its gas limit less its intrinsic gas, and PUSH1 costs 3.
+        assert_eq!(recorder.gas_remaining[0], u64::MAX - 21000);
+        assert_eq!(recorder.gas_remaining[1], u64::MAX - 21003);
+        assert_eq!(
+            recorder.logs,
+            [Log::new(Address::from(0x2222), vec![], vec![])]
+        );
+    }
+
+    #[test]
+    fn outcome_returned_by_call_skips_the_frame() {
+        let (result, recorder) = run_recorded(true);
+
+        assert_eq!(result.stack, [U256::zero()]);
+        assert_eq!(recorder.steps.len(), 8);
+        assert!(recorder.logs.is_empty());
+        assert_eq!(recorder.call_ends, 2);
+    }
+}
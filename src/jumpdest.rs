@@ -3,15 +3,17 @@ use bit_vec::BitVec;
 use primitive_types::U256;
 
 pub fn is_valid_jumpdest(position: U256, code: &[u8]) -> Result<bool, ExecutionError> {
-    if let Some(opcode) = OpCode::new(code[position.as_usize()]) {
-        if opcode != OpCode::Jumpdest {
-            return Err(ExecutionError::InvalidJumpDestination);
-        }
-        let is_valid = is_code(position.as_usize(), code)?;
-        Ok(is_valid)
-    } else {
-        Err(ExecutionError::InvalidOpcode)
+    // destinations past the end of the code are invalid.
+    let Some((position, &byte)) = usize::try_from(position)
+        .ok()
+        .and_then(|position| Some((position, code.get(position)?)))
+    else {
+        return Err(ExecutionError::InvalidJumpDestination);
+    };
+    if OpCode::new(byte) != Some(OpCode::Jumpdest) {
+        return Err(ExecutionError::InvalidJumpDestination);
     }
+    is_code(position, code)
 }
 
 fn is_code(position: usize, code: &[u8]) -> Result<bool, ExecutionError> {
@@ -29,19 +31,16 @@ fn code_bitmap(code: &[u8]) -> Result<BitVec, ExecutionError> {
     let mut bitvec = BitVec::from_elem(code.len() + 32, true);
     let mut pc = 0;
     while pc < code.len() {
-        if let Some(opcode) = OpCode::new(code[pc]) {
-            if opcode.is_push() {
-                let push_data_size = opcode.push_data_size();
-                let start = pc;
-                for i in start..=(start + push_data_size) {
-                    bitvec.set(i, false);
-                }
-                pc += push_data_size;
+        // undefined opcodes are single bytes, which only halt when executed.
+        if let Some(opcode) = OpCode::new(code[pc]).filter(|opcode| opcode.is_push()) {
+            let push_data_size = opcode.push_data_size();
+            let start = pc;
+            for i in start..=(start + push_data_size) {
+                bitvec.set(i, false);
             }
-            pc += 1;
-        } else {
-            return Err(ExecutionError::InvalidOpcode);
+            pc += push_data_size;
         }
+        pc += 1;
     }
     Ok(bitvec)
 }
@@ -1,74 +1,517 @@
+mod authorization;
 mod block;
+mod builder;
+mod db;
+mod eof;
 mod errors;
 mod evm;
+mod gas;
+mod inspector;
 mod jumpdest;
 mod log;
 mod memory;
 mod opcode;
+mod outcome;
+mod overrides;
+mod precompile;
+mod primitives;
+mod rlp;
+mod serde_hex;
+mod session;
+mod spec;
 mod state;
 mod storage;
+mod system;
 mod tx;
 mod utils;
 
 use std::collections::HashMap;
 
-use crate::tx::TxData;
-use block::BlockData;
-use evm::Evm;
+use authorization::{apply_authorizations, load_code};
+use errors::ExecutionError;
+use evm::{calculate_address, checkpoint, inspect_call, inspect_create, Evm, ExecutionResult};
+use gas::{floor_gas, intrinsic_gas, max_refund, Gas, EXISTING_AUTHORITY_REFUND};
+use outcome::state_diff;
 use primitive_types::U256;
-use state::State;
-use storage::Storage;
+use serde::{Deserialize, Serialize};
+use serde_hex::hex_bytes;
 
+pub use authorization::Authorization;
+pub use block::BlockEnv;
+pub use builder::{BuilderError, EvmBuilder};
+pub use db::{code_hash, AccountInfo, Database, DatabaseError, EmptyDB, InMemoryDB, KECCAK_EMPTY};
+pub use eof::{validate_eof, ContainerKind, Eof, EofError, TypeSection};
+pub use errors::{HaltReason, TransactionError};
+pub use inspector::{
+    CallInputs, CallKind, CreateInputs, CreateKind, Inspector, SharedInspector, Step,
+};
 pub use log::Log;
+pub use outcome::{AccountDiff, Change, Outcome, StateDiff, SuccessReason};
+pub use overrides::{AccountOverride, OverrideError, StateOverride};
+pub use precompile::{
+    p256_verify, Precompile, PrecompileError, PrecompileOutput, PrecompileResult, Precompiles,
+    P256_VERIFY_ADDRESS,
+};
+pub use primitives::{Address, B256};
+pub use session::{Session, SnapshotId};
+pub use spec::SpecId;
+pub use state::{AddressData, State};
+pub use storage::Storage;
+pub use system::{
+    SystemCallError, SystemCallStatus, SystemCalls, SystemCallsReport, BEACON_ROOTS_ADDRESS,
+    BEACON_ROOTS_CODE, HISTORY_STORAGE_ADDRESS, HISTORY_STORAGE_CODE, SYSTEM_ADDRESS,
+};
+pub use tx::TxEnv;
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EvmResult {
     pub stack: Vec<U256>,
     pub success: bool,
     pub logs: Vec<Log>,
+    #[serde(with = "hex_bytes")]
     pub ret: Vec<u8>,
+    pub outcome: Outcome,
+    /// The address of the contract deployed by a successful deployment.
+    pub created_address: Option<Address>,
+    /// The accounts changed by a successful execution.
+    pub state_diff: StateDiff,
+    /// The gas used by the transaction, its intrinsic gas included and its refund deducted.
+    pub gas_used: u64,
+    /// The gas refunded to the transaction, already deducted from `gas_used`.
+    pub gas_refunded: u64,
 }
 
+/// Runs the code with the positional transaction, block and state data, through an
+/// [`EvmBuilder`] with the default spec.
+///
+/// `evm` predates balance checks, so it mints the value a sender lacks, be it the caller of
+/// the transaction or a contract sending value with `CALL` or `CREATE`, leaving the pre-state
+/// as given (see [`EvmBuilder::mint_missing_value`]). It also predates gas metering, so the
+/// code runs unmetered (see [`EvmBuilder::unmetered`]).
 pub fn evm(
     _code: impl AsRef<[u8]>,
     _tx_data: Vec<Vec<u8>>,
     _block_data: Vec<Vec<u8>>,
     _state_data: HashMap<Vec<u8>, (usize, Vec<u8>, Vec<u8>)>,
-) -> EvmResult {
-    let stack: Vec<U256> = Vec::new();
+) -> Result<EvmResult, BuilderError> {
+    let tx_env = TxEnv::new(_tx_data)?;
+    let block_env = BlockEnv::new(_block_data)?;
+    let mut builder = EvmBuilder::new()
+        .code(_code)
+        .to(tx_env.to)
+        .caller(tx_env.caller)
+        .origin(tx_env.origin)
+        .gas_price(tx_env.gas_price)
+        .value(tx_env.value)
+        .calldata(tx_env.data)
+        .gas_limit(tx_env.gas_limit)
+        .basefee(block_env.basefee)
+        .coinbase(block_env.coinbase)
+        .timestamp(block_env.timestamp)
+        .number(block_env.number)
+        .difficulty(block_env.difficulty)
+        .prevrandao(block_env.prevrandao)
+        .block_gas_limit(block_env.gas_limit)
+        .chain_id(block_env.chain_id)
+        .mint_missing_value(true)
+        .unmetered(true);
 
-    let code = _code.as_ref();
+    for (address, (nonce, balance, code)) in _state_data {
+        let address = Address::from_word(builder::word("address", &address)?);
+        let balance = builder::word("balance", &balance)?;
+        builder = builder.account(address, nonce, balance, code);
+    }
+    builder.run()
+}
+
+/// Runs the code of a transaction on the state and storage, or deploys it when `create` is
+/// set, committing its changes to them when it succeeds. Without `code`, the code of the `to`
+/// account runs, as delegated by the authorizations.
+///
+/// The nonce of the caller is incremented and the authorization list applied even if the
+/// transaction fails, while the value sent to the `to` account is returned. A caller holding
+/// less than the value, unless the state mints the missing value, or whose nonce is at its
+/// maximum makes the transaction invalid, as does a value overflowing the balance of the `to`
+/// account, or a gas limit below the intrinsic gas of the transaction.
+///
+/// The frame of the transaction gets the gas limit left once the intrinsic gas is paid, and
+/// the gas refunded is capped by a share of the gas used. `unmetered` transactions are charged
+/// nothing and use no gas.
+#[allow(clippy::too_many_arguments)]
+fn run(
+    code: Option<Vec<u8>>,
+    authorization_list: &[Authorization],
+    mut tx_env: TxEnv,
+    block_env: BlockEnv,
+    state: &mut State,
+    storage: &mut Storage,
+    spec: SpecId,
+    precompiles: Precompiles,
+    create: bool,
+    inspector: Option<SharedInspector>,
+    unmetered: bool,
+) -> Result<EvmResult, TransactionError> {
+    // deployments pay for their initcode as they pay for the calldata of calls.
+    let data = match (&code, create) {
+        (Some(code), true) => code.as_slice(),
+        _ => &tx_env.data,
+    };
+    let authorizations = if spec.is_enabled_in(SpecId::Prague) {
+        authorization_list.len()
+    } else {
+        0
+    };
+    let intrinsic_gas = intrinsic_gas(data, create, authorizations, spec);
+    // the calldata floor only prices the data (EIP-7623).
+    let floor_gas = if spec.is_enabled_in(SpecId::Prague) {
+        floor_gas(data)
+    } else {
+        0
+    };
+    if !unmetered && tx_env.gas_limit < intrinsic_gas.max(floor_gas) {
+        return Err(TransactionError::IntrinsicGasTooLow {
+            gas_limit: tx_env.gas_limit,
+            intrinsic_gas: intrinsic_gas.max(floor_gas),
+        });
+    }
+
+    if !state.mint_missing_value && state.get_balance(tx_env.caller)? < tx_env.value {
+        return Err(TransactionError::InsufficientBalance);
+    }
+
+    // the changes of the transaction are journaled from here, the ones of its frame from
+    // `frame_checkpoint` to be reverted if it fails.
+    let (tx_checkpoint, tx_storage_checkpoint) = checkpoint(state, storage);
+    state.start_transaction();
+    storage.start_transaction();
+    let nonce = state.get_nonce(tx_env.caller)?;
+    if !state.increment_nonce(tx_env.caller)? {
+        state.revert(tx_checkpoint);
+        storage.revert(tx_storage_checkpoint);
+        return Err(TransactionError::NonceOverflow);
+    }
+
+    // set-code transactions only exist since Prague, and delegate the authorities before the
+    // code of `to` is loaded.
+    let prepared = (|| {
+        let mut existing_authorities = 0;
+        if spec.is_enabled_in(SpecId::Prague) {
+            existing_authorities =
+                apply_authorizations(state, authorization_list, block_env.chain_id.into())?;
+        }
+        let code = match code {
+            Some(code) => code,
+            None => load_code(state, tx_env.to, spec)?,
+        };
+        Ok::<_, DatabaseError>((code, existing_authorities))
+    })();
+    let (code, existing_authorities) = match prepared {
+        Ok((code, existing_authorities)) => (code.into_boxed_slice(), existing_authorities),
+        Err(error) => {
+            state.revert(tx_checkpoint);
+            storage.revert(tx_storage_checkpoint);
+            return Err(error.into());
+        }
+    };
+
+    // deployments run the initcode at the address of the new contract.
+    let created_address = create.then(|| calculate_address(tx_env.caller, nonce));
+    if let Some(address) = created_address {
+        tx_env.to = address;
+    }
+    // the accounts of the transaction start warm, as do the precompiles since Berlin (EIP-2929)
+    // and the coinbase since Shanghai (EIP-3651).
+    state.access(tx_env.caller);
+    state.access(tx_env.to);
+    if spec.is_enabled_in(SpecId::Berlin) {
+        for address in precompiles.addresses() {
+            state.access(address);
+        }
+    }
+    if spec.is_enabled_in(SpecId::Shanghai) {
+        state.access(block_env.coinbase);
+    }
+    let gas = if unmetered {
+        Gas::unmetered()
+    } else {
+        Gas::new(tx_env.gas_limit - intrinsic_gas)
+    };
+    let value = tx_env.value;
+    let inputs_env = tx_env.clone();
+    let init_code = code.clone();
+
+    let frame_checkpoint = checkpoint(state, storage);
+    if !state.transfer(tx_env.caller, tx_env.to, value)? {
+        state.revert(tx_checkpoint);
+        storage.revert(tx_storage_checkpoint);
+        return Err(TransactionError::BalanceOverflow);
+    }
     let mut evm = Evm::new(
-        Box::from(code),
-        stack,
-        TxData::new(_tx_data),
-        BlockData::new(_block_data),
-        State::new(_state_data),
-        Storage::new(),
+        code,
+        vec![],
+        tx_env,
+        block_env,
+        state.take(),
+        storage.take(),
         vec![],
         vec![],
         vec![],
+        spec,
+        precompiles,
         false,
     );
+    evm.gas = gas;
+    evm.inspector = inspector.clone();
+    evm.initcode = create;
 
-    let result = evm.execute();
-    match result {
-        evm::ExecutionResult::Success => EvmResult {
-            stack: evm.stack(),
-            success: true,
-            logs: evm.logs(),
-            ret: evm.return_data(),
-        },
-        evm::ExecutionResult::Halt => EvmResult {
-            stack: evm.stack(),
-            success: true,
-            logs: evm.logs(),
-            ret: evm.return_data(),
-        },
-        evm::ExecutionResult::Revert => EvmResult {
-            stack: evm.stack(),
-            success: false,
-            logs: evm.logs(),
-            ret: evm.return_data(),
+    let execute = || {
+        if let Some(address) = created_address {
+            if evm.state.collides(address)? {
+                return Ok((evm.exceptional_halt(HaltReason::CreateCollision), vec![]));
+            }
+        }
+
+        let result = evm.execute()?;
+        let output = evm.return_data();
+        if let (Some(address), ExecutionResult::Success(_)) = (created_address, &result) {
+            evm.state.set_code(address, output.clone())?;
+        }
+        Ok((result, output))
+    };
+    let executed = match created_address {
+        Some(address) => {
+            let inputs = || CreateInputs {
+                kind: CreateKind::Transaction,
+                caller: inputs_env.caller,
+                address,
+                value,
+                init_code: init_code.to_vec(),
+            };
+            inspect_create(&inspector, inputs, execute)
+        }
+        None => {
+            let inputs = || CallInputs {
+                kind: CallKind::Transaction,
+                target: inputs_env.to,
+                context: inputs_env.to,
+                caller: inputs_env.caller,
+                value,
+                input: inputs_env.data.clone(),
+                gas_limit: gas.remaining(),
+            };
+            inspect_call(&inspector, inputs, execute)
+        }
+    };
+    *state = evm.state.take();
+    *storage = evm.storage.take();
+    let (result, output) = match executed {
+        Ok(executed) => executed,
+        // failed reads of the database abort the transaction, undoing all its changes.
+        Err(ExecutionError::Database(error)) => {
+            state.revert(tx_checkpoint);
+            storage.revert(tx_storage_checkpoint);
+            return Err(error.into());
+        }
+        // other errors end the frame as they would end its code.
+        Err(error) => match error.halt_reason() {
+            Some(reason) => (evm.exceptional_halt(reason), vec![]),
+            None => (ExecutionResult::Success(SuccessReason::Stop), vec![]),
         },
+    };
+    let outcome = Outcome::new(result, output);
+    let success = outcome.is_success();
+    let state_diff = if success {
+        state_diff(state, storage, tx_checkpoint, tx_storage_checkpoint)?
+    } else {
+        let (frame_checkpoint, frame_storage_checkpoint) = frame_checkpoint;
+        state.revert(frame_checkpoint);
+        storage.revert(frame_storage_checkpoint);
+        StateDiff::new()
+    };
+    state.commit(tx_checkpoint);
+    storage.commit(tx_storage_checkpoint);
+
+    // the refunds of a failed frame are discarded, not the ones of the authorizations.
+    let mut refunded = existing_authorities as i64 * EXISTING_AUTHORITY_REFUND;
+    if success {
+        refunded += evm.gas.refunded();
+    }
+    let (gas_used, gas_refunded) = if unmetered {
+        (0, 0)
+    } else {
+        let spent = intrinsic_gas + evm.gas.spent();
+        let refunded = (refunded.max(0) as u64).min(max_refund(spent, spec));
+        ((spent - refunded).max(floor_gas), refunded)
+    };
+
+    Ok(EvmResult {
+        stack: evm.stack(),
+        success,
+        logs: evm.logs(),
+        ret: outcome.output().to_vec(),
+        outcome,
+        created_address: created_address.filter(|_| success),
+        state_diff,
+        gas_used,
+        gas_refunded,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words_longer_than_32_bytes_are_rejected() {
+        let result = evm(vec![0], vec![vec![1; 33]], vec![], HashMap::new());
+        assert!(matches!(
+            result,
+            Err(BuilderError::InvalidWordLength {
+                field: "to",
+                len: 33
+            })
+        ));
+
+        let result = evm(vec![0], vec![], vec![vec![1; 33]], HashMap::new());
+        assert!(matches!(
+            result,
+            Err(BuilderError::InvalidWordLength {
+                field: "basefee",
+                len: 33
+            })
+        ));
+
+        let state = HashMap::from([(vec![1; 33], (0, vec![], vec![]))]);
+        let result = evm(vec![0], vec![], vec![], state);
+        assert!(matches!(
+            result,
+            Err(BuilderError::InvalidWordLength {
+                field: "address",
+                len: 33
+            })
+        ));
+    }
+
+    #[test]
+    fn evm_mints_the_value_the_sender_lacks() {
+        // CALLVALUE, then CALL the account 0x33 with a value of 0x20 and push the status.
+        let code = hex_literal::hex!("34" "5f5f5f5f602060335af1");
+        let tx_data = vec![vec![0x22], vec![0x11], vec![], vec![], vec![0x10]];
+        let state = HashMap::from([(vec![0x11], (0, vec![0x01], vec![]))]);
+        let result = evm(code, tx_data, vec![], state).unwrap();
+
+        assert!(result.success, "{:?}", result.outcome);
+        assert_eq!(result.stack, [1.into(), 0x10.into()]);
+        // the pre-state is left as given, the caller sending the balance it holds.
+        let caller = result.state_diff[&Address::from(0x11)].balance.clone();
+        assert_eq!(
+            caller,
+            Some(Change {
+                before: 1.into(),
+                after: 0.into()
+            })
+        );
+        let callee = result.state_diff[&Address::from(0x33)].balance.clone();
+        assert_eq!(callee.unwrap().after, 0x20.into());
+
+        // without minting, the caller must hold the value.
+        let result = EvmBuilder::new()
+            .code(code)
+            .to([0x22; 20])
+            .caller([0x11; 20])
+            .value(0x10.into())
+            .account([0x11; 20], 0, 1.into(), [])
+            .run();
+        assert!(matches!(
+            result,
+            Err(BuilderError::Transaction(
+                TransactionError::InsufficientBalance
+            ))
+        ));
+    }
+
+    #[test]
+    fn prevrandao_falls_back_to_the_difficulty() {
+        // PREVRANDAO
+        let block_data = vec![vec![], vec![], vec![], vec![], vec![0x20]];
+        let result = evm([0x44], vec![], block_data.clone(), HashMap::new()).unwrap();
+        assert_eq!(result.stack, [0x20.into()]);
+
+        let block_data = [block_data, vec![vec![], vec![], vec![0x30]]].concat();
+        let result = evm([0x44], vec![], block_data, HashMap::new()).unwrap();
+        assert_eq!(result.stack, [0x30.into()]);
+    }
+
+    #[test]
+    fn overflowing_transactions_are_rejected() {
+        let run = |nonce, balance| {
+            EvmBuilder::new()
+                .to([0x22; 20])
+                .caller([0x11; 20])
+                .value(1.into())
+                .account([0x11; 20], nonce, 1.into(), [])
+                .account([0x22; 20], 0, balance, [0x00])
+                .run()
+        };
+
+        assert!(matches!(
+            run(0, U256::MAX),
+            Err(BuilderError::Transaction(TransactionError::BalanceOverflow))
+        ));
+        assert!(matches!(
+            run(usize::MAX, U256::zero()),
+            Err(BuilderError::Transaction(TransactionError::NonceOverflow))
+        ));
+    }
+
+    #[test]
+    fn overflowing_calls_fail_without_panicking() {
+        // CALL the account 0x22 with a value of 1 and return the status, then SELFDESTRUCT to it.
+        let call = hex_literal::hex!("5f5f5f5f600160225af15f5260205ff3");
+        let selfdestruct = hex_literal::hex!("6022ff");
+        let run = |code: &[u8]| {
+            EvmBuilder::new()
+                .to([0x11; 20])
+                .account([0x11; 20], 0, 1.into(), code)
+                .account(Address::from(0x22), 0, U256::MAX, [])
+                .run()
+                .unwrap()
+        };
+
+        let result = run(&call);
+        assert!(result.success, "{:?}", result.outcome);
+        assert_eq!(U256::from_big_endian(&result.ret), U256::zero());
+
+        let result = run(&selfdestruct);
+        assert_eq!(
+            result.outcome,
+            Outcome::Halt {
+                reason: HaltReason::BalanceOverflow
+            }
+        );
+    }
+
+    #[test]
+    fn result_serde_round_trip() {
+        // SSTORE(1, 2), LOG1 with topic 0x10, then return the byte 0x2a.
+        let code = [
+            0x60, 0x02, 0x60, 0x01, 0x55, 0x60, 0x10, 0x5f, 0x5f, 0xa1, 0x60, 0x2a, 0x5f, 0x53,
+            0x60, 0x01, 0x5f, 0xf3,
+        ];
+        let result = EvmBuilder::new()
+            .to([0x11; 20])
+            .account([0x11; 20], 0, U256::zero(), code)
+            .run()
+            .unwrap();
+
+        let json = serde_json::to_string(&result).unwrap();
+        let deserialized: EvmResult = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.ret, [0x2a]);
+        assert_eq!(deserialized.logs, result.logs);
+        assert_eq!(deserialized.state_diff, result.state_diff);
+        let storage = &deserialized.state_diff[&Address([0x11; 20])].storage;
+        assert_eq!(storage[&U256::one()].after, 2.into());
+        assert!(json.contains(r#""ret":"0x2a""#));
+        assert_eq!(serde_json::to_string(&deserialized).unwrap(), json);
     }
 }
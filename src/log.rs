@@ -1,14 +1,18 @@
 use primitive_types::U256;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq, Clone)]
+use crate::{primitives::Address, serde_hex::hex_bytes};
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Log {
-    pub address: U256,
+    pub address: Address,
+    #[serde(with = "hex_bytes")]
     pub data: Vec<u8>,
     pub topics: Vec<U256>,
 }
 
 impl Log {
-    pub fn new(address: U256, data: Vec<u8>, topics: Vec<U256>) -> Log {
+    pub fn new(address: Address, data: Vec<u8>, topics: Vec<U256>) -> Log {
         Log {
             address,
             data,
@@ -16,3 +20,32 @@ impl Log {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_as_hex() {
+        let log = Log::new(
+            Address::from(0xab),
+            vec![0x01, 0xff],
+            vec![U256::from(0x10)],
+        );
+        let json = serde_json::to_string(&log).unwrap();
+        assert_eq!(
+            json,
+            r#"{"address":"0x00000000000000000000000000000000000000ab","data":"0x01ff","topics":["0x10"]}"#
+        );
+        assert_eq!(serde_json::from_str::<Log>(&json).unwrap(), log);
+    }
+
+    #[test]
+    fn rejects_malformed_hex() {
+        let json = r#"{"address":"0xab","data":"0x","topics":[]}"#;
+        assert!(serde_json::from_str::<Log>(json).is_err());
+        let json =
+            r#"{"address":"0x00000000000000000000000000000000000000ab","data":"01","topics":[]}"#;
+        assert!(serde_json::from_str::<Log>(json).is_err());
+    }
+}
@@ -1,6 +1,6 @@
-use std::{collections::HashMap, vec};
+use std::collections::HashMap;
 
-use evm_dev_rs::{evm, Log};
+use evm_dev_rs::{Address, EvmBuilder, Log, B256};
 use primitive_types::U256;
 use serde::{Deserialize, Deserializer};
 
@@ -54,7 +54,10 @@ struct BlockDataRaw {
     coinbase: Option<String>,
     timestamp: Option<String>,
     number: Option<String>,
+    #[serde(alias = "currentDifficulty")]
     difficulty: Option<String>,
+    #[serde(alias = "currentRandom")]
+    prevrandao: Option<String>,
     gaslimit: Option<String>,
     chainid: Option<String>,
 }
@@ -89,6 +92,16 @@ struct Expect {
     return_data: Option<String>,
 }
 
+/// Parses a `0x` prefixed hex number.
+fn word(hex: &str) -> U256 {
+    U256::from_str_radix(&hex[2..], 16).unwrap()
+}
+
+/// Parses a `0x` prefixed hex address, which may omit its leading zeros.
+fn address(hex: &str) -> Address {
+    Address::from_slice(&hex::decode(format!("{:0>40}", &hex[2..])).unwrap()).unwrap()
+}
+
 fn main() {
     let text = std::fs::read_to_string("./evm.json").unwrap();
     let data: Vec<EvmTest> = serde_json::from_str(&text).unwrap();
@@ -100,135 +113,76 @@ fn main() {
 
         let code = hex::decode(&test.code.bin).unwrap();
 
-        let tx = match &test.tx {
-            Some(tx) => {
-                // [2..] is necessary to delete the initial 0x
-                let to = hex::decode(format!(
-                    "{:0>64}",
-                    &tx.to.as_ref().unwrap_or(&String::from("aa"))[2..]
-                ))
-                .unwrap();
-
-                let from = hex::decode(format!(
-                    "{:0>64}",
-                    &tx.from.as_ref().unwrap_or(&String::from("aa"))[2..]
-                ))
-                .unwrap();
-
-                let origin = hex::decode(format!(
-                    "{:0>64}",
-                    &tx.origin.as_ref().unwrap_or(&String::from("aa"))[2..]
-                ))
-                .unwrap();
-
-                let gasprice = hex::decode(format!(
-                    "{:0>64}",
-                    &tx.gasprice.as_ref().unwrap_or(&String::from("aa"))[2..]
-                ))
-                .unwrap();
-
-                let value = hex::decode(format!(
-                    "{:0>64}",
-                    &tx.value.as_ref().unwrap_or(&String::from("aa"))[2..]
-                ))
-                .unwrap();
-
-                let data = hex::decode(format!(
-                    "{:0>64}",
-                    &tx.data.as_ref().unwrap_or(&String::from("aa"))[2..]
-                ))
-                .unwrap();
+        // the fixtures predate balance checks, sending value their senders don't hold.
+        let mut builder = EvmBuilder::new()
+            .code(code)
+            .mint_missing_value(true)
+            .unmetered(true);
 
-                vec![to, from, origin, gasprice, value, data]
+        if let Some(tx) = &test.tx {
+            if let Some(to) = &tx.to {
+                builder = builder.to(address(to));
             }
-            None => vec![],
-        };
-
-        let block = match &test.block {
-            Some(block) => {
-                // [2..] is necessary to delete the initial 0x
-                let basefee = hex::decode(format!(
-                    "{:0>64}",
-                    &block.basefee.as_ref().unwrap_or(&String::from("aa"))[2..]
-                ))
-                .unwrap();
-
-                let coinbase = hex::decode(format!(
-                    "{:0>64}",
-                    &block.coinbase.as_ref().unwrap_or(&String::from("aa"))[2..]
-                ))
-                .unwrap();
-
-                let timestamp = hex::decode(format!(
-                    "{:0>64}",
-                    &block.timestamp.as_ref().unwrap_or(&String::from("aa"))[2..]
-                ))
-                .unwrap();
-
-                let number = hex::decode(format!(
-                    "{:0>64}",
-                    &block.number.as_ref().unwrap_or(&String::from("aa"))[2..]
-                ))
-                .unwrap();
-
-                let difficulty = hex::decode(format!(
-                    "{:0>64}",
-                    &block.difficulty.as_ref().unwrap_or(&String::from("aa"))[2..]
-                ))
-                .unwrap();
-
-                let gaslimit = hex::decode(format!(
-                    "{:0>64}",
-                    &block.gaslimit.as_ref().unwrap_or(&String::from("aa"))[2..]
-                ))
-                .unwrap();
-
-                let chainid = hex::decode(format!(
-                    "{:0>64}",
-                    &block.chainid.as_ref().unwrap_or(&String::from("aa"))[2..]
-                ))
-                .unwrap();
-
-                vec![
-                    basefee, coinbase, timestamp, number, difficulty, gaslimit, chainid,
-                ]
+            if let Some(from) = &tx.from {
+                builder = builder.caller(address(from));
             }
-            None => vec![],
-        };
-
-        let state = test
-            .state
-            .entries
-            .is_empty()
-            .then(HashMap::default)
-            .unwrap_or_else(|| {
-                test.state
-                    .entries
-                    .iter()
-                    .map(|(address, data)| {
-                        let address_bytes = hex::decode(format!("{:0>64}", &address[2..])).unwrap();
-
-                        let nonce = data
-                            .nonce
-                            .as_deref()
-                            .unwrap_or("0")
-                            .parse::<usize>()
-                            .unwrap();
-
-                        let balance = hex::decode(format!(
-                            "{:0>64}",
-                            &data.balance.as_deref().unwrap_or("0xaa")[2..]
-                        ))
-                        .unwrap();
+            if let Some(origin) = &tx.origin {
+                builder = builder.origin(address(origin));
+            }
+            if let Some(gasprice) = &tx.gasprice {
+                builder = builder.gas_price(word(gasprice));
+            }
+            if let Some(value) = &tx.value {
+                builder = builder.value(word(value));
+            }
+            if let Some(data) = &tx.data {
+                // the fixtures left-pad calldata to a word.
+                builder = builder.calldata(hex::decode(format!("{:0>64}", &data[2..])).unwrap());
+            }
+        }
 
-                        let code = hex::decode(data.code.as_ref().map_or("", |c| &c.bin)).unwrap();
+        if let Some(block) = &test.block {
+            if let Some(basefee) = &block.basefee {
+                builder = builder.basefee(word(basefee));
+            }
+            if let Some(coinbase) = &block.coinbase {
+                builder = builder.coinbase(address(coinbase));
+            }
+            if let Some(timestamp) = &block.timestamp {
+                builder = builder.timestamp(word(timestamp).as_u64());
+            }
+            if let Some(number) = &block.number {
+                builder = builder.number(word(number).as_u64());
+            }
+            if let Some(difficulty) = &block.difficulty {
+                builder = builder.difficulty(word(difficulty));
+            }
+            // fixtures written before the Merge only provide the difficulty,
+            // which PREVRANDAO replaced.
+            if let Some(prevrandao) = block.prevrandao.as_ref().or(block.difficulty.as_ref()) {
+                builder = builder.prevrandao(B256::from_word(word(prevrandao)));
+            }
+            if let Some(gaslimit) = &block.gaslimit {
+                builder = builder.block_gas_limit(word(gaslimit).as_u64());
+            }
+            if let Some(chainid) = &block.chainid {
+                builder = builder.chain_id(word(chainid).as_u64());
+            }
+        }
 
-                        (address_bytes, (nonce, balance, code))
-                    })
-                    .collect::<HashMap<_, _>>()
-            });
+        for (account, data) in &test.state.entries {
+            let nonce = data
+                .nonce
+                .as_deref()
+                .unwrap_or("0")
+                .parse::<usize>()
+                .unwrap();
+            let balance = data.balance.as_deref().map(word).unwrap_or_default();
+            let code = hex::decode(data.code.as_ref().map_or("", |c| &c.bin)).unwrap();
+            builder = builder.account(address(account), nonce, balance, code);
+        }
 
-        let result = evm(code, tx, block, state);
+        let result = builder.run().unwrap();
 
         let mut expected_stack: Vec<U256> = Vec::new();
         if let Some(ref stacks) = test.expect.stack {
@@ -240,8 +194,8 @@ fn main() {
         let mut expected_logs: Vec<Log> = Vec::new();
         if let Some(logs) = &test.expect.logs {
             for log in logs {
-                let address = U256::from_str_radix(&log.address, 16).unwrap();
-                let data = hex::decode(format!("{}", &log.data)).unwrap();
+                let address = Address::from_word(U256::from_str_radix(&log.address, 16).unwrap());
+                let data = hex::decode(&log.data).unwrap();
                 let topics = log
                     .topics
                     .iter()
@@ -256,31 +210,14 @@ fn main() {
             }
         }
 
-        let mut matching = result.stack.len() == expected_stack.len();
-        if matching {
-            for i in 0..result.stack.len() {
-                if result.stack[i] != expected_stack[i] {
-                    matching = false;
-                    break;
-                }
-                for i in 0..result.logs.len() {
-                    if result.logs[i] != expected_logs[i] {
-                        matching = false;
-                        break;
-                    }
-                }
-            }
-        }
+        let mut matching = result.stack == expected_stack && result.logs == expected_logs;
 
         matching = matching && result.success == test.expect.success;
 
         let mut expected_ret = vec![];
-        match &test.expect.return_data {
-            Some(ret) => {
-                let ret = hex::decode(format!("{}", &ret)).unwrap();
-                expected_ret = ret;
-            }
-            None => {}
+        if let Some(ret) = &test.expect.return_data {
+            let ret = hex::decode(ret).unwrap();
+            expected_ret = ret;
         };
 
         matching = matching && result.ret == expected_ret;
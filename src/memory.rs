@@ -1,14 +1,37 @@
+use std::{cell::Cell, rc::Rc};
+
 use primitive_types::U256;
 
 use crate::errors::ExecutionError;
 
+/// The largest memory, in bytes. Expanding it to this size costs over two billion gas, more than
+/// any block holds, so going past it runs out of gas.
+const MEMORY_LIMIT: usize = 32 * 1024 * 1024;
+/// The largest memory of the frames of a transaction together, which unmetered frames could
+/// otherwise each grow to `MEMORY_LIMIT`, up to the call depth limit.
+const TOTAL_MEMORY_LIMIT: usize = 256 * 1024 * 1024;
+
 pub struct Memory {
     store: Vec<u8>,
+    /// The bytes held by the memories of the frames sharing the bound of this one.
+    allocated: Rc<Cell<usize>>,
 }
 
 impl Memory {
     pub fn new() -> Memory {
-        Memory { store: Vec::new() }
+        Memory {
+            store: Vec::new(),
+            allocated: Rc::new(Cell::new(0)),
+        }
+    }
+
+    /// Returns the empty memory of a frame nested in the frame of this memory, both memories
+    /// counting towards the same `TOTAL_MEMORY_LIMIT`.
+    pub fn child(&self) -> Memory {
+        Memory {
+            store: Vec::new(),
+            allocated: self.allocated.clone(),
+        }
     }
 
     pub fn save_word(&mut self, offset: usize, word: U256) -> Result<U256, ExecutionError> {
@@ -17,9 +40,7 @@ impl Memory {
         // memory must have at least offset + 32 free bytes left.
         self.resize(offset, 32)?;
 
-        for i in 0..32 {
-            self.store[offset + i] = value_bytes[i];
-        }
+        self.store[offset..offset + 32].copy_from_slice(&value_bytes);
         Ok(word)
     }
 
@@ -53,37 +74,70 @@ impl Memory {
     }
 
     pub fn get_bytes(&mut self, offset: usize, n_bytes: usize) -> Result<Vec<u8>, ExecutionError> {
-        let mut bytes = vec![];
-        for i in 0..n_bytes {
-            let current_offset = offset + i;
-            self.resize(current_offset, 1)?;
-
-            let byte = *self.store.get(current_offset).unwrap_or(&0);
-
-            bytes.push(byte);
+        if n_bytes == 0 {
+            return Ok(vec![]);
         }
+        self.resize(offset, n_bytes)?;
 
-        Ok(bytes)
+        Ok(self.store[offset..offset + n_bytes].to_vec())
     }
 
     pub fn save_bytes(&mut self, offset: usize, bytes: &[u8]) -> Result<(), ExecutionError> {
-        for i in 0..bytes.len() {
-            self.save_byte(offset + i, bytes[i])?;
+        if bytes.is_empty() {
+            return Ok(());
         }
+        self.resize(offset, bytes.len())?;
 
+        self.store[offset..offset + bytes.len()].copy_from_slice(bytes);
         Ok(())
     }
 
-    fn resize(&mut self, offset: usize, size: usize) -> Result<(), ExecutionError> {
-        if self.store.len() < offset + size {
-            let resize_value = (offset + size - 1) / 32 + 1;
-            if let Some(resize_value) = resize_value.checked_mul(32) {
-                self.store.resize(resize_value, 0);
-                return Ok(());
-            } else {
-                return Err(ExecutionError::IntegerOverflow);
+    /// Expands the memory to hold `size` bytes at `offset`, in words of 32 bytes.
+    pub fn resize(&mut self, offset: usize, size: usize) -> Result<(), ExecutionError> {
+        let end = offset
+            .checked_add(size)
+            .ok_or(ExecutionError::IntegerOverflow)?;
+        if end > MEMORY_LIMIT {
+            return Err(ExecutionError::OutOfGas);
+        }
+        if self.store.len() < end {
+            let len = end.div_ceil(32) * 32;
+            let allocated = self.allocated.get() + len - self.store.len();
+            if allocated > TOTAL_MEMORY_LIMIT {
+                return Err(ExecutionError::OutOfGas);
             }
+            self.allocated.set(allocated);
+            self.store.resize(len, 0);
         }
         Ok(())
     }
 }
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        self.allocated.set(self.allocated.get() - self.store.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_memories_share_their_bound() {
+        let mut memory = Memory::new();
+        memory.resize(0, 64).unwrap();
+        let mut child = memory.child();
+        child.resize(0, 1).unwrap();
+        assert_eq!(memory.allocated.get(), 96);
+
+        // the memory of a frame is given back once the frame ends.
+        drop(child);
+        assert_eq!(memory.allocated.get(), 64);
+
+        memory.allocated.set(TOTAL_MEMORY_LIMIT - 32);
+        let mut child = memory.child();
+        child.resize(0, 32).unwrap();
+        assert!(matches!(child.resize(32, 1), Err(ExecutionError::OutOfGas)));
+    }
+}
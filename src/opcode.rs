@@ -1,6 +1,6 @@
 macro_rules! opcodes {
     ($($name:ident($number:expr),)*) => {
-        #[derive(Debug, PartialEq, PartialOrd)]
+        #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
         pub enum OpCode {
             $($name = $number,)*
         }
@@ -153,22 +153,38 @@ opcodes! {
     Log2(162),
     Log3(163),
     Log4(164),
+    Dataload(208),
+    Dataloadn(209),
+    Datasize(210),
+    Datacopy(211),
+    Rjump(224),
+    Rjumpi(225),
+    Rjumpv(226),
+    Callf(227),
+    Retf(228),
+    Jumpf(229),
+    Dupn(230),
+    Swapn(231),
+    Exchange(232),
+    Eofcreate(236),
+    Returncontract(238),
     Create(240),
     Call(241),
     Return(243),
     Delegatecall(244),
+    Returndataload(247),
+    Extcall(248),
+    Extdelegatecall(249),
     Staticcall(250),
+    Extstaticcall(251),
     Revert(253),
+    Invalid(254),
     Selfdestruct(255),
 }
 
 impl OpCode {
     pub fn new(opcode: u8) -> Option<Self> {
-        if let Ok(opcode) = opcode.try_into() {
-            Some(opcode)
-        } else {
-            None
-        }
+        opcode.try_into().ok()
     }
 
     /// Helper function to determine the push data size for each `Push` opcode
@@ -249,4 +265,155 @@ impl OpCode {
             _ => 0, // return 0 for non-`LOG`
         }
     }
+
+    /// Returns the number of stack items consumed and produced by the opcode.
+    ///
+    /// The EOF opcodes whose effect depends on their immediates or on the called code
+    /// section (`CALLF`, `RETF`, `JUMPF`, `DUPN`, `SWAPN` and `EXCHANGE`) return `(0, 0)`.
+    pub fn stack_io(&self) -> (usize, usize) {
+        match self {
+            OpCode::Stop | OpCode::Jumpdest | OpCode::Invalid => (0, 0),
+            OpCode::Add
+            | OpCode::Mul
+            | OpCode::Sub
+            | OpCode::Div
+            | OpCode::Sdiv
+            | OpCode::Mod
+            | OpCode::Smod
+            | OpCode::Exp
+            | OpCode::Signextend
+            | OpCode::Lt
+            | OpCode::Gt
+            | OpCode::Slt
+            | OpCode::Sgt
+            | OpCode::Eq
+            | OpCode::And
+            | OpCode::Or
+            | OpCode::Xor
+            | OpCode::Byte
+            | OpCode::Shl
+            | OpCode::Shr
+            | OpCode::Sar
+            | OpCode::Sha3 => (2, 1),
+            OpCode::AddMod | OpCode::MulMod => (3, 1),
+            OpCode::Iszero
+            | OpCode::Not
+            | OpCode::Balance
+            | OpCode::Calldataload
+            | OpCode::Extcodesize
+            | OpCode::Extcodehash
+            | OpCode::Blockhash
+            | OpCode::Mload
+            | OpCode::Sload
+            | OpCode::Dataload
+            | OpCode::Returndataload => (1, 1),
+            OpCode::Address
+            | OpCode::Origin
+            | OpCode::Caller
+            | OpCode::Callvalue
+            | OpCode::Calldatasize
+            | OpCode::Codesize
+            | OpCode::Gasprice
+            | OpCode::Returndatasize
+            | OpCode::Coinbase
+            | OpCode::Timestamp
+            | OpCode::Number
+            | OpCode::Difficulty
+            | OpCode::Gaslimit
+            | OpCode::Chainid
+            | OpCode::Selfbalance
+            | OpCode::Basefee
+            | OpCode::Pc
+            | OpCode::Msize
+            | OpCode::Gas
+            | OpCode::Dataloadn
+            | OpCode::Datasize => (0, 1),
+            OpCode::Calldatacopy | OpCode::Codecopy | OpCode::Returndatacopy | OpCode::Datacopy => {
+                (3, 0)
+            }
+            OpCode::Extcodecopy => (4, 0),
+            OpCode::Pop | OpCode::Jump | OpCode::Selfdestruct | OpCode::Rjumpi | OpCode::Rjumpv => {
+                (1, 0)
+            }
+            OpCode::Mstore
+            | OpCode::Mstore8
+            | OpCode::Sstore
+            | OpCode::Jumpi
+            | OpCode::Return
+            | OpCode::Revert
+            | OpCode::Returncontract => (2, 0),
+            OpCode::Log0 | OpCode::Log1 | OpCode::Log2 | OpCode::Log3 | OpCode::Log4 => {
+                (2 + self.topics(), 0)
+            }
+            OpCode::Create => (3, 1),
+            OpCode::Call => (7, 1),
+            OpCode::Delegatecall | OpCode::Staticcall => (6, 1),
+            OpCode::Eofcreate | OpCode::Extcall => (4, 1),
+            OpCode::Extdelegatecall | OpCode::Extstaticcall => (3, 1),
+            OpCode::Rjump
+            | OpCode::Callf
+            | OpCode::Retf
+            | OpCode::Jumpf
+            | OpCode::Dupn
+            | OpCode::Swapn
+            | OpCode::Exchange => (0, 0),
+            _ if self.is_push() => (0, 1),
+            _ if OpCode::Dup1 <= *self && *self <= OpCode::Dup16 => {
+                (self.data_index(), self.data_index() + 1)
+            }
+            // `SWAP`s are the only opcodes left.
+            _ => (self.data_index() + 1, self.data_index() + 1),
+        }
+    }
+
+    /// Returns the size of the immediate data following the opcode.
+    ///
+    /// For `RJUMPV` this is only the size of its jump table length, the table itself follows.
+    pub fn immediate_size(&self) -> usize {
+        match self {
+            OpCode::Dataloadn | OpCode::Rjump | OpCode::Rjumpi | OpCode::Callf | OpCode::Jumpf => 2,
+            OpCode::Rjumpv
+            | OpCode::Dupn
+            | OpCode::Swapn
+            | OpCode::Exchange
+            | OpCode::Eofcreate
+            | OpCode::Returncontract => 1,
+            _ => self.push_data_size(),
+        }
+    }
+
+    /// Returns true if the opcode ends the execution of a code section.
+    pub fn is_terminating(&self) -> bool {
+        matches!(
+            self,
+            OpCode::Stop
+                | OpCode::Return
+                | OpCode::Revert
+                | OpCode::Invalid
+                | OpCode::Retf
+                | OpCode::Jumpf
+                | OpCode::Returncontract
+        )
+    }
+
+    /// Returns true if the opcode is only valid in legacy code and rejected in EOF containers.
+    pub fn is_legacy_only(&self) -> bool {
+        matches!(
+            self,
+            OpCode::Codesize
+                | OpCode::Codecopy
+                | OpCode::Extcodesize
+                | OpCode::Extcodecopy
+                | OpCode::Extcodehash
+                | OpCode::Jump
+                | OpCode::Jumpi
+                | OpCode::Pc
+                | OpCode::Gas
+                | OpCode::Create
+                | OpCode::Call
+                | OpCode::Delegatecall
+                | OpCode::Staticcall
+                | OpCode::Selfdestruct
+        )
+    }
 }
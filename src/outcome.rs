@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+
+use primitive_types::U256;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::DatabaseError,
+    errors::HaltReason,
+    evm::ExecutionResult,
+    primitives::Address,
+    serde_hex::{hex_bytes, hex_change},
+    state::{Checkpoint, State},
+    storage::Storage,
+};
+
+/// How an execution ended.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Outcome {
+    /// The execution completed, keeping its changes.
+    Success {
+        reason: SuccessReason,
+        #[serde(with = "hex_bytes")]
+        output: Vec<u8>,
+    },
+    /// The execution was reverted by `REVERT`, discarding its changes.
+    Revert {
+        #[serde(with = "hex_bytes")]
+        output: Vec<u8>,
+    },
+    /// The execution halted exceptionally, discarding its changes and consuming all its gas.
+    Halt { reason: HaltReason },
+}
+
+impl Outcome {
+    pub(crate) fn new(result: ExecutionResult, output: Vec<u8>) -> Outcome {
+        match result {
+            ExecutionResult::Success(reason) => Outcome::Success { reason, output },
+            ExecutionResult::Revert => Outcome::Revert { output },
+            ExecutionResult::Exception(reason) => Outcome::Halt { reason },
+        }
+    }
+
+    pub(crate) fn into_result(self) -> (ExecutionResult, Vec<u8>) {
+        match self {
+            Outcome::Success { reason, output } => (ExecutionResult::Success(reason), output),
+            Outcome::Revert { output } => (ExecutionResult::Revert, output),
+            Outcome::Halt { reason } => (ExecutionResult::Exception(reason), vec![]),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        matches!(self, Outcome::Success { .. })
+    }
+
+    /// Returns the reason of the exceptional halt, if the execution ended with one.
+    pub fn halt_reason(&self) -> Option<HaltReason> {
+        match self {
+            Outcome::Halt { reason } => Some(*reason),
+            _ => None,
+        }
+    }
+
+    /// Returns the output of the execution, empty after an exceptional halt.
+    pub fn output(&self) -> &[u8] {
+        match self {
+            Outcome::Success { output, .. } | Outcome::Revert { output } => output,
+            Outcome::Halt { .. } => &[],
+        }
+    }
+}
+
+/// The instruction that ended a successful execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SuccessReason {
+    /// `STOP`, or the end of the code.
+    Stop,
+    /// `RETURN`, or `RETURNCONTRACT` for EOF initcode.
+    Return,
+    SelfDestruct,
+}
+
+/// A value changed by the execution.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Change<T> {
+    pub before: T,
+    pub after: T,
+}
+
+/// The changes made to an account by the execution. Unchanged fields are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountDiff {
+    /// The account didn't exist before the execution.
+    pub created: bool,
+    /// The account was deleted by the execution.
+    pub destroyed: bool,
+    pub balance: Option<Change<U256>>,
+    pub nonce: Option<Change<usize>>,
+    #[serde(with = "hex_change")]
+    pub code: Option<Change<Vec<u8>>>,
+    /// The storage slots changed, by slot.
+    pub storage: BTreeMap<U256, Change<U256>>,
+}
+
+impl AccountDiff {
+    fn is_empty(&self) -> bool {
+        *self == AccountDiff::default()
+    }
+}
+
+/// The accounts changed by the execution, by address.
+pub type StateDiff = BTreeMap<Address, AccountDiff>;
+
+fn change<T: PartialEq>(before: T, after: T) -> Option<Change<T>> {
+    (before != after).then_some(Change { before, after })
+}
+
+/// Returns the changes made to the state and storage since their checkpoints, which must
+/// still be open.
+pub fn state_diff(
+    state: &mut State,
+    storage: &mut Storage,
+    checkpoint: Checkpoint,
+    storage_checkpoint: Checkpoint,
+) -> Result<StateDiff, DatabaseError> {
+    let mut diff = StateDiff::new();
+
+    for (address, old) in state.changes(checkpoint) {
+        let new = state.account(address)?;
+        if old.is_none() && new.is_none() {
+            continue;
+        }
+
+        let created = old.is_none();
+        let destroyed = new.is_none();
+        let old = old.unwrap_or_default();
+        let new = new.unwrap_or_default();
+        let account = AccountDiff {
+            created,
+            destroyed,
+            balance: change(old.balance, new.balance),
+            nonce: change(old.nonce, new.nonce),
+            code: change(old.code, new.code),
+            storage: BTreeMap::new(),
+        };
+        diff.insert(address, account);
+    }
+
+    for ((address, slot), before) in storage.changes(storage_checkpoint) {
+        if let Some(change) = change(before, storage.load_slot(address, slot)?) {
+            diff.entry(address)
+                .or_default()
+                .storage
+                .insert(slot, change);
+        }
+    }
+
+    diff.retain(|_, account| !account.is_empty());
+    Ok(diff)
+}
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+
+use primitive_types::U256;
+use thiserror::Error;
+
+use crate::{errors::TransactionError, primitives::Address, state::State, storage::Storage};
+
+/// Replaces fields of an account for one execution, like the state overrides of geth's
+/// `eth_call`. Fields left to `None` keep their value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccountOverride {
+    pub balance: Option<U256>,
+    pub nonce: Option<usize>,
+    pub code: Option<Vec<u8>>,
+    /// Replaces the whole storage of the account, the slots not given being zero.
+    pub state: Option<HashMap<U256, U256>>,
+    /// Replaces the given storage slots, keeping the others.
+    pub state_diff: Option<HashMap<U256, U256>>,
+}
+
+/// The accounts to override, by address.
+pub type StateOverride = HashMap<Address, AccountOverride>;
+
+/// An invalid [`StateOverride`].
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum OverrideError {
+    #[error("account {0} has both `state` and `state_diff` overrides")]
+    StateAndStateDiff(Address),
+}
+
+/// Applies the overrides to the state and storage, creating the accounts that don't exist.
+/// Invalid overrides are refused before any is applied.
+pub fn apply_overrides(
+    state: &mut State,
+    storage: &mut Storage,
+    overrides: &StateOverride,
+) -> Result<(), TransactionError> {
+    for (address, account) in overrides {
+        if account.state.is_some() && account.state_diff.is_some() {
+            return Err(OverrideError::StateAndStateDiff(*address).into());
+        }
+    }
+
+    for (address, account) in overrides {
+        let mut data = state.account(*address)?.unwrap_or_default();
+        if let Some(balance) = account.balance {
+            data.balance = balance;
+        }
+        if let Some(nonce) = account.nonce {
+            data.nonce = nonce;
+        }
+        if let Some(code) = &account.code {
+            data.code = code.clone();
+        }
+        state.set_account(*address, data)?;
+
+        if let Some(slots) = &account.state {
+            storage.replace_storage(*address, slots.clone());
+        }
+        for (slot, value) in account.state_diff.iter().flatten() {
+            storage.set_constract_slot(*address, *slot, *value)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::db::InMemoryDB;
+
+    const ACCOUNT: Address = Address([0x11; 20]);
+
+    /// Returns a state holding `ACCOUNT` with slots 1 and 2 set.
+    fn state() -> (State, Storage) {
+        let mut db = InMemoryDB::new();
+        db.insert_account(ACCOUNT, 1, 10.into(), vec![0x00]);
+        db.insert_storage(ACCOUNT, 1.into(), 1.into());
+        db.insert_storage(ACCOUNT, 2.into(), 2.into());
+        let db = Arc::new(db);
+        (State::new(db.clone()), Storage::new(db))
+    }
+
+    #[test]
+    fn overrides_only_the_given_fields() {
+        let (mut state, mut storage) = state();
+        let overrides = StateOverride::from([(
+            ACCOUNT,
+            AccountOverride {
+                balance: Some(20.into()),
+                ..AccountOverride::default()
+            },
+        )]);
+        apply_overrides(&mut state, &mut storage, &overrides).unwrap();
+
+        let account = state.account(ACCOUNT).unwrap().unwrap();
+        assert_eq!(
+            (account.nonce, account.balance, account.code),
+            (1, 20.into(), vec![0x00])
+        );
+    }
+
+    #[test]
+    fn creates_missing_accounts() {
+        let (mut state, mut storage) = state();
+        let address = Address::from(0x22);
+        let overrides = StateOverride::from([(
+            address,
+            AccountOverride {
+                nonce: Some(5),
+                code: Some(vec![0x5f]),
+                ..AccountOverride::default()
+            },
+        )]);
+        apply_overrides(&mut state, &mut storage, &overrides).unwrap();
+
+        let account = state.account(address).unwrap().unwrap();
+        assert_eq!(
+            (account.nonce, account.balance, account.code),
+            (5, U256::zero(), vec![0x5f])
+        );
+    }
+
+    #[test]
+    fn state_replaces_the_whole_storage() {
+        let (mut state, mut storage) = state();
+        let overrides = StateOverride::from([(
+            ACCOUNT,
+            AccountOverride {
+                state: Some(HashMap::from([(1.into(), 3.into())])),
+                ..AccountOverride::default()
+            },
+        )]);
+        apply_overrides(&mut state, &mut storage, &overrides).unwrap();
+
+        assert_eq!(storage.load_slot(ACCOUNT, 1.into()).unwrap(), 3.into());
+        assert_eq!(storage.load_slot(ACCOUNT, 2.into()).unwrap(), U256::zero());
+    }
+
+    #[test]
+    fn state_diff_replaces_the_given_slots() {
+        let (mut state, mut storage) = state();
+        let overrides = StateOverride::from([(
+            ACCOUNT,
+            AccountOverride {
+                state_diff: Some(HashMap::from([(1.into(), 3.into())])),
+                ..AccountOverride::default()
+            },
+        )]);
+        apply_overrides(&mut state, &mut storage, &overrides).unwrap();
+
+        assert_eq!(storage.load_slot(ACCOUNT, 1.into()).unwrap(), 3.into());
+        assert_eq!(storage.load_slot(ACCOUNT, 2.into()).unwrap(), 2.into());
+    }
+
+    #[test]
+    fn state_and_state_diff_are_exclusive() {
+        let (mut state, mut storage) = state();
+        let overrides = StateOverride::from([
+            (
+                Address::from(0x22),
+                AccountOverride {
+                    balance: Some(1.into()),
+                    ..AccountOverride::default()
+                },
+            ),
+            (
+                ACCOUNT,
+                AccountOverride {
+                    state: Some(HashMap::new()),
+                    state_diff: Some(HashMap::new()),
+                    ..AccountOverride::default()
+                },
+            ),
+        ]);
+        let result = apply_overrides(&mut state, &mut storage, &overrides);
+
+        assert!(matches!(
+            result,
+            Err(TransactionError::Override(
+                OverrideError::StateAndStateDiff(ACCOUNT)
+            ))
+        ));
+        // no override is applied.
+        assert_eq!(state.account(Address::from(0x22)).unwrap(), None);
+    }
+}
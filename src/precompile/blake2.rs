@@ -0,0 +1,183 @@
+use super::{invalid_input, PrecompileError, PrecompileOutput, PrecompileResult};
+
+/// Size of the input: `rounds || h || m || t || f`.
+const INPUT_SIZE: usize = 4 + 64 + 128 + 16 + 1;
+const ROUND_GAS: u64 = 1;
+
+const IV: [u64; 8] = [
+    0x6a09e667f3bcc908,
+    0xbb67ae8584caa73b,
+    0x3c6ef372fe94f82b,
+    0xa54ff53a5f1d36f1,
+    0x510e527fade682d1,
+    0x9b05688c2b3e6c1f,
+    0x1f83d9abfb41bd6b,
+    0x5be0cd19137e2179,
+];
+
+/// Message word permutations of each round.
+const SIGMA: [[usize; 16]; 10] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+];
+
+/// Runs the BLAKE2b compression function F on the state `h` (EIP-152), costing one gas
+/// per round.
+pub fn blake2f(input: &[u8], gas_limit: u64) -> PrecompileResult {
+    if input.len() != INPUT_SIZE {
+        return Err(PrecompileError::InvalidInput(format!(
+            "input must be {INPUT_SIZE} bytes long"
+        )));
+    }
+
+    let rounds = u32::from_be_bytes([input[0], input[1], input[2], input[3]]);
+    let cost = rounds as u64 * ROUND_GAS;
+    if cost > gas_limit {
+        return Err(PrecompileError::OutOfGas);
+    }
+
+    let final_block = match input[INPUT_SIZE - 1] {
+        0 => false,
+        1 => true,
+        _ => return Err(invalid_input("final block flag must be 0 or 1")),
+    };
+
+    let words = |bytes: &[u8]| -> Vec<u64> {
+        bytes
+            .chunks(8)
+            .map(|word| u64::from_le_bytes(word.try_into().unwrap()))
+            .collect()
+    };
+    let mut h: [u64; 8] = words(&input[4..68]).try_into().unwrap();
+    let m: [u64; 16] = words(&input[68..196]).try_into().unwrap();
+    let t: [u64; 2] = words(&input[196..212]).try_into().unwrap();
+
+    compress(rounds as usize, &mut h, &m, t, final_block);
+
+    let output = h.iter().flat_map(|word| word.to_le_bytes()).collect();
+    Ok(PrecompileOutput::new(cost, output))
+}
+
+fn compress(rounds: usize, h: &mut [u64; 8], m: &[u64; 16], t: [u64; 2], final_block: bool) {
+    let mut v = [0u64; 16];
+    v[..8].copy_from_slice(h);
+    v[8..].copy_from_slice(&IV);
+    v[12] ^= t[0];
+    v[13] ^= t[1];
+    if final_block {
+        v[14] = !v[14];
+    }
+
+    for round in 0..rounds {
+        let s = &SIGMA[round % 10];
+        mix(&mut v, 0, 4, 8, 12, m[s[0]], m[s[1]]);
+        mix(&mut v, 1, 5, 9, 13, m[s[2]], m[s[3]]);
+        mix(&mut v, 2, 6, 10, 14, m[s[4]], m[s[5]]);
+        mix(&mut v, 3, 7, 11, 15, m[s[6]], m[s[7]]);
+        mix(&mut v, 0, 5, 10, 15, m[s[8]], m[s[9]]);
+        mix(&mut v, 1, 6, 11, 12, m[s[10]], m[s[11]]);
+        mix(&mut v, 2, 7, 8, 13, m[s[12]], m[s[13]]);
+        mix(&mut v, 3, 4, 9, 14, m[s[14]], m[s[15]]);
+    }
+
+    for i in 0..8 {
+        h[i] ^= v[i] ^ v[i + 8];
+    }
+}
+
+/// The mixing function G.
+fn mix(v: &mut [u64; 16], a: usize, b: usize, c: usize, d: usize, x: u64, y: u64) {
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+    v[d] = (v[d] ^ v[a]).rotate_right(32);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(24);
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+    v[d] = (v[d] ^ v[a]).rotate_right(16);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(63);
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    /// The fourth test vector of EIP-152: 12 rounds compressing the final block of "abc".
+    const ABC: [u8; INPUT_SIZE] = hex!(
+        "0000000c"
+        "48c9bdf267e6096a3ba7ca8485ae67bb2bf894fe72f36e3cf1361d5f3af54fa5"
+        "d182e6ad7f520e511f6c3e2b8c68059b6bbd41fbabd9831f79217e1319cde05b"
+        "6162630000000000000000000000000000000000000000000000000000000000"
+        "0000000000000000000000000000000000000000000000000000000000000000"
+        "0000000000000000000000000000000000000000000000000000000000000000"
+        "0000000000000000000000000000000000000000000000000000000000000000"
+        "0300000000000000"
+        "0000000000000000"
+        "01"
+    );
+
+    #[test]
+    fn eip_152_vectors() {
+        // BLAKE2b-512 of "abc".
+        let output = hex!(
+            "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d1"
+            "7d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923"
+        );
+        assert_eq!(
+            blake2f(&ABC, u64::MAX),
+            Ok(PrecompileOutput::new(12, output.to_vec()))
+        );
+
+        let mut input = ABC;
+        input[INPUT_SIZE - 1] = 0;
+        let output = hex!(
+            "75ab69d3190a562c51aef8d88f1c2775876944407270c42c9844252c26d28752"
+            "98743e7f6d5ea2f2d3e8d226039cd31b4e426ac4f2d3d666a610c2116fde4735"
+        );
+        assert_eq!(blake2f(&input, u64::MAX).unwrap().bytes, output);
+
+        let mut input = ABC;
+        input[3] = 0;
+        let output = hex!(
+            "08c9bcf367e6096a3ba7ca8485ae67bb2bf894fe72f36e3cf1361d5f3af54fa5"
+            "d282e6ad7f520e511f6c3e2b8c68059b9442be0454267ce079217e1319cde05b"
+        );
+        assert_eq!(
+            blake2f(&input, u64::MAX),
+            Ok(PrecompileOutput::new(0, output.to_vec()))
+        );
+    }
+
+    #[test]
+    fn malformed_inputs_are_rejected() {
+        assert!(matches!(
+            blake2f(&[], u64::MAX),
+            Err(PrecompileError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            blake2f(&ABC[..INPUT_SIZE - 1], u64::MAX),
+            Err(PrecompileError::InvalidInput(_))
+        ));
+
+        let mut input = ABC;
+        input[INPUT_SIZE - 1] = 2;
+        assert!(matches!(
+            blake2f(&input, u64::MAX),
+            Err(PrecompileError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn runs_out_of_gas_below_one_per_round() {
+        assert_eq!(blake2f(&ABC, 11), Err(PrecompileError::OutOfGas));
+    }
+}
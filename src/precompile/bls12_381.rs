@@ -0,0 +1,424 @@
+use ark_bls12_381::{
+    g1, g2, Bls12_381, Fq, Fq2, Fr, G1Affine, G1Projective, G2Affine, G2Projective,
+};
+use ark_ec::{
+    hashing::{curve_maps::wb::WBMap, map_to_curve_hasher::MapToCurve},
+    pairing::Pairing,
+    AffineRepr, CurveGroup, VariableBaseMSM,
+};
+use ark_ff::{BigInt, BigInteger, One, PrimeField};
+
+use super::{invalid_input, PrecompileError, PrecompileOutput, PrecompileResult};
+
+const G1_ADD_GAS: u64 = 375;
+const G2_ADD_GAS: u64 = 600;
+const G1_MSM_BASE_GAS: u64 = 12000;
+const G2_MSM_BASE_GAS: u64 = 22500;
+const PAIRING_BASE_GAS: u64 = 37700;
+const PAIRING_PAIR_GAS: u64 = 32600;
+const MAP_FP_TO_G1_GAS: u64 = 5500;
+const MAP_FP2_TO_G2_GAS: u64 = 23800;
+
+/// Field elements are encoded on 64 bytes, the 16 first ones being zeros.
+const FP_SIZE: usize = 64;
+const FP_PADDING: usize = 16;
+const G1_SIZE: usize = 2 * FP_SIZE;
+const G2_SIZE: usize = 4 * FP_SIZE;
+const SCALAR_SIZE: usize = 32;
+
+/// Discounts of the G1 multi-scalar multiplication, per thousand, by number of pairs. Longer
+/// inputs get the last discount.
+const G1_MSM_DISCOUNTS: [u16; 128] = [
+    1000, 949, 848, 797, 764, 750, 738, 728, 719, 712, 705, 698, 692, 687, 682, 677, 673, 669, 665,
+    661, 658, 654, 651, 648, 645, 642, 640, 637, 635, 632, 630, 627, 625, 623, 621, 619, 617, 615,
+    613, 611, 609, 608, 606, 604, 603, 601, 599, 598, 596, 595, 593, 592, 591, 589, 588, 586, 585,
+    584, 582, 581, 580, 579, 577, 576, 575, 574, 573, 572, 570, 569, 568, 567, 566, 565, 564, 563,
+    562, 561, 560, 559, 558, 557, 556, 555, 554, 553, 552, 551, 550, 549, 548, 547, 547, 546, 545,
+    544, 543, 542, 541, 540, 540, 539, 538, 537, 536, 536, 535, 534, 533, 532, 532, 531, 530, 529,
+    528, 528, 527, 526, 525, 525, 524, 523, 522, 522, 521, 520, 520, 519,
+];
+
+/// Discounts of the G2 multi-scalar multiplication, per thousand, by number of pairs.
+const G2_MSM_DISCOUNTS: [u16; 128] = [
+    1000, 1000, 923, 884, 855, 832, 812, 796, 782, 770, 759, 749, 740, 732, 724, 717, 711, 704,
+    699, 693, 688, 683, 679, 674, 670, 666, 663, 659, 655, 652, 649, 646, 643, 640, 637, 634, 632,
+    629, 627, 624, 622, 620, 618, 615, 613, 611, 609, 607, 606, 604, 602, 600, 598, 597, 595, 593,
+    592, 590, 589, 587, 586, 584, 583, 582, 580, 579, 578, 576, 575, 574, 573, 571, 570, 569, 568,
+    567, 566, 565, 563, 562, 561, 560, 559, 558, 557, 556, 555, 554, 553, 552, 552, 551, 550, 549,
+    548, 547, 546, 545, 545, 544, 543, 542, 541, 541, 540, 539, 538, 537, 537, 536, 535, 535, 534,
+    533, 532, 532, 531, 530, 530, 529, 528, 528, 527, 526, 526, 525, 524, 524,
+];
+
+/// Adds two G1 points (BLS12_G1ADD, 0x0b). Points must be on the curve, but not necessarily
+/// in the subgroup.
+pub fn g1_add(input: &[u8], gas_limit: u64) -> PrecompileResult {
+    check_gas(G1_ADD_GAS, gas_limit)?;
+    check_length(input, 2 * G1_SIZE)?;
+
+    let a = read_g1(&input[..G1_SIZE], false)?;
+    let b = read_g1(&input[G1_SIZE..], false)?;
+    let sum = (a.into_group() + b).into_affine();
+    Ok(PrecompileOutput::new(G1_ADD_GAS, encode_g1(sum)))
+}
+
+/// Adds two G2 points (BLS12_G2ADD, 0x0d).
+pub fn g2_add(input: &[u8], gas_limit: u64) -> PrecompileResult {
+    check_gas(G2_ADD_GAS, gas_limit)?;
+    check_length(input, 2 * G2_SIZE)?;
+
+    let a = read_g2(&input[..G2_SIZE], false)?;
+    let b = read_g2(&input[G2_SIZE..], false)?;
+    let sum = (a.into_group() + b).into_affine();
+    Ok(PrecompileOutput::new(G2_ADD_GAS, encode_g2(sum)))
+}
+
+/// Computes the sum of the G1 points of the input multiplied by their scalars
+/// (BLS12_G1MSM, 0x0c).
+pub fn g1_msm(input: &[u8], gas_limit: u64) -> PrecompileResult {
+    let pairs = count_pairs(input, G1_SIZE + SCALAR_SIZE)?;
+    let cost = msm_cost(pairs, G1_MSM_BASE_GAS, &G1_MSM_DISCOUNTS);
+    check_gas(cost, gas_limit)?;
+
+    let mut points = vec![];
+    let mut scalars = vec![];
+    for pair in input.chunks(G1_SIZE + SCALAR_SIZE) {
+        points.push(read_g1(&pair[..G1_SIZE], true)?);
+        scalars.push(read_scalar(&pair[G1_SIZE..]));
+    }
+    let result = G1Projective::msm_unchecked(&points, &scalars).into_affine();
+    Ok(PrecompileOutput::new(cost, encode_g1(result)))
+}
+
+/// Computes the sum of the G2 points of the input multiplied by their scalars
+/// (BLS12_G2MSM, 0x0e).
+pub fn g2_msm(input: &[u8], gas_limit: u64) -> PrecompileResult {
+    let pairs = count_pairs(input, G2_SIZE + SCALAR_SIZE)?;
+    let cost = msm_cost(pairs, G2_MSM_BASE_GAS, &G2_MSM_DISCOUNTS);
+    check_gas(cost, gas_limit)?;
+
+    let mut points = vec![];
+    let mut scalars = vec![];
+    for pair in input.chunks(G2_SIZE + SCALAR_SIZE) {
+        points.push(read_g2(&pair[..G2_SIZE], true)?);
+        scalars.push(read_scalar(&pair[G2_SIZE..]));
+    }
+    let result = G2Projective::msm_unchecked(&points, &scalars).into_affine();
+    Ok(PrecompileOutput::new(cost, encode_g2(result)))
+}
+
+/// Checks that the product of the pairings of the (G1, G2) pairs of the input is one
+/// (BLS12_PAIRING_CHECK, 0x0f), returning 1 or 0 as a word.
+pub fn pairing(input: &[u8], gas_limit: u64) -> PrecompileResult {
+    let pairs = count_pairs(input, G1_SIZE + G2_SIZE)?;
+    let cost = (pairs as u64)
+        .saturating_mul(PAIRING_PAIR_GAS)
+        .saturating_add(PAIRING_BASE_GAS);
+    check_gas(cost, gas_limit)?;
+
+    let mut g1_points = vec![];
+    let mut g2_points = vec![];
+    for pair in input.chunks(G1_SIZE + G2_SIZE) {
+        g1_points.push(read_g1(&pair[..G1_SIZE], true)?);
+        g2_points.push(read_g2(&pair[G1_SIZE..], true)?);
+    }
+    let success = Bls12_381::multi_pairing(g1_points, g2_points).0.is_one();
+
+    let mut output = [0u8; 32];
+    output[31] = success as u8;
+    Ok(PrecompileOutput::new(cost, output.to_vec()))
+}
+
+/// Maps a field element to a G1 point (BLS12_MAP_FP_TO_G1, 0x10).
+pub fn map_fp_to_g1(input: &[u8], gas_limit: u64) -> PrecompileResult {
+    check_gas(MAP_FP_TO_G1_GAS, gas_limit)?;
+    check_length(input, FP_SIZE)?;
+
+    let fp = read_fp(input)?;
+    let point = WBMap::<g1::Config>::map_to_curve(fp)
+        .map_err(|_| invalid_input("field element can't be mapped"))?
+        .clear_cofactor();
+    Ok(PrecompileOutput::new(MAP_FP_TO_G1_GAS, encode_g1(point)))
+}
+
+/// Maps an element of the quadratic extension field to a G2 point (BLS12_MAP_FP2_TO_G2, 0x11).
+pub fn map_fp2_to_g2(input: &[u8], gas_limit: u64) -> PrecompileResult {
+    check_gas(MAP_FP2_TO_G2_GAS, gas_limit)?;
+    check_length(input, 2 * FP_SIZE)?;
+
+    let fp2 = read_fp2(input)?;
+    let point = WBMap::<g2::Config>::map_to_curve(fp2)
+        .map_err(|_| invalid_input("field element can't be mapped"))?
+        .clear_cofactor();
+    Ok(PrecompileOutput::new(MAP_FP2_TO_G2_GAS, encode_g2(point)))
+}
+
+fn check_gas(cost: u64, gas_limit: u64) -> Result<(), PrecompileError> {
+    if cost > gas_limit {
+        return Err(PrecompileError::OutOfGas);
+    }
+    Ok(())
+}
+
+fn check_length(input: &[u8], length: usize) -> Result<(), PrecompileError> {
+    if input.len() != length {
+        return Err(invalid_input("invalid input length"));
+    }
+    Ok(())
+}
+
+/// Returns the number of items of `pair_size` bytes of a non-empty input.
+fn count_pairs(input: &[u8], pair_size: usize) -> Result<usize, PrecompileError> {
+    if input.is_empty() || !input.len().is_multiple_of(pair_size) {
+        return Err(invalid_input("invalid input length"));
+    }
+    Ok(input.len() / pair_size)
+}
+
+fn msm_cost(pairs: usize, base_gas: u64, discounts: &[u16]) -> u64 {
+    let discount = discounts[pairs.min(discounts.len()) - 1] as u64;
+    (pairs as u64)
+        .saturating_mul(base_gas)
+        .saturating_mul(discount)
+        / 1000
+}
+
+/// Reads a field element, rejecting non-zero padding and values past the modulus.
+fn read_fp(bytes: &[u8]) -> Result<Fq, PrecompileError> {
+    if bytes[..FP_PADDING].iter().any(|byte| *byte != 0) {
+        return Err(invalid_input("invalid field element padding"));
+    }
+    // the limbs of the integer are little-endian.
+    let mut limbs = [0u64; 6];
+    for (limb, chunk) in limbs.iter_mut().zip(bytes[FP_PADDING..].rchunks(8)) {
+        *limb = u64::from_be_bytes(chunk.try_into().unwrap());
+    }
+    Fq::from_bigint(BigInt::new(limbs)).ok_or(invalid_input("field element is not canonical"))
+}
+
+/// Reads an element of Fp2 as `c0 || c1`.
+fn read_fp2(bytes: &[u8]) -> Result<Fq2, PrecompileError> {
+    Ok(Fq2::new(
+        read_fp(&bytes[..FP_SIZE])?,
+        read_fp(&bytes[FP_SIZE..2 * FP_SIZE])?,
+    ))
+}
+
+fn read_scalar(bytes: &[u8]) -> Fr {
+    Fr::from_be_bytes_mod_order(bytes)
+}
+
+/// Reads a G1 point as `x || y`, the point at infinity being encoded as zeros.
+fn read_g1(bytes: &[u8], subgroup_check: bool) -> Result<G1Affine, PrecompileError> {
+    let x = read_fp(&bytes[..FP_SIZE])?;
+    let y = read_fp(&bytes[FP_SIZE..])?;
+    if bytes.iter().all(|byte| *byte == 0) {
+        return Ok(G1Affine::identity());
+    }
+
+    let point = G1Affine::new_unchecked(x, y);
+    if !point.is_on_curve() {
+        return Err(invalid_input("point is not on the curve"));
+    }
+    if subgroup_check && !point.is_in_correct_subgroup_assuming_on_curve() {
+        return Err(invalid_input("point is not in the subgroup"));
+    }
+    Ok(point)
+}
+
+/// Reads a G2 point as `x || y`, the point at infinity being encoded as zeros.
+fn read_g2(bytes: &[u8], subgroup_check: bool) -> Result<G2Affine, PrecompileError> {
+    let x = read_fp2(&bytes[..2 * FP_SIZE])?;
+    let y = read_fp2(&bytes[2 * FP_SIZE..])?;
+    if bytes.iter().all(|byte| *byte == 0) {
+        return Ok(G2Affine::identity());
+    }
+
+    let point = G2Affine::new_unchecked(x, y);
+    if !point.is_on_curve() {
+        return Err(invalid_input("point is not on the curve"));
+    }
+    if subgroup_check && !point.is_in_correct_subgroup_assuming_on_curve() {
+        return Err(invalid_input("point is not in the subgroup"));
+    }
+    Ok(point)
+}
+
+fn encode_fp(fp: &Fq) -> Vec<u8> {
+    [&[0u8; FP_PADDING][..], &fp.into_bigint().to_bytes_be()].concat()
+}
+
+fn encode_g1(point: G1Affine) -> Vec<u8> {
+    match point.xy() {
+        Some((x, y)) => [encode_fp(&x), encode_fp(&y)].concat(),
+        None => vec![0u8; G1_SIZE],
+    }
+}
+
+fn encode_g2(point: G2Affine) -> Vec<u8> {
+    match point.xy() {
+        Some((x, y)) => [
+            encode_fp(&x.c0),
+            encode_fp(&x.c1),
+            encode_fp(&y.c0),
+            encode_fp(&y.c1),
+        ]
+        .concat(),
+        None => vec![0u8; G2_SIZE],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    const G1_X: [u8; 48] = hex!("17f1d3a73197d7942695638c4fa9ac0fc3688c4f9774b905a14e3a3f171bac586c55e83ff97a1aeffb3af00adb22c6bb");
+    const G1_Y: [u8; 48] = hex!("08b3f481e3aaa0f1a09e30ed741d8ae4fcf5e095d5d00af600db18cb2c04b3edd03cc744a2888ae40caa232946c5e7e1");
+    /// `p - G1_Y`, the ordinate of the negated generator.
+    const G1_Y_NEG: [u8; 48] = hex!("114d1d6855d545a8aa7d76c8cf2e21f267816aef1db507c96655b9d5caac42364e6f38ba0ecb751bad54dcd6b939c2ca");
+    const G1_DOUBLE_X: [u8; 48] = hex!("0572cbea904d67468808c8eb50a9450c9721db309128012543902d0ac358a62ae28f75bb8f1c7c42c39a8c5529bf0f4e");
+    const G1_DOUBLE_Y: [u8; 48] = hex!("166a9d8cabc673a322fda673779d8e3822ba3ecb8670e461f73bb9021d5fd76a4c56d9d4cd16bd1bba86881979749d28");
+    const G2_X_C0: [u8; 48] = hex!("024aa2b2f08f0a91260805272dc51051c6e47ad4fa403b02b4510b647ae3d1770bac0326a805bbefd48056c8c121bdb8");
+    const G2_X_C1: [u8; 48] = hex!("13e02b6052719f607dacd3a088274f65596bd0d09920b61ab5da61bbdc7f5049334cf11213945d57e5ac7d055d042b7e");
+    const G2_Y_C0: [u8; 48] = hex!("0ce5d527727d6e118cc9cdc6da2e351aadfd9baa8cbdd3a76d429a695160d12c923ac9cc3baca289e193548608b82801");
+    const G2_Y_C1: [u8; 48] = hex!("0606c4a02ea734cc32acd2b02bc28b99cb3e287e85a763af267492ab572e99ab3f370d275cec1da1aaa9075ff05f79be");
+
+    /// Encodes the coordinates of a point, each padded to 64 bytes.
+    fn point(coordinates: &[[u8; 48]]) -> Vec<u8> {
+        coordinates
+            .iter()
+            .flat_map(|fp| [&[0u8; FP_PADDING][..], fp].concat())
+            .collect()
+    }
+
+    fn scalar(value: u8) -> Vec<u8> {
+        let mut scalar = vec![0u8; SCALAR_SIZE];
+        scalar[SCALAR_SIZE - 1] = value;
+        scalar
+    }
+
+    fn g1() -> Vec<u8> {
+        point(&[G1_X, G1_Y])
+    }
+
+    fn g2() -> Vec<u8> {
+        point(&[G2_X_C0, G2_X_C1, G2_Y_C0, G2_Y_C1])
+    }
+
+    #[test]
+    fn g1_add_doubles_the_generator() {
+        let input = [g1(), g1()].concat();
+        assert_eq!(
+            g1_add(&input, u64::MAX),
+            Ok(PrecompileOutput::new(
+                375,
+                point(&[G1_DOUBLE_X, G1_DOUBLE_Y])
+            ))
+        );
+    }
+
+    #[test]
+    fn g1_add_of_opposite_points_is_zero() {
+        let input = [g1(), point(&[G1_X, G1_Y_NEG])].concat();
+        assert_eq!(g1_add(&input, u64::MAX).unwrap().bytes, [0; G1_SIZE]);
+        let input = [g1(), vec![0; G1_SIZE]].concat();
+        assert_eq!(g1_add(&input, u64::MAX).unwrap().bytes, g1());
+    }
+
+    #[test]
+    fn g1_msm_matches_g1_add() {
+        let input = [g1(), scalar(2)].concat();
+        assert_eq!(
+            g1_msm(&input, u64::MAX),
+            Ok(PrecompileOutput::new(
+                12000,
+                point(&[G1_DOUBLE_X, G1_DOUBLE_Y])
+            ))
+        );
+        let input = [g1(), scalar(1), g1(), scalar(1)].concat();
+        assert_eq!(
+            g1_msm(&input, u64::MAX).unwrap().bytes,
+            point(&[G1_DOUBLE_X, G1_DOUBLE_Y])
+        );
+    }
+
+    #[test]
+    fn g2_add_and_msm_agree() {
+        let sum = g2_add(&[g2(), g2()].concat(), u64::MAX).unwrap();
+        assert_eq!(sum.gas_used, 600);
+        let product = g2_msm(&[g2(), scalar(2)].concat(), u64::MAX).unwrap();
+        assert_eq!(product.gas_used, 22500);
+        assert_eq!(sum.bytes, product.bytes);
+
+        let input = [g2(), vec![0; G2_SIZE]].concat();
+        assert_eq!(g2_add(&input, u64::MAX).unwrap().bytes, g2());
+    }
+
+    #[test]
+    fn pairing_of_opposite_points_is_one() {
+        let input = [g1(), g2(), point(&[G1_X, G1_Y_NEG]), g2()].concat();
+        let mut one = vec![0u8; 32];
+        one[31] = 1;
+        assert_eq!(
+            pairing(&input, u64::MAX),
+            Ok(PrecompileOutput::new(102900, one))
+        );
+
+        let input = [g1(), g2()].concat();
+        assert_eq!(pairing(&input, u64::MAX).unwrap().bytes, [0; 32]);
+    }
+
+    #[test]
+    fn mapped_points_are_in_the_subgroup() {
+        let mapped = map_fp_to_g1(&[0; FP_SIZE], u64::MAX).unwrap();
+        assert_eq!(mapped.gas_used, 5500);
+        assert!(g1_msm(&[mapped.bytes, scalar(1)].concat(), u64::MAX).is_ok());
+
+        let mapped = map_fp2_to_g2(&[0; 2 * FP_SIZE], u64::MAX).unwrap();
+        assert_eq!(mapped.gas_used, 23800);
+        assert!(g2_msm(&[mapped.bytes, scalar(1)].concat(), u64::MAX).is_ok());
+    }
+
+    #[test]
+    fn malformed_inputs_are_rejected() {
+        // a point off the curve.
+        let input = [point(&[G1_X, G1_X]), g1()].concat();
+        assert!(matches!(
+            g1_add(&input, u64::MAX),
+            Err(PrecompileError::InvalidInput(_))
+        ));
+
+        // non-zero padding.
+        let mut input = [g1(), g1()].concat();
+        input[0] = 1;
+        assert!(matches!(
+            g1_add(&input, u64::MAX),
+            Err(PrecompileError::InvalidInput(_))
+        ));
+
+        // the modulus is not a field element.
+        let modulus = hex!("1a0111ea397fe69a4b1ba7b6434bacd764774b84f38512bf6730d2a0f6b0f6241eabfffeb153ffffb9feffffffffaaab");
+        assert!(matches!(
+            map_fp_to_g1(&point(&[modulus]), u64::MAX),
+            Err(PrecompileError::InvalidInput(_))
+        ));
+
+        assert!(matches!(
+            g1_msm(&[], u64::MAX),
+            Err(PrecompileError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            pairing(&[g1(), g2()].concat()[1..], u64::MAX),
+            Err(PrecompileError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn runs_out_of_gas_below_the_cost() {
+        let input = [g1(), g1()].concat();
+        assert_eq!(g1_add(&input, 374), Err(PrecompileError::OutOfGas));
+        let input = [g1(), g2()].concat();
+        assert_eq!(pairing(&input, 70299), Err(PrecompileError::OutOfGas));
+    }
+}
@@ -0,0 +1,294 @@
+use bn::{arith, AffineG1, AffineG2, Fq, Fq2, Fr, Group, Gt, G1, G2};
+
+use super::{invalid_input, right_pad, PrecompileError, PrecompileOutput, PrecompileResult};
+
+/// Costs of the precompiles before and since EIP-1108 (Istanbul).
+pub const BYZANTIUM_ADD_GAS: u64 = 500;
+pub const BYZANTIUM_MUL_GAS: u64 = 40000;
+pub const BYZANTIUM_PAIRING_BASE_GAS: u64 = 100000;
+pub const BYZANTIUM_PAIRING_PAIR_GAS: u64 = 80000;
+pub const ISTANBUL_ADD_GAS: u64 = 150;
+pub const ISTANBUL_MUL_GAS: u64 = 6000;
+pub const ISTANBUL_PAIRING_BASE_GAS: u64 = 45000;
+pub const ISTANBUL_PAIRING_PAIR_GAS: u64 = 34000;
+
+/// Size of an encoded G1 point, its two coordinates.
+const G1_SIZE: usize = 64;
+/// Size of an encoded G2 point, its two coordinates in Fq2.
+const G2_SIZE: usize = 128;
+const PAIR_SIZE: usize = G1_SIZE + G2_SIZE;
+
+/// Adds the two G1 points of the input (ECADD, 0x06).
+pub fn add(input: &[u8], gas_limit: u64, cost: u64) -> PrecompileResult {
+    if cost > gas_limit {
+        return Err(PrecompileError::OutOfGas);
+    }
+
+    let input = right_pad::<{ 2 * G1_SIZE }>(input);
+    let p1 = read_g1(&input[..G1_SIZE])?;
+    let p2 = read_g1(&input[G1_SIZE..])?;
+    Ok(PrecompileOutput::new(cost, encode_g1(p1 + p2)))
+}
+
+/// Multiplies the G1 point of the input by a scalar (ECMUL, 0x07).
+pub fn mul(input: &[u8], gas_limit: u64, cost: u64) -> PrecompileResult {
+    if cost > gas_limit {
+        return Err(PrecompileError::OutOfGas);
+    }
+
+    let input = right_pad::<{ G1_SIZE + 32 }>(input);
+    let point = read_g1(&input[..G1_SIZE])?;
+    // scalars are taken modulo the order of the group.
+    let scalar =
+        arith::U256::from_slice(&input[G1_SIZE..]).map_err(|_| invalid_input("invalid scalar"))?;
+    let scalar = Fr::new_mul_factor(scalar);
+    Ok(PrecompileOutput::new(cost, encode_g1(point * scalar)))
+}
+
+/// Checks that the product of the pairings of the (G1, G2) pairs of the input is one
+/// (ECPAIRING, 0x08), returning 1 or 0 as a word.
+pub fn pairing(input: &[u8], gas_limit: u64, base_cost: u64, pair_cost: u64) -> PrecompileResult {
+    let pairs = (input.len() / PAIR_SIZE) as u64;
+    let cost = pairs.saturating_mul(pair_cost).saturating_add(base_cost);
+    if cost > gas_limit {
+        return Err(PrecompileError::OutOfGas);
+    }
+    if !input.len().is_multiple_of(PAIR_SIZE) {
+        return Err(invalid_input("input is not a list of pairs"));
+    }
+
+    let mut points = vec![];
+    for pair in input.chunks(PAIR_SIZE) {
+        let g1 = read_g1(&pair[..G1_SIZE])?;
+        let g2 = read_g2(&pair[G1_SIZE..])?;
+        if !g1.is_zero() && !g2.is_zero() {
+            points.push((g1, g2));
+        }
+    }
+    let success = points.is_empty() || bn::pairing_batch(&points) == Gt::one();
+
+    let mut output = [0u8; 32];
+    output[31] = success as u8;
+    Ok(PrecompileOutput::new(cost, output.to_vec()))
+}
+
+fn read_fq(bytes: &[u8]) -> Result<Fq, PrecompileError> {
+    Fq::from_slice(bytes).map_err(|_| invalid_input("coordinate is not a field element"))
+}
+
+/// Reads a G1 point as `x || y`, the point at infinity being encoded as zeros.
+fn read_g1(bytes: &[u8]) -> Result<G1, PrecompileError> {
+    let x = read_fq(&bytes[..32])?;
+    let y = read_fq(&bytes[32..64])?;
+    if x.is_zero() && y.is_zero() {
+        return Ok(G1::zero());
+    }
+    AffineG1::new(x, y)
+        .map(G1::from)
+        .map_err(|_| invalid_input("point is not on the curve"))
+}
+
+/// Reads a G2 point as `x_imaginary || x_real || y_imaginary || y_real`, checking that it
+/// belongs to the subgroup.
+fn read_g2(bytes: &[u8]) -> Result<G2, PrecompileError> {
+    let x = Fq2::new(read_fq(&bytes[32..64])?, read_fq(&bytes[..32])?);
+    let y = Fq2::new(read_fq(&bytes[96..128])?, read_fq(&bytes[64..96])?);
+    if x.is_zero() && y.is_zero() {
+        return Ok(G2::zero());
+    }
+    AffineG2::new(x, y)
+        .map(G2::from)
+        .map_err(|_| invalid_input("point is not in the G2 subgroup"))
+}
+
+fn encode_g1(point: G1) -> Vec<u8> {
+    let mut output = vec![0u8; G1_SIZE];
+    // the point at infinity has no affine form and is encoded as zeros.
+    if let Some(point) = AffineG1::from_jacobian(point) {
+        point.x().to_big_endian(&mut output[..32]).unwrap();
+        point.y().to_big_endian(&mut output[32..]).unwrap();
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    /// The generator of G1, `(1, 2)`.
+    const G1_GENERATOR: [u8; 64] = hex!(
+        "0000000000000000000000000000000000000000000000000000000000000001"
+        "0000000000000000000000000000000000000000000000000000000000000002"
+    );
+    /// The negated generator of G1, `(1, p - 2)`.
+    const G1_GENERATOR_NEG: [u8; 64] = hex!(
+        "0000000000000000000000000000000000000000000000000000000000000001"
+        "30644e72e131a029b85045b68181585d97816a916871ca8d3c208c16d87cfd45"
+    );
+    /// Twice the generator of G1.
+    const G1_DOUBLE: [u8; 64] = hex!(
+        "030644e72e131a029b85045b68181585d97816a916871ca8d3c208c16d87cfd3"
+        "15ed738c0e0a7c92e7845f96b2ae9c0a68a6a449e3538fc7ff3ebf7a5a18a2c4"
+    );
+    /// The generator of G2.
+    const G2_GENERATOR: [u8; 128] = hex!(
+        "198e9393920d483a7260bfb731fb5d25f1aa493335a9e71297e485b7aef312c2"
+        "1800deef121f1e76426a00665e5c4479674322d4f75edadd46debd5cd992f6ed"
+        "090689d0585ff075ec9e99ad690c3395bc4b313370b38ef355acdadcd122975b"
+        "12c85ea5db8c6deb4aab71808dcb408fe3d1e7690c43d37b4ce6cc0166fa7daa"
+    );
+
+    fn word(value: u8) -> Vec<u8> {
+        let mut word = vec![0u8; 32];
+        word[31] = value;
+        word
+    }
+
+    #[test]
+    fn add_doubles_the_generator() {
+        let input = [G1_GENERATOR, G1_GENERATOR].concat();
+        assert_eq!(
+            add(&input, u64::MAX, ISTANBUL_ADD_GAS),
+            Ok(PrecompileOutput::new(150, G1_DOUBLE.to_vec()))
+        );
+    }
+
+    #[test]
+    fn add_of_the_points_at_infinity_is_zero() {
+        assert_eq!(add(&[], u64::MAX, ISTANBUL_ADD_GAS).unwrap().bytes, [0; 64]);
+        let input = [G1_GENERATOR, G1_GENERATOR_NEG].concat();
+        assert_eq!(
+            add(&input, u64::MAX, ISTANBUL_ADD_GAS).unwrap().bytes,
+            [0; 64]
+        );
+    }
+
+    #[test]
+    fn mul_by_scalars() {
+        let input = [&G1_GENERATOR[..], &word(2)].concat();
+        assert_eq!(
+            mul(&input, u64::MAX, ISTANBUL_MUL_GAS),
+            Ok(PrecompileOutput::new(6000, G1_DOUBLE.to_vec()))
+        );
+
+        let triple = hex!(
+            "0769bf9ac56bea3ff40232bcb1b6bd159315d84715b8e679f2d355961915abf0"
+            "2ab799bee0489429554fdb7c8d086475319e63b40b9c5b57cdf1ff3dd9fe2261"
+        );
+        let input = [&G1_GENERATOR[..], &word(3)].concat();
+        assert_eq!(
+            mul(&input, u64::MAX, ISTANBUL_MUL_GAS).unwrap().bytes,
+            triple
+        );
+
+        // the order of the group gives the point at infinity.
+        let order = hex!("30644e72e131a029b85045b68181585d2833e84879b9709143e1f593f0000001");
+        let input = [&G1_GENERATOR[..], &order].concat();
+        assert_eq!(
+            mul(&input, u64::MAX, ISTANBUL_MUL_GAS).unwrap().bytes,
+            [0; 64]
+        );
+    }
+
+    #[test]
+    fn points_off_the_curve_are_rejected() {
+        let mut input = [G1_GENERATOR, G1_GENERATOR].concat();
+        input[63] = 1;
+        assert!(matches!(
+            add(&input, u64::MAX, ISTANBUL_ADD_GAS),
+            Err(PrecompileError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            mul(&input[..96], u64::MAX, ISTANBUL_MUL_GAS),
+            Err(PrecompileError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn pairing_of_opposite_points_is_one() {
+        let input = [
+            &G1_GENERATOR[..],
+            &G2_GENERATOR,
+            &G1_GENERATOR_NEG,
+            &G2_GENERATOR,
+        ]
+        .concat();
+        assert_eq!(
+            pairing(
+                &input,
+                u64::MAX,
+                ISTANBUL_PAIRING_BASE_GAS,
+                ISTANBUL_PAIRING_PAIR_GAS
+            ),
+            Ok(PrecompileOutput::new(113000, word(1)))
+        );
+    }
+
+    #[test]
+    fn pairing_of_equal_points_is_not_one() {
+        let input = [
+            &G1_GENERATOR[..],
+            &G2_GENERATOR,
+            &G1_GENERATOR,
+            &G2_GENERATOR,
+        ]
+        .concat();
+        assert_eq!(
+            pairing(
+                &input,
+                u64::MAX,
+                ISTANBUL_PAIRING_BASE_GAS,
+                ISTANBUL_PAIRING_PAIR_GAS
+            )
+            .unwrap()
+            .bytes,
+            word(0)
+        );
+    }
+
+    #[test]
+    fn empty_pairing_is_one() {
+        assert_eq!(
+            pairing(
+                &[],
+                u64::MAX,
+                BYZANTIUM_PAIRING_BASE_GAS,
+                BYZANTIUM_PAIRING_PAIR_GAS
+            ),
+            Ok(PrecompileOutput::new(100000, word(1)))
+        );
+    }
+
+    #[test]
+    fn pairing_input_must_be_a_list_of_pairs() {
+        assert!(matches!(
+            pairing(
+                &[0; 191],
+                u64::MAX,
+                ISTANBUL_PAIRING_BASE_GAS,
+                ISTANBUL_PAIRING_PAIR_GAS
+            ),
+            Err(PrecompileError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn runs_out_of_gas_below_the_cost() {
+        assert_eq!(
+            add(&[], 149, ISTANBUL_ADD_GAS),
+            Err(PrecompileError::OutOfGas)
+        );
+        let input = [&G1_GENERATOR[..], &G2_GENERATOR].concat();
+        assert_eq!(
+            pairing(
+                &input,
+                78999,
+                ISTANBUL_PAIRING_BASE_GAS,
+                ISTANBUL_PAIRING_PAIR_GAS
+            ),
+            Err(PrecompileError::OutOfGas)
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use crate::utils::recover_address;
+
+use super::{right_pad, PrecompileError, PrecompileOutput, PrecompileResult};
+
+const ECRECOVER_GAS: u64 = 3000;
+
+/// Recovers the address that signed a hash from the input `hash || v || r || s`, returning
+/// it left-padded to 32 bytes, or nothing if the signature is invalid.
+pub fn ecrecover(input: &[u8], gas_limit: u64) -> PrecompileResult {
+    if gas_limit < ECRECOVER_GAS {
+        return Err(PrecompileError::OutOfGas);
+    }
+
+    let input = right_pad::<128>(input);
+    let word =
+        |index: usize| -> [u8; 32] { input[index * 32..(index + 1) * 32].try_into().unwrap() };
+    let (hash, v, r, s) = (word(0), word(1), word(2), word(3));
+
+    // `v` is a full word that must be 27 or 28.
+    if v[..31].iter().any(|byte| *byte != 0) || !matches!(v[31], 27 | 28) {
+        return Ok(PrecompileOutput::new(ECRECOVER_GAS, vec![]));
+    }
+
+    let output = match recover_address(&hash, v[31] - 27, &r, &s) {
+        Some(address) => [&[0u8; 12][..], &address].concat(),
+        None => vec![],
+    };
+    Ok(PrecompileOutput::new(ECRECOVER_GAS, output))
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    /// A signature of the go-ethereum precompile tests, `hash || v || r || s`.
+    const SIGNED: [u8; 128] = hex!(
+        "456e9aea5e197a1f1af7a3e85a3212fa4049a3ba34c2289b4c860fc0b0c64ef3"
+        "000000000000000000000000000000000000000000000000000000000000001c"
+        "9242685bf161793cc25603c231bc2f568eb630ea16aa137d2664ac8038825608"
+        "4f8ae3bd7535248d0bd448298cc2e2071e56992d0774dc340c368ae950852ada"
+    );
+
+    #[test]
+    fn recovers_the_signer() {
+        let signer = hex!("0000000000000000000000007156526fbd7a3c72969b54f64e42c10fbb768c8a");
+        assert_eq!(
+            ecrecover(&SIGNED, 3000),
+            Ok(PrecompileOutput::new(3000, signer.to_vec()))
+        );
+    }
+
+    #[test]
+    fn invalid_v_returns_nothing() {
+        let mut input = SIGNED;
+        input[63] = 0x1d;
+        assert_eq!(
+            ecrecover(&input, 3000),
+            Ok(PrecompileOutput::new(3000, vec![]))
+        );
+
+        // `v` is read as a whole word.
+        let mut input = SIGNED;
+        input[32] = 1;
+        assert_eq!(
+            ecrecover(&input, 3000),
+            Ok(PrecompileOutput::new(3000, vec![]))
+        );
+    }
+
+    #[test]
+    fn zero_signature_returns_nothing() {
+        let mut input = SIGNED;
+        input[64..].fill(0);
+        assert_eq!(
+            ecrecover(&input, 3000),
+            Ok(PrecompileOutput::new(3000, vec![]))
+        );
+        // a short input is padded with zeros.
+        assert_eq!(
+            ecrecover(&[], 3000),
+            Ok(PrecompileOutput::new(3000, vec![]))
+        );
+    }
+
+    #[test]
+    fn runs_out_of_gas_below_3000() {
+        assert_eq!(ecrecover(&SIGNED, 2999), Err(PrecompileError::OutOfGas));
+    }
+}
@@ -0,0 +1,84 @@
+use ripemd::Ripemd160;
+use sha2::{Digest, Sha256};
+
+use super::{linear_cost, PrecompileError, PrecompileOutput, PrecompileResult};
+
+/// Returns the SHA-256 hash of the input.
+pub fn sha256(input: &[u8], gas_limit: u64) -> PrecompileResult {
+    let cost = linear_cost(input.len(), 60, 12);
+    if cost > gas_limit {
+        return Err(PrecompileError::OutOfGas);
+    }
+    Ok(PrecompileOutput::new(cost, Sha256::digest(input).to_vec()))
+}
+
+/// Returns the RIPEMD-160 hash of the input, left-padded to 32 bytes.
+pub fn ripemd160(input: &[u8], gas_limit: u64) -> PrecompileResult {
+    let cost = linear_cost(input.len(), 600, 120);
+    if cost > gas_limit {
+        return Err(PrecompileError::OutOfGas);
+    }
+    let output = [&[0u8; 12][..], &Ripemd160::digest(input)].concat();
+    Ok(PrecompileOutput::new(cost, output))
+}
+
+/// Returns the input as is.
+pub fn identity(input: &[u8], gas_limit: u64) -> PrecompileResult {
+    let cost = linear_cost(input.len(), 15, 3);
+    if cost > gas_limit {
+        return Err(PrecompileError::OutOfGas);
+    }
+    Ok(PrecompileOutput::new(cost, input.to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn sha256_of_known_inputs() {
+        let empty = hex!("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(
+            sha256(&[], u64::MAX),
+            Ok(PrecompileOutput::new(60, empty.to_vec()))
+        );
+        let abc = hex!("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(
+            sha256(b"abc", u64::MAX),
+            Ok(PrecompileOutput::new(72, abc.to_vec()))
+        );
+    }
+
+    #[test]
+    fn ripemd160_of_known_inputs() {
+        let empty = hex!("0000000000000000000000009c1185a5c5e9fc54612808977ee8f548b2258d31");
+        assert_eq!(
+            ripemd160(&[], u64::MAX),
+            Ok(PrecompileOutput::new(600, empty.to_vec()))
+        );
+        let abc = hex!("0000000000000000000000008eb208f7e05d987a9b044a8e98c6b087f15a0bfc");
+        assert_eq!(
+            ripemd160(b"abc", u64::MAX),
+            Ok(PrecompileOutput::new(720, abc.to_vec()))
+        );
+    }
+
+    #[test]
+    fn identity_returns_the_input() {
+        let input = [0xab; 33];
+        assert_eq!(
+            identity(&input, u64::MAX),
+            Ok(PrecompileOutput::new(21, input.to_vec()))
+        );
+    }
+
+    #[test]
+    fn cost_is_charged_per_started_word() {
+        assert_eq!(sha256(&[0; 33], 83), Err(PrecompileError::OutOfGas));
+        assert_eq!(sha256(&[0; 33], 84).unwrap().gas_used, 84);
+        assert_eq!(ripemd160(&[0; 32], 719), Err(PrecompileError::OutOfGas));
+        assert_eq!(identity(&[0; 64], 20), Err(PrecompileError::OutOfGas));
+    }
+}
@@ -0,0 +1,133 @@
+use c_kzg::{ethereum_kzg_settings, Bytes32, Bytes48};
+use sha2::{Digest, Sha256};
+
+use super::{invalid_input, PrecompileError, PrecompileOutput, PrecompileResult};
+
+const POINT_EVALUATION_GAS: u64 = 50000;
+/// Size of the input: `versioned_hash || z || y || commitment || proof`.
+const INPUT_SIZE: usize = 32 + 32 + 32 + 48 + 48;
+const VERSIONED_HASH_VERSION_KZG: u8 = 0x01;
+
+/// The output of the precompile: `FIELD_ELEMENTS_PER_BLOB || BLS_MODULUS` as two words.
+const RETURN_VALUE: [u8; 64] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00,
+    0x73, 0xed, 0xa7, 0x53, 0x29, 0x9d, 0x7d, 0x48, 0x33, 0x39, 0xd8, 0x08, 0x09, 0xa1, 0xd8, 0x05,
+    0x53, 0xbd, 0xa4, 0x02, 0xff, 0xfe, 0x5b, 0xfe, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x01,
+];
+
+/// Verifies that the blob committed to by `commitment`, whose versioned hash is given,
+/// evaluates to `y` at `z` (EIP-4844), using the mainnet trusted setup.
+pub fn point_evaluation(input: &[u8], gas_limit: u64) -> PrecompileResult {
+    if POINT_EVALUATION_GAS > gas_limit {
+        return Err(PrecompileError::OutOfGas);
+    }
+    if input.len() != INPUT_SIZE {
+        return Err(invalid_input("input must be 192 bytes long"));
+    }
+
+    let versioned_hash = &input[..32];
+    let z: [u8; 32] = input[32..64].try_into().unwrap();
+    let y: [u8; 32] = input[64..96].try_into().unwrap();
+    let commitment: [u8; 48] = input[96..144].try_into().unwrap();
+    let proof: [u8; 48] = input[144..].try_into().unwrap();
+
+    if versioned_hash != kzg_to_versioned_hash(&commitment) {
+        return Err(invalid_input(
+            "versioned hash does not match the commitment",
+        ));
+    }
+
+    let verified = ethereum_kzg_settings(0).verify_kzg_proof(
+        &Bytes48::from(commitment),
+        &Bytes32::from(z),
+        &Bytes32::from(y),
+        &Bytes48::from(proof),
+    );
+    if !matches!(verified, Ok(true)) {
+        return Err(invalid_input("invalid KZG proof"));
+    }
+
+    Ok(PrecompileOutput::new(
+        POINT_EVALUATION_GAS,
+        RETURN_VALUE.to_vec(),
+    ))
+}
+
+/// Returns the versioned hash of a commitment: its SHA-256 hash, versioned by its first byte.
+fn kzg_to_versioned_hash(commitment: &[u8]) -> [u8; 32] {
+    let mut hash: [u8; 32] = Sha256::digest(commitment).into();
+    hash[0] = VERSIONED_HASH_VERSION_KZG;
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    /// The versioned hash of the commitment to the zero polynomial, the point at infinity.
+    const ZERO_VERSIONED_HASH: [u8; 32] =
+        hex!("010657f37554c781402a22917dee2f75def7ab966d7b770905398eba3c444014");
+    const INFINITY: [u8; 48] = hex!(
+        "c00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000"
+    );
+
+    /// Returns the input claiming that the zero polynomial evaluates to `y` at `z`.
+    fn zero_polynomial(z: u8, y: u8) -> Vec<u8> {
+        let (mut z_word, mut y_word) = ([0u8; 32], [0u8; 32]);
+        z_word[31] = z;
+        y_word[31] = y;
+        [
+            &ZERO_VERSIONED_HASH[..],
+            &z_word,
+            &y_word,
+            &INFINITY,
+            &INFINITY,
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn verifies_the_evaluation_of_the_zero_polynomial() {
+        assert_eq!(
+            point_evaluation(&zero_polynomial(2, 0), u64::MAX),
+            Ok(PrecompileOutput::new(50000, RETURN_VALUE.to_vec()))
+        );
+    }
+
+    #[test]
+    fn wrong_evaluation_is_rejected() {
+        assert!(matches!(
+            point_evaluation(&zero_polynomial(2, 1), u64::MAX),
+            Err(PrecompileError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn versioned_hash_must_match_the_commitment() {
+        let mut input = zero_polynomial(2, 0);
+        input[0] = 0x02;
+        assert!(matches!(
+            point_evaluation(&input, u64::MAX),
+            Err(PrecompileError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn input_must_be_192_bytes_long() {
+        assert!(matches!(
+            point_evaluation(&zero_polynomial(2, 0)[..191], u64::MAX),
+            Err(PrecompileError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn runs_out_of_gas_below_50000() {
+        assert_eq!(
+            point_evaluation(&zero_polynomial(2, 0), 49999),
+            Err(PrecompileError::OutOfGas)
+        );
+    }
+}
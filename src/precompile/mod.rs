@@ -0,0 +1,269 @@
+mod blake2;
+mod bls12_381;
+mod bn128;
+mod ecrecover;
+mod hash;
+mod kzg;
+mod modexp;
+mod p256;
+
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use thiserror::Error;
+
+use crate::{primitives::Address, spec::SpecId};
+
+pub use p256::{p256_verify, P256_VERIFY_ADDRESS};
+
+/// Output of a successful precompile call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrecompileOutput {
+    pub gas_used: u64,
+    pub bytes: Vec<u8>,
+}
+
+impl PrecompileOutput {
+    pub fn new(gas_used: u64, bytes: Vec<u8>) -> PrecompileOutput {
+        PrecompileOutput { gas_used, bytes }
+    }
+}
+
+/// Errors of a precompile call, consuming all the gas given to it.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum PrecompileError {
+    #[error("out of gas")]
+    OutOfGas,
+    #[error("invalid input: {0}")]
+    InvalidInput(String),
+}
+
+pub type PrecompileResult = Result<PrecompileOutput, PrecompileError>;
+
+/// A native contract, called with the input of the call and the gas made available to it.
+pub type Precompile = Arc<dyn Fn(&[u8], u64) -> PrecompileResult + Send + Sync>;
+
+/// The precompiled contracts of a chain, keyed by address.
+///
+/// Cloning the registry is cheap, the precompiles being shared.
+#[derive(Clone, Default)]
+pub struct Precompiles {
+    precompiles: Arc<HashMap<Address, Precompile>>,
+}
+
+impl Precompiles {
+    /// Returns the precompiles of the Ethereum mainnet at the given hard fork.
+    pub fn new(spec: SpecId) -> Precompiles {
+        let mut precompiles = Precompiles::default();
+        precompiles.insert(1.into(), ecrecover::ecrecover);
+        precompiles.insert(2.into(), hash::sha256);
+        precompiles.insert(3.into(), hash::ripemd160);
+        precompiles.insert(4.into(), hash::identity);
+
+        if spec.is_enabled_in(SpecId::Osaka) {
+            precompiles.insert(5.into(), modexp::osaka);
+        } else if spec.is_enabled_in(SpecId::Berlin) {
+            precompiles.insert(5.into(), modexp::berlin);
+        } else if spec.is_enabled_in(SpecId::Byzantium) {
+            precompiles.insert(5.into(), modexp::byzantium);
+        }
+
+        if spec.is_enabled_in(SpecId::Byzantium) {
+            let (add_gas, mul_gas, pairing_base_gas, pairing_pair_gas) =
+                if spec.is_enabled_in(SpecId::Istanbul) {
+                    (
+                        bn128::ISTANBUL_ADD_GAS,
+                        bn128::ISTANBUL_MUL_GAS,
+                        bn128::ISTANBUL_PAIRING_BASE_GAS,
+                        bn128::ISTANBUL_PAIRING_PAIR_GAS,
+                    )
+                } else {
+                    (
+                        bn128::BYZANTIUM_ADD_GAS,
+                        bn128::BYZANTIUM_MUL_GAS,
+                        bn128::BYZANTIUM_PAIRING_BASE_GAS,
+                        bn128::BYZANTIUM_PAIRING_PAIR_GAS,
+                    )
+                };
+            precompiles.insert(6.into(), move |input: &[u8], gas_limit| {
+                bn128::add(input, gas_limit, add_gas)
+            });
+            precompiles.insert(7.into(), move |input: &[u8], gas_limit| {
+                bn128::mul(input, gas_limit, mul_gas)
+            });
+            precompiles.insert(8.into(), move |input: &[u8], gas_limit| {
+                bn128::pairing(input, gas_limit, pairing_base_gas, pairing_pair_gas)
+            });
+        }
+
+        if spec.is_enabled_in(SpecId::Istanbul) {
+            precompiles.insert(9.into(), blake2::blake2f);
+        }
+
+        if spec.is_enabled_in(SpecId::Cancun) {
+            precompiles.insert(0x0a.into(), kzg::point_evaluation);
+        }
+
+        if spec.is_enabled_in(SpecId::Prague) {
+            precompiles.insert(0x0b.into(), bls12_381::g1_add);
+            precompiles.insert(0x0c.into(), bls12_381::g1_msm);
+            precompiles.insert(0x0d.into(), bls12_381::g2_add);
+            precompiles.insert(0x0e.into(), bls12_381::g2_msm);
+            precompiles.insert(0x0f.into(), bls12_381::pairing);
+            precompiles.insert(0x10.into(), bls12_381::map_fp_to_g1);
+            precompiles.insert(0x11.into(), bls12_381::map_fp2_to_g2);
+        }
+        precompiles
+    }
+
+    /// Registers the secp256r1 signature verification precompile of RIP-7212 at its address,
+    /// for the chains that adopted it.
+    pub fn enable_p256_verify(&mut self) {
+        self.insert(P256_VERIFY_ADDRESS, p256_verify);
+    }
+
+    /// Registers a precompile at `address`, returning the one it replaces.
+    pub fn insert(
+        &mut self,
+        address: Address,
+        precompile: impl Fn(&[u8], u64) -> PrecompileResult + Send + Sync + 'static,
+    ) -> Option<Precompile> {
+        Arc::make_mut(&mut self.precompiles).insert(address, Arc::new(precompile))
+    }
+
+    /// Unregisters the precompile at `address`.
+    pub fn remove(&mut self, address: Address) -> Option<Precompile> {
+        Arc::make_mut(&mut self.precompiles).remove(&address)
+    }
+
+    pub fn get(&self, address: Address) -> Option<&Precompile> {
+        self.precompiles.get(&address)
+    }
+
+    pub fn contains(&self, address: Address) -> bool {
+        self.precompiles.contains_key(&address)
+    }
+
+    /// Returns the addresses of the registered precompiles, in ascending order.
+    pub fn addresses(&self) -> Vec<Address> {
+        let mut addresses: Vec<Address> = self.precompiles.keys().copied().collect();
+        addresses.sort();
+        addresses
+    }
+}
+
+fn invalid_input(reason: &str) -> PrecompileError {
+    PrecompileError::InvalidInput(reason.to_string())
+}
+
+/// Returns the cost of a precompile charging `base` gas plus `word` gas per 32-byte word of
+/// input.
+fn linear_cost(len: usize, base: u64, word: u64) -> u64 {
+    let words = (len as u64).div_ceil(32);
+    base.saturating_add(words.saturating_mul(word))
+}
+
+/// Returns the input padded with zeros, or truncated, to `N` bytes.
+fn right_pad<const N: usize>(input: &[u8]) -> [u8; N] {
+    let mut padded = [0u8; N];
+    let len = input.len().min(N);
+    padded[..len].copy_from_slice(&input[..len]);
+    padded
+}
+
+impl fmt::Debug for Precompiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Precompiles")
+            .field("addresses", &self.addresses())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EvmBuilder;
+
+    fn addresses(last: u64) -> Vec<Address> {
+        (1..=last).map(Address::from).collect()
+    }
+
+    #[test]
+    fn registers_the_precompiles_of_each_fork() {
+        assert_eq!(Precompiles::new(SpecId::Frontier).addresses(), addresses(4));
+        assert_eq!(
+            Precompiles::new(SpecId::Byzantium).addresses(),
+            addresses(8)
+        );
+        assert_eq!(Precompiles::new(SpecId::Istanbul).addresses(), addresses(9));
+        assert_eq!(
+            Precompiles::new(SpecId::Cancun).addresses(),
+            addresses(0x0a)
+        );
+        assert_eq!(
+            Precompiles::new(SpecId::Prague).addresses(),
+            addresses(0x11)
+        );
+    }
+
+    #[test]
+    fn prices_follow_the_fork() {
+        let add = |spec| {
+            let precompiles = Precompiles::new(spec);
+            let add = precompiles.get(Address::from(6)).unwrap();
+            add(&[], u64::MAX).unwrap().gas_used
+        };
+        assert_eq!(add(SpecId::Byzantium), 500);
+        assert_eq!(add(SpecId::Istanbul), 150);
+    }
+
+    #[test]
+    fn p256_verify_is_opt_in() {
+        let mut precompiles = Precompiles::new(SpecId::Prague);
+        assert!(!precompiles.contains(P256_VERIFY_ADDRESS));
+        precompiles.enable_p256_verify();
+        assert!(precompiles.contains(P256_VERIFY_ADDRESS));
+    }
+
+    #[test]
+    fn registry_replaces_and_removes_precompiles() {
+        fn out_of_gas(_: &[u8], _: u64) -> PrecompileResult {
+            Err(PrecompileError::OutOfGas)
+        }
+        let address = Address::from(0x42);
+        let mut precompiles = Precompiles::new(SpecId::Cancun);
+        assert!(precompiles.insert(address, out_of_gas).is_none());
+        assert!(precompiles.insert(address, out_of_gas).is_some());
+        assert!(precompiles.contains(address));
+        assert!(precompiles.remove(address).is_some());
+        assert!(!precompiles.contains(address));
+    }
+
+    #[test]
+    fn calls_reach_registered_precompiles() {
+        let mut precompiles = Precompiles::new(SpecId::Cancun);
+        precompiles.insert(Address::from(0x42), |_: &[u8], _| {
+            Ok(PrecompileOutput::new(10, vec![0xab]))
+        });
+        // STATICCALL 0x42 writing one byte of output at 0, then return it.
+        let code = [
+            0x60, 0x01, 0x5f, 0x5f, 0x5f, 0x60, 0x42, 0x5a, 0xfa, 0x50, 0x60, 0x01, 0x5f, 0xf3,
+        ];
+        let result = EvmBuilder::new()
+            .spec(SpecId::Cancun)
+            .precompiles(precompiles.clone())
+            .code(code)
+            .run()
+            .unwrap();
+        assert_eq!(result.ret, [0xab]);
+
+        // an unregistered address is an empty account.
+        precompiles.remove(Address::from(0x42));
+        let result = EvmBuilder::new()
+            .spec(SpecId::Cancun)
+            .precompiles(precompiles)
+            .code(code)
+            .run()
+            .unwrap();
+        assert_eq!(result.ret, [0x00]);
+    }
+}
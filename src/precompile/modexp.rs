@@ -0,0 +1,311 @@
+use num_bigint::BigUint;
+use primitive_types::U256;
+
+use super::{PrecompileError, PrecompileOutput, PrecompileResult};
+
+/// Computes `base ** exponent % modulus` with the gas formula of EIP-198.
+pub fn byzantium(input: &[u8], gas_limit: u64) -> PrecompileResult {
+    modexp(input, gas_limit, byzantium_gas, None)
+}
+
+/// Computes `base ** exponent % modulus` with the gas formula of EIP-2565.
+pub fn berlin(input: &[u8], gas_limit: u64) -> PrecompileResult {
+    modexp(input, gas_limit, berlin_gas, None)
+}
+
+/// Computes `base ** exponent % modulus` with the gas formula of EIP-7883, rejecting the
+/// operands longer than [`MAX_LENGTH`] (EIP-7823).
+pub fn osaka(input: &[u8], gas_limit: u64) -> PrecompileResult {
+    modexp(input, gas_limit, osaka_gas, Some(MAX_LENGTH))
+}
+
+/// The largest base, exponent and modulus lengths accepted since Osaka (EIP-7823).
+const MAX_LENGTH: u128 = 1024;
+
+/// A gas formula, taking the largest of the base and modulus lengths, the exponent length and
+/// the first 32 bytes of the exponent.
+type GasFn = fn(u128, u128, &[u8]) -> u128;
+
+/// Runs the precompile with the given gas formula, rejecting the lengths past `max_length`.
+///
+/// The input is `base_len || exponent_len || modulus_len || base || exponent || modulus`, the
+/// lengths being 32-byte words and missing bytes being zeros. Every length an operand is
+/// allocated with is priced, so the gas is charged before anything is allocated and the
+/// memory taken is bounded by the gas the call can afford.
+fn modexp(input: &[u8], gas_limit: u64, gas: GasFn, max_length: Option<u128>) -> PrecompileResult {
+    // lengths past a `u128` cost more than any gas limit.
+    let length = |index: usize| {
+        let length = U256::from_big_endian(&read_padded(input, index * 32, 32));
+        let length = u128::try_from(length).unwrap_or(u128::MAX);
+        match max_length {
+            Some(max_length) if length > max_length => Err(PrecompileError::InvalidInput(format!(
+                "operand lengths must be at most {max_length} bytes"
+            ))),
+            _ => Ok(length),
+        }
+    };
+    let (base_len, exponent_len, modulus_len) = (length(0)?, length(1)?, length(2)?);
+
+    // the exponent only matters through its length and its first word.
+    let max_len = base_len.max(modulus_len);
+    let exponent_offset = 96usize.saturating_add(usize::try_from(base_len).unwrap_or(usize::MAX));
+    let exponent_head = read_padded(input, exponent_offset, exponent_len.min(32) as usize);
+
+    let cost = gas(max_len, exponent_len, &exponent_head);
+    if cost > gas_limit as u128 {
+        return Err(PrecompileError::OutOfGas);
+    }
+    let cost = cost as u64;
+
+    if base_len == 0 && modulus_len == 0 {
+        return Ok(PrecompileOutput::new(cost, vec![]));
+    }
+
+    // the lengths are affordable, but may still be too large for the memory of the host.
+    let to_usize = |length: u128| usize::try_from(length).map_err(|_| PrecompileError::OutOfGas);
+    let (base_len, exponent_len, modulus_len) = (
+        to_usize(base_len)?,
+        to_usize(exponent_len)?,
+        to_usize(modulus_len)?,
+    );
+
+    // the output is left-padded to the length of the modulus.
+    let mut output = Vec::new();
+    output
+        .try_reserve_exact(modulus_len)
+        .map_err(|_| PrecompileError::OutOfGas)?;
+    output.resize(modulus_len, 0);
+    let modulus = operand(
+        input,
+        exponent_offset.saturating_add(exponent_len),
+        modulus_len,
+    );
+    if modulus != BigUint::ZERO {
+        let base = operand(input, 96, base_len);
+        let exponent = operand(input, exponent_offset, exponent_len);
+        let result = base.modpow(&exponent, &modulus).to_bytes_be();
+        output[modulus_len - result.len()..].copy_from_slice(&result);
+    }
+    Ok(PrecompileOutput::new(cost, output))
+}
+
+/// EIP-198: `mult_complexity(max_len) * max(adjusted_exponent_len, 1) / 20`.
+fn byzantium_gas(max_len: u128, exponent_len: u128, exponent_head: &[u8]) -> u128 {
+    let complexity = if max_len <= 64 {
+        max_len * max_len
+    } else if max_len <= 1024 {
+        (max_len * max_len / 4 + 96 * max_len).saturating_sub(3072)
+    } else {
+        (max_len.saturating_mul(max_len) / 16)
+            .saturating_add(max_len.saturating_mul(480))
+            .saturating_sub(199680)
+    };
+    let adjusted_exponent_len = adjusted_exponent_len(exponent_len, exponent_head, 8);
+    complexity.saturating_mul(adjusted_exponent_len.max(1)) / 20
+}
+
+/// EIP-2565: `max(200, ceil(max_len / 8) ** 2 * max(adjusted_exponent_len, 1) / 3)`.
+fn berlin_gas(max_len: u128, exponent_len: u128, exponent_head: &[u8]) -> u128 {
+    let words = max_len.div_ceil(8);
+    let complexity = words.saturating_mul(words);
+    let adjusted_exponent_len = adjusted_exponent_len(exponent_len, exponent_head, 8);
+    let cost = complexity.saturating_mul(adjusted_exponent_len.max(1)) / 3;
+    cost.max(200)
+}
+
+/// EIP-7883: `max(500, complexity * max(iteration_count, 1))`, the complexity being 16 up to
+/// 32 bytes and `2 * ceil(max_len / 8) ** 2` past them, and each exponent byte past the first
+/// 32 counting 16 iterations.
+fn osaka_gas(max_len: u128, exponent_len: u128, exponent_head: &[u8]) -> u128 {
+    let complexity = if max_len <= 32 {
+        16
+    } else {
+        let words = max_len.div_ceil(8);
+        words.saturating_mul(words).saturating_mul(2)
+    };
+    let iteration_count = adjusted_exponent_len(exponent_len, exponent_head, 16);
+    complexity.saturating_mul(iteration_count.max(1)).max(500)
+}
+
+/// Returns the length of the exponent used for pricing: the index of its highest bit for
+/// exponents of up to 32 bytes, plus `multiplier` per byte for longer ones.
+fn adjusted_exponent_len(exponent_len: u128, exponent_head: &[u8], multiplier: u128) -> u128 {
+    let head_bits = U256::from_big_endian(exponent_head).bits() as u128;
+    let head_index = head_bits.saturating_sub(1);
+    if exponent_len <= 32 {
+        head_index
+    } else {
+        (exponent_len - 32)
+            .saturating_mul(multiplier)
+            .saturating_add(head_index)
+    }
+}
+
+/// Returns `len` bytes of the input from `offset`, the bytes past its end being zeros.
+fn read_padded(input: &[u8], offset: usize, len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    if let Some(available) = input.get(offset..) {
+        let copied = available.len().min(len);
+        bytes[..copied].copy_from_slice(&available[..copied]);
+    }
+    bytes
+}
+
+/// Reads the operand of `len` bytes at `offset`, copying only the bytes present in the input:
+/// the missing ones are zeros, which shift the present ones left.
+fn operand(input: &[u8], offset: usize, len: usize) -> BigUint {
+    let available = input.get(offset..).unwrap_or_default();
+    let present = &available[..available.len().min(len)];
+    let missing = (len - present.len()) as u64;
+    BigUint::from_bytes_be(present) << missing.saturating_mul(8)
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    /// `3 ** (p - 2) % p` for the secp256k1 field prime `p`, the first example of EIP-198.
+    const FERMAT: [u8; 161] = hex!(
+        "0000000000000000000000000000000000000000000000000000000000000001"
+        "0000000000000000000000000000000000000000000000000000000000000020"
+        "0000000000000000000000000000000000000000000000000000000000000020"
+        "03"
+        "fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2e"
+        "fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f"
+    );
+
+    #[test]
+    fn eip_198_example() {
+        let one = hex!("0000000000000000000000000000000000000000000000000000000000000001");
+        assert_eq!(
+            byzantium(&FERMAT, u64::MAX),
+            Ok(PrecompileOutput::new(13056, one.to_vec()))
+        );
+        assert_eq!(
+            berlin(&FERMAT, u64::MAX),
+            Ok(PrecompileOutput::new(1360, one.to_vec()))
+        );
+    }
+
+    #[test]
+    fn missing_base_reads_zero() {
+        // the second example of EIP-198, the base length being zero.
+        let input = hex!(
+            "0000000000000000000000000000000000000000000000000000000000000000"
+            "0000000000000000000000000000000000000000000000000000000000000020"
+            "0000000000000000000000000000000000000000000000000000000000000020"
+            "fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2e"
+            "fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f"
+        );
+        let output = berlin(&input, u64::MAX).unwrap();
+        assert_eq!(output.bytes, [0; 32]);
+    }
+
+    #[test]
+    fn short_gas_limit_runs_out_of_gas() {
+        assert_eq!(berlin(&FERMAT, 1359), Err(PrecompileError::OutOfGas));
+    }
+
+    /// Runs `modexp` on a base and a modulus of the given lengths, with an empty exponent.
+    fn run_lengths(
+        modexp: fn(&[u8], u64) -> PrecompileResult,
+        base_len: U256,
+        modulus_len: U256,
+        gas_limit: u64,
+    ) -> PrecompileResult {
+        let mut input = [0u8; 96];
+        input[..32].copy_from_slice(&base_len.to_big_endian());
+        input[64..].copy_from_slice(&modulus_len.to_big_endian());
+        modexp(&input, gas_limit)
+    }
+
+    #[test]
+    fn lengths_past_the_limit_are_rejected_since_osaka() {
+        let run =
+            |base_len: U256, modulus_len: U256| run_lengths(osaka, base_len, modulus_len, u64::MAX);
+
+        assert!(matches!(
+            run(U256::MAX, 1.into()),
+            Err(PrecompileError::InvalidInput(_))
+        ));
+        // a 2^30-byte modulus, affordable with all the gas, is rejected before the output is
+        // allocated.
+        assert!(matches!(
+            run(1.into(), (1u64 << 30).into()),
+            Err(PrecompileError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            run(1.into(), 1025.into()),
+            Err(PrecompileError::InvalidInput(_))
+        ));
+        assert_eq!(run(1.into(), 1024.into()).unwrap().bytes, [0; 1024]);
+    }
+
+    #[test]
+    fn lengths_before_osaka_are_bounded_by_the_gas() {
+        let run = |base_len: U256, modulus_len: U256, gas_limit| {
+            run_lengths(berlin, base_len, modulus_len, gas_limit)
+        };
+
+        // 1025 bytes are accepted, costing ceil(1025 / 8) ** 2 / 3.
+        let output = run(1.into(), 1025.into(), u64::MAX).unwrap();
+        assert_eq!(output.bytes, [0; 1025]);
+        assert_eq!(output.gas_used, 129 * 129 / 3);
+        // larger lengths run out of gas before anything is allocated.
+        assert_eq!(
+            run(1.into(), (1u64 << 30).into(), 30_000_000),
+            Err(PrecompileError::OutOfGas)
+        );
+        assert_eq!(
+            run(U256::MAX, 1.into(), u64::MAX),
+            Err(PrecompileError::OutOfGas)
+        );
+        assert_eq!(
+            run_lengths(byzantium, U256::MAX, U256::MAX, u64::MAX),
+            Err(PrecompileError::OutOfGas)
+        );
+    }
+
+    #[test]
+    fn osaka_prices_eip_7883() {
+        let one = hex!("0000000000000000000000000000000000000000000000000000000000000001");
+        // a 32-byte modulus costs 16 per iteration, the highest bit of the exponent being at
+        // index 255.
+        assert_eq!(
+            osaka(&FERMAT, u64::MAX),
+            Ok(PrecompileOutput::new(16 * 255, one.to_vec()))
+        );
+        // the minimum is 500.
+        let output = run_lengths(osaka, 1.into(), 1.into(), u64::MAX).unwrap();
+        assert_eq!(output.gas_used, 500);
+    }
+
+    #[test]
+    fn exponent_past_the_input_is_priced_by_its_length() {
+        // a 1024-byte exponent of which only the byte 0x03 is present, leaving the modulus out
+        // of the input.
+        let input = hex!(
+            "0000000000000000000000000000000000000000000000000000000000000001"
+            "0000000000000000000000000000000000000000000000000000000000000400"
+            "0000000000000000000000000000000000000000000000000000000000000001"
+            "02" "03"
+        );
+        let output = berlin(&input, u64::MAX).unwrap();
+        assert_eq!(output.bytes, [0]);
+        // the exponent head 0x03 followed by zeros has its highest bit at index 249.
+        assert_eq!(output.gas_used, ((1024 - 32) * 8 + 249) / 3);
+    }
+
+    #[test]
+    fn zero_modulus_returns_zeros() {
+        let input = hex!(
+            "0000000000000000000000000000000000000000000000000000000000000001"
+            "0000000000000000000000000000000000000000000000000000000000000001"
+            "0000000000000000000000000000000000000000000000000000000000000002"
+            "02" "03" "0000"
+        );
+        assert_eq!(berlin(&input, u64::MAX).unwrap().bytes, [0, 0]);
+    }
+}
@@ -0,0 +1,102 @@
+use hex_literal::hex;
+use p256::{
+    ecdsa::{signature::hazmat::PrehashVerifier, Signature, VerifyingKey},
+    EncodedPoint,
+};
+
+use super::{PrecompileError, PrecompileOutput, PrecompileResult};
+use crate::primitives::Address;
+
+/// Address of the precompile on the chains adopting RIP-7212.
+pub const P256_VERIFY_ADDRESS: Address = Address(hex!("0000000000000000000000000000000000000100"));
+
+const P256_VERIFY_GAS: u64 = 3450;
+/// Size of the input: `hash || r || s || x || y`.
+const INPUT_SIZE: usize = 160;
+
+/// Verifies a secp256r1 signature of a hash by the public key `(x, y)` (RIP-7212), returning
+/// 1 as a word if it is valid, or nothing otherwise.
+pub fn p256_verify(input: &[u8], gas_limit: u64) -> PrecompileResult {
+    if P256_VERIFY_GAS > gas_limit {
+        return Err(PrecompileError::OutOfGas);
+    }
+
+    let output = if input.len() == INPUT_SIZE && verify(input) {
+        let mut output = vec![0u8; 32];
+        output[31] = 1;
+        output
+    } else {
+        vec![]
+    };
+    Ok(PrecompileOutput::new(P256_VERIFY_GAS, output))
+}
+
+fn verify(input: &[u8]) -> bool {
+    let hash = &input[..32];
+    let r: [u8; 32] = input[32..64].try_into().unwrap();
+    let s: [u8; 32] = input[64..96].try_into().unwrap();
+    let point =
+        EncodedPoint::from_affine_coordinates(input[96..128].into(), input[128..160].into(), false);
+
+    let Ok(signature) = Signature::from_scalars(r, s) else {
+        return false;
+    };
+    let Ok(key) = VerifyingKey::from_encoded_point(&point) else {
+        return false;
+    };
+    key.verify_prehash(hash, &signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A valid signature of the RIP-7212 test vectors, `hash || r || s || x || y`.
+    const SIGNED: [u8; INPUT_SIZE] = hex!(
+        "4cee90eb86eaa050036147a12d49004b6b9c72bd725d39d4785011fe190f0b4d"
+        "a73bd4903f0ce3b639bbbf6e8e80d16931ff4bcf5993d58468e8fb19086e8cac"
+        "36dbcd03009df8c59286b162af3bd7fcc0450c9aa81be5d10d312af6c66b1d60"
+        "4aebd3099c618202fcfe16ae7770b0c49ab5eadf74b754204a3bb6060e44eff3"
+        "7618b065f9832de4ca6ca971a7a1adc826d0f7c00181a5fb2ddf79ae00b4e10e"
+    );
+
+    #[test]
+    fn valid_signature_returns_one() {
+        let mut one = vec![0u8; 32];
+        one[31] = 1;
+        assert_eq!(
+            p256_verify(&SIGNED, u64::MAX),
+            Ok(PrecompileOutput::new(3450, one))
+        );
+    }
+
+    #[test]
+    fn invalid_signatures_return_nothing() {
+        let nothing = Ok(PrecompileOutput::new(3450, vec![]));
+
+        let mut input = SIGNED;
+        input[0] ^= 1;
+        assert_eq!(p256_verify(&input, u64::MAX), nothing);
+
+        // a public key off the curve.
+        let mut input = SIGNED;
+        input[159] ^= 1;
+        assert_eq!(p256_verify(&input, u64::MAX), nothing);
+
+        // a zero `r`.
+        let mut input = SIGNED;
+        input[32..64].fill(0);
+        assert_eq!(p256_verify(&input, u64::MAX), nothing);
+
+        assert_eq!(p256_verify(&SIGNED[..159], u64::MAX), nothing);
+        assert_eq!(
+            p256_verify(&[&SIGNED[..], &[0]].concat(), u64::MAX),
+            nothing
+        );
+    }
+
+    #[test]
+    fn runs_out_of_gas_below_3450() {
+        assert_eq!(p256_verify(&SIGNED, 3449), Err(PrecompileError::OutOfGas));
+    }
+}
@@ -0,0 +1,136 @@
+use std::fmt;
+
+use primitive_types::U256;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::serde_hex::decode_fixed;
+
+/// A 20-byte account address.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Address(pub [u8; 20]);
+
+impl Address {
+    pub const ZERO: Address = Address([0; 20]);
+
+    /// Returns the address held in the low 20 bytes of a word, the high 12 bytes being dropped
+    /// as done by the instructions taking an address.
+    pub fn from_word(word: U256) -> Address {
+        let mut address = [0u8; 20];
+        address.copy_from_slice(&word.to_big_endian()[12..]);
+        Address(address)
+    }
+
+    /// Returns the address as a word, left-padded with zeros.
+    pub fn to_word(self) -> U256 {
+        U256::from_big_endian(&self.0)
+    }
+
+    /// Reads an address from at most 20 big-endian bytes, left-padding shorter inputs.
+    pub fn from_slice(bytes: &[u8]) -> Option<Address> {
+        let offset = 20usize.checked_sub(bytes.len())?;
+        let mut address = [0u8; 20];
+        address[offset..].copy_from_slice(bytes);
+        Some(Address(address))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == Address::ZERO
+    }
+}
+
+impl From<[u8; 20]> for Address {
+    fn from(bytes: [u8; 20]) -> Address {
+        Address(bytes)
+    }
+}
+
+/// Builds the address of a low number, such as the address of a precompile.
+impl From<u64> for Address {
+    fn from(value: u64) -> Address {
+        Address::from_word(value.into())
+    }
+}
+
+impl AsRef<[u8]> for Address {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{}", hex::encode(self.0))
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{}", hex::encode(self.0))
+    }
+}
+
+/// Serializes as a `0x`-prefixed hex string.
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Address, D::Error> {
+        decode_fixed(&String::deserialize(deserializer)?).map(Address)
+    }
+}
+
+/// A 32-byte value, such as a hash.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct B256(pub [u8; 32]);
+
+impl B256 {
+    pub const ZERO: B256 = B256([0; 32]);
+
+    pub fn from_word(word: U256) -> B256 {
+        B256(word.to_big_endian())
+    }
+
+    pub fn to_word(self) -> U256 {
+        U256::from_big_endian(&self.0)
+    }
+}
+
+impl From<[u8; 32]> for B256 {
+    fn from(bytes: [u8; 32]) -> B256 {
+        B256(bytes)
+    }
+}
+
+impl AsRef<[u8]> for B256 {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for B256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{}", hex::encode(self.0))
+    }
+}
+
+impl fmt::Display for B256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{}", hex::encode(self.0))
+    }
+}
+
+/// Serializes as a `0x`-prefixed hex string.
+impl Serialize for B256 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for B256 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<B256, D::Error> {
+        decode_fixed(&String::deserialize(deserializer)?).map(B256)
+    }
+}
@@ -0,0 +1,36 @@
+use primitive_types::U256;
+
+/// RLP encoding of a byte string.
+pub fn encode_bytes(bytes: &[u8]) -> Vec<u8> {
+    if bytes.len() == 1 && bytes[0] < 0x80 {
+        return bytes.to_vec();
+    }
+    [encode_length(bytes.len(), 0x80), bytes.to_vec()].concat()
+}
+
+/// RLP encoding of an integer, as its big endian representation without leading zeros.
+pub fn encode_u256(value: U256) -> Vec<u8> {
+    let bytes = value.to_big_endian();
+    let leading_zeros = bytes.iter().take_while(|byte| **byte == 0).count();
+    encode_bytes(&bytes[leading_zeros..])
+}
+
+/// RLP encoding of a list whose items are already encoded.
+pub fn encode_list(items: &[Vec<u8>]) -> Vec<u8> {
+    let payload = items.concat();
+    [encode_length(payload.len(), 0xc0), payload].concat()
+}
+
+fn encode_length(length: usize, offset: u8) -> Vec<u8> {
+    if length < 56 {
+        return vec![offset + length as u8];
+    }
+    let length_bytes = length.to_be_bytes();
+    let leading_zeros = length_bytes.iter().take_while(|byte| **byte == 0).count();
+    let length_bytes = &length_bytes[leading_zeros..];
+    [
+        vec![offset + 55 + length_bytes.len() as u8],
+        length_bytes.to_vec(),
+    ]
+    .concat()
+}
@@ -0,0 +1,66 @@
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::outcome::Change;
+
+/// Decodes a `0x`-prefixed hex string.
+pub(crate) fn decode<E: Error>(text: &str) -> Result<Vec<u8>, E> {
+    let digits = text
+        .strip_prefix("0x")
+        .ok_or_else(|| E::custom(format!("`{text}` is missing the 0x prefix")))?;
+    hex::decode(digits).map_err(E::custom)
+}
+
+/// Decodes a `0x`-prefixed hex string of exactly `N` bytes.
+pub(crate) fn decode_fixed<const N: usize, E: Error>(text: &str) -> Result<[u8; N], E> {
+    let bytes = decode::<E>(text)?;
+    let len = bytes.len();
+    bytes
+        .try_into()
+        .map_err(|_| E::custom(format!("expected {N} bytes, got {len}")))
+}
+
+/// Serializes a `Vec<u8>` field as a hex string, with `#[serde(with = "hex_bytes")]`.
+pub(crate) mod hex_bytes {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("0x{}", hex::encode(bytes)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        decode(&String::deserialize(deserializer)?)
+    }
+}
+
+/// Bytes serialized as a hex string.
+#[derive(Serialize, Deserialize)]
+#[serde(transparent)]
+struct HexBytes(#[serde(with = "hex_bytes")] Vec<u8>);
+
+/// Serializes a change of code as hex strings, with `#[serde(with = "hex_change")]`.
+pub(crate) mod hex_change {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        change: &Option<Change<Vec<u8>>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        change
+            .as_ref()
+            .map(|change| Change {
+                before: HexBytes(change.before.clone()),
+                after: HexBytes(change.after.clone()),
+            })
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Change<Vec<u8>>>, D::Error> {
+        let change = Option::<Change<HexBytes>>::deserialize(deserializer)?;
+        Ok(change.map(|change| Change {
+            before: change.before.0,
+            after: change.after.0,
+        }))
+    }
+}
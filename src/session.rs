@@ -0,0 +1,586 @@
+use std::sync::Arc;
+
+use primitive_types::U256;
+
+use crate::{
+    authorization::Authorization,
+    block::BlockEnv,
+    db::{Database, DatabaseError},
+    errors::TransactionError,
+    overrides::{apply_overrides, StateOverride},
+    precompile::Precompiles,
+    primitives::Address,
+    run,
+    spec::SpecId,
+    state::{AddressData, Checkpoint, State},
+    storage::Storage,
+    system::{apply_system_calls, SystemCallError, SystemCalls, SystemCallsReport},
+    tx::TxEnv,
+    EvmResult,
+};
+
+/// A world state executing a sequence of transactions, each one running on the changes
+/// committed by the previous ones.
+///
+/// The state is read through from a [`Database`], which is never written to.
+///
+/// Snapshots are checkpoints of the state and storage, which journal the changes made while
+/// they are open and undo them on revert instead of copying the state.
+pub struct Session {
+    state: State,
+    storage: Storage,
+    block_env: BlockEnv,
+    spec: SpecId,
+    precompiles: Precompiles,
+    /// The checkpoints of the state and storage of each open snapshot, the oldest first.
+    snapshots: Vec<(SnapshotId, (Checkpoint, Checkpoint))>,
+    /// The id of the next snapshot, never reused so that discarded snapshots stay unknown.
+    next_snapshot: usize,
+}
+
+/// Identifies a snapshot of a [`Session`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SnapshotId(usize);
+
+impl Session {
+    /// Opens a session on the state of `db`, running transactions in `block_env` with the
+    /// rules of `spec`.
+    pub fn new(db: impl Database + 'static, block_env: BlockEnv, spec: SpecId) -> Session {
+        let db = Arc::new(db);
+        Session {
+            state: State::new(db.clone()),
+            storage: Storage::new(db),
+            block_env,
+            spec,
+            precompiles: Precompiles::new(spec),
+            snapshots: vec![],
+            next_snapshot: 0,
+        }
+    }
+
+    /// Opens a session on a state and storage, such as ones deserialized from a previous
+    /// session, which read their own database.
+    pub fn from_state(
+        state: State,
+        storage: Storage,
+        block_env: BlockEnv,
+        spec: SpecId,
+    ) -> Session {
+        Session {
+            state,
+            storage,
+            block_env,
+            spec,
+            precompiles: Precompiles::new(spec),
+            snapshots: vec![],
+            next_snapshot: 0,
+        }
+    }
+
+    /// Returns the state and storage of the session, to serialize them.
+    pub fn world_state(&self) -> (&State, &Storage) {
+        (&self.state, &self.storage)
+    }
+
+    /// Sets the block the next transactions run in. The system calls starting the block are
+    /// run by [`Session::apply_system_calls`].
+    pub fn set_block_env(&mut self, block_env: BlockEnv) {
+        self.block_env = block_env;
+    }
+
+    /// Runs the system calls starting the current block (EIP-4788 and EIP-2935), storing the
+    /// parent beacon root and the parent block hash in the state of the session, and reports
+    /// what each call did. A failing call makes the block invalid, leaving the state unchanged
+    /// by it, and is returned as [`SystemCallError::Failed`].
+    pub fn apply_system_calls(
+        &mut self,
+        system_calls: &SystemCalls,
+    ) -> Result<SystemCallsReport, SystemCallError> {
+        apply_system_calls(
+            &mut self.state,
+            &mut self.storage,
+            &self.block_env,
+            system_calls,
+            self.spec,
+            &self.precompiles,
+        )
+    }
+
+    /// Returns the precompiles of the spec, to register, replace or remove precompiles for the
+    /// next transactions.
+    pub fn precompiles_mut(&mut self) -> &mut Precompiles {
+        &mut self.precompiles
+    }
+
+    /// Runs the code of the `to` account of the transaction, committing its changes if it
+    /// succeeds. The nonce of the caller is incremented even if the call fails.
+    pub fn call(&mut self, tx_env: TxEnv) -> Result<EvmResult, TransactionError> {
+        self.transact(None, &[], tx_env, false)
+    }
+
+    /// Runs a set-code transaction (EIP-7702), delegating the code of the authorities of the
+    /// `authorization_list` before calling the `to` account. The delegations are kept even if
+    /// the call fails.
+    pub fn call_with_authorizations(
+        &mut self,
+        tx_env: TxEnv,
+        authorization_list: &[Authorization],
+    ) -> Result<EvmResult, TransactionError> {
+        self.transact(None, authorization_list, tx_env, false)
+    }
+
+    /// Deploys a contract with the `initcode`, at the address derived from the caller and its
+    /// nonce. The nonce of the caller is incremented even if the deployment fails.
+    pub fn deploy(
+        &mut self,
+        tx_env: TxEnv,
+        initcode: impl AsRef<[u8]>,
+    ) -> Result<EvmResult, TransactionError> {
+        self.transact(Some(initcode.as_ref().to_vec()), &[], tx_env, true)
+    }
+
+    /// Runs the code of the `to` account of the transaction with the `overrides` applied to
+    /// the state, leaving the state of the session unchanged.
+    pub fn call_with_overrides(
+        &mut self,
+        tx_env: TxEnv,
+        overrides: &StateOverride,
+    ) -> Result<EvmResult, TransactionError> {
+        // the overrides and the changes of the call are layered on the state of the session,
+        // and undone once it ran.
+        let checkpoint = self.state.checkpoint();
+        let storage_checkpoint = self.storage.checkpoint();
+        let result = apply_overrides(&mut self.state, &mut self.storage, overrides)
+            .and_then(|()| self.transact(None, &[], tx_env, false));
+        self.state.revert(checkpoint);
+        self.storage.revert(storage_checkpoint);
+        result
+    }
+
+    fn transact(
+        &mut self,
+        code: Option<Vec<u8>>,
+        authorization_list: &[Authorization],
+        tx_env: TxEnv,
+        create: bool,
+    ) -> Result<EvmResult, TransactionError> {
+        run(
+            code,
+            authorization_list,
+            tx_env,
+            self.block_env.clone(),
+            &mut self.state,
+            &mut self.storage,
+            self.spec,
+            self.precompiles.clone(),
+            create,
+            None,
+            false,
+        )
+    }
+
+    /// Takes a snapshot of the state, which [`Session::revert`] goes back to.
+    ///
+    /// Snapshots nest: reverting to or committing a snapshot discards the ones taken after it.
+    pub fn snapshot(&mut self) -> SnapshotId {
+        let checkpoints = (self.state.checkpoint(), self.storage.checkpoint());
+        let id = SnapshotId(self.next_snapshot);
+        self.next_snapshot += 1;
+        self.snapshots.push((id, checkpoints));
+        id
+    }
+
+    /// Returns the position of the open snapshot `id`.
+    fn find_snapshot(&self, id: SnapshotId) -> Option<usize> {
+        self.snapshots
+            .iter()
+            .position(|(snapshot, _)| *snapshot == id)
+    }
+
+    /// Reverts the state to the snapshot `id`, discarding the snapshots taken after it. The
+    /// snapshot stays open, so the state can be reverted to it again.
+    ///
+    /// Returns `false` if there is no such snapshot, it being discarded by a previous revert
+    /// or commit.
+    pub fn revert(&mut self, id: SnapshotId) -> bool {
+        let Some(index) = self.find_snapshot(id) else {
+            return false;
+        };
+        let (checkpoint, storage_checkpoint) = self.snapshots[index].1;
+        self.state.revert(checkpoint);
+        self.storage.revert(storage_checkpoint);
+        self.snapshots[index].1 = (self.state.checkpoint(), self.storage.checkpoint());
+        self.snapshots.truncate(index + 1);
+        true
+    }
+
+    /// Closes the snapshot `id` and the ones taken after it, keeping the changes made since.
+    /// The changes are no longer journaled once no snapshot is open.
+    ///
+    /// Returns `false` if there is no such snapshot.
+    pub fn commit(&mut self, id: SnapshotId) -> bool {
+        let Some(index) = self.find_snapshot(id) else {
+            return false;
+        };
+        let (checkpoint, storage_checkpoint) = self.snapshots[index].1;
+        self.state.commit(checkpoint);
+        self.storage.commit(storage_checkpoint);
+        self.snapshots.truncate(index);
+        true
+    }
+
+    /// Replaces an account, ignoring the one in the database.
+    pub fn set_account(
+        &mut self,
+        address: Address,
+        nonce: usize,
+        balance: U256,
+        code: impl AsRef<[u8]>,
+    ) -> Result<(), DatabaseError> {
+        let data = AddressData {
+            nonce,
+            balance,
+            code: code.as_ref().to_vec(),
+        };
+        self.state.set_account(address, data)
+    }
+
+    /// Sets a storage slot of an account, over the value of the database.
+    pub fn set_storage(
+        &mut self,
+        address: Address,
+        slot: U256,
+        value: U256,
+    ) -> Result<(), DatabaseError> {
+        self.storage.set_constract_slot(address, slot, value)
+    }
+
+    pub fn balance(&mut self, address: Address) -> Result<U256, DatabaseError> {
+        self.state.get_balance(address)
+    }
+
+    pub fn nonce(&mut self, address: Address) -> Result<usize, DatabaseError> {
+        self.state.get_nonce(address)
+    }
+
+    pub fn code(&mut self, address: Address) -> Result<Vec<u8>, DatabaseError> {
+        self.state.get_code(address)
+    }
+
+    /// Returns the value of a storage slot of an account.
+    pub fn storage(&mut self, address: Address, slot: U256) -> Result<U256, DatabaseError> {
+        self.storage.load_slot(address, slot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::InMemoryDB,
+        overrides::{AccountOverride, OverrideError},
+        precompile::{PrecompileOutput, PrecompileResult},
+        primitives::B256,
+        system::BEACON_ROOTS_ADDRESS,
+    };
+
+    const CALLER: u64 = 0x1111;
+
+    fn session() -> Session {
+        let mut db = InMemoryDB::new();
+        db.insert_account(Address::from(CALLER), 0, U256::zero(), vec![]);
+        Session::new(db, BlockEnv::default(), SpecId::Cancun)
+    }
+
+    fn caller_tx() -> TxEnv {
+        TxEnv {
+            caller: Address::from(CALLER),
+            origin: Address::from(CALLER),
+            ..TxEnv::default()
+        }
+    }
+
+    #[test]
+    fn system_calls_store_the_beacon_root_in_the_session() {
+        let mut session = session();
+        session.set_block_env(BlockEnv {
+            timestamp: 20000,
+            ..BlockEnv::default()
+        });
+        let system_calls = SystemCalls {
+            parent_beacon_block_root: Some(B256([0xaa; 32])),
+            deploy_missing: true,
+            ..SystemCalls::default()
+        };
+        session.apply_system_calls(&system_calls).unwrap();
+
+        // the next transactions read the root by the timestamp of the block.
+        let result = session
+            .call(TxEnv {
+                to: BEACON_ROOTS_ADDRESS,
+                data: U256::from(20000).to_big_endian().to_vec(),
+                ..caller_tx()
+            })
+            .unwrap();
+        assert!(result.success, "{:?}", result.outcome);
+        assert_eq!(result.ret, [0xaa; 32]);
+    }
+
+    #[test]
+    fn revert_drops_accounts_created_after_snapshot() {
+        let mut session = session();
+        let snapshot = session.snapshot();
+        session
+            .set_account(Address::from(0x2222), 1, U256::one(), [])
+            .unwrap();
+        // PUSH1 1 PUSH1 0 SSTORE STOP
+        let result = session
+            .deploy(caller_tx(), [0x60, 0x01, 0x60, 0x00, 0x55, 0x00])
+            .unwrap();
+        let contract = result.created_address.unwrap();
+        assert_eq!(
+            session.storage(contract, U256::zero()).unwrap(),
+            U256::one()
+        );
+
+        assert!(session.revert(snapshot));
+        assert_eq!(session.nonce(Address::from(CALLER)).unwrap(), 0);
+        assert_eq!(
+            session.storage(contract, U256::zero()).unwrap(),
+            U256::zero()
+        );
+        let state = serde_json::to_value(session.world_state().0).unwrap();
+        assert_eq!(state["destroyed"], serde_json::json!([]));
+        assert!(!state["accounts"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .any(|address| address.ends_with("2222")));
+    }
+
+    #[test]
+    fn snapshots_nest() {
+        let mut session = session();
+        let address = Address::from(0x2222);
+        let outer = session.snapshot();
+        session
+            .set_storage(address, U256::zero(), U256::one())
+            .unwrap();
+        let inner = session.snapshot();
+        session
+            .set_storage(address, U256::zero(), 2.into())
+            .unwrap();
+
+        assert!(session.revert(inner));
+        assert_eq!(session.storage(address, U256::zero()).unwrap(), U256::one());
+        assert!(session.revert(outer));
+        assert_eq!(
+            session.storage(address, U256::zero()).unwrap(),
+            U256::zero()
+        );
+        assert!(!session.revert(inner));
+    }
+
+    #[test]
+    fn commit_keeps_changes() {
+        let mut session = session();
+        let address = Address::from(0x2222);
+        let snapshot = session.snapshot();
+        session
+            .set_storage(address, U256::zero(), U256::one())
+            .unwrap();
+
+        assert!(session.commit(snapshot));
+        assert!(!session.revert(snapshot));
+        assert_eq!(session.storage(address, U256::zero()).unwrap(), U256::one());
+    }
+
+    #[test]
+    fn discarded_snapshot_ids_are_not_reused() {
+        let mut session = session();
+        let address = Address::from(0x2222);
+        let a = session.snapshot();
+        let b = session.snapshot();
+        assert!(session.revert(a));
+        let c = session.snapshot();
+        session
+            .set_storage(address, U256::zero(), U256::one())
+            .unwrap();
+
+        // `b` was discarded by the revert, and doesn't name `c`.
+        assert!(!session.revert(b));
+        assert_eq!(session.storage(address, U256::zero()).unwrap(), U256::one());
+
+        assert!(session.commit(c));
+        let d = session.snapshot();
+        assert!(!session.revert(c));
+        assert!(!session.commit(c));
+        assert!(session.revert(d));
+        assert_eq!(session.storage(address, U256::zero()).unwrap(), U256::one());
+    }
+
+    #[test]
+    fn deploys_twice_from_a_new_caller() {
+        let mut session = Session::new(InMemoryDB::new(), BlockEnv::default(), SpecId::Cancun);
+        let caller = Address::from(0x3333);
+        let tx_env = TxEnv {
+            caller,
+            ..TxEnv::default()
+        };
+
+        let first = session.deploy(tx_env.clone(), []).unwrap();
+        let second = session.deploy(tx_env, []).unwrap();
+
+        assert!(second.success, "{:?}", second.outcome);
+        assert_ne!(first.created_address, second.created_address);
+        assert_eq!(session.nonce(caller).unwrap(), 2);
+    }
+
+    #[test]
+    fn call_moves_value_and_increments_nonce() {
+        let mut session = session();
+        let caller = Address::from(CALLER);
+        let callee = Address::from(0x2222);
+        session.set_account(caller, 0, 10.into(), []).unwrap();
+        let tx_env = TxEnv {
+            to: callee,
+            value: 3.into(),
+            ..caller_tx()
+        };
+
+        let result = session.call(tx_env.clone()).unwrap();
+        assert!(result.success);
+        assert_eq!(session.balance(caller).unwrap(), 7.into());
+        assert_eq!(session.balance(callee).unwrap(), 3.into());
+        assert_eq!(session.nonce(caller).unwrap(), 1);
+
+        let tx_env = TxEnv {
+            value: 8.into(),
+            ..tx_env
+        };
+        assert!(matches!(
+            session.call(tx_env),
+            Err(TransactionError::InsufficientBalance)
+        ));
+        assert_eq!(session.nonce(caller).unwrap(), 1);
+    }
+
+    #[test]
+    fn failed_call_returns_value_and_keeps_nonce() {
+        let mut session = session();
+        let caller = Address::from(CALLER);
+        let callee = Address::from(0x2222);
+        session.set_account(caller, 0, 10.into(), []).unwrap();
+        // PUSH1 0 PUSH1 0 REVERT
+        session
+            .set_account(callee, 0, U256::zero(), [0x60, 0x00, 0x60, 0x00, 0xfd])
+            .unwrap();
+        let tx_env = TxEnv {
+            to: callee,
+            value: 3.into(),
+            ..caller_tx()
+        };
+
+        let result = session.call(tx_env).unwrap();
+        assert!(!result.success);
+        assert_eq!(session.balance(caller).unwrap(), 10.into());
+        assert_eq!(session.balance(callee).unwrap(), U256::zero());
+        assert_eq!(session.nonce(caller).unwrap(), 1);
+    }
+
+    #[test]
+    fn call_with_overrides_leaves_the_session_unchanged() {
+        let mut session = session();
+        let caller = Address::from(CALLER);
+        let callee = Address::from(0x2222);
+        // PUSH1 0 SLOAD PUSH1 1 PUSH1 0 SSTORE
+        session
+            .set_account(
+                callee,
+                0,
+                U256::zero(),
+                [0x60, 0x00, 0x54, 0x60, 0x01, 0x60, 0x00, 0x55],
+            )
+            .unwrap();
+        session.set_storage(callee, 1.into(), 5.into()).unwrap();
+        let overrides = StateOverride::from([
+            (
+                caller,
+                AccountOverride {
+                    balance: Some(10.into()),
+                    ..AccountOverride::default()
+                },
+            ),
+            (
+                callee,
+                AccountOverride {
+                    state: Some([(U256::zero(), 7.into())].into()),
+                    ..AccountOverride::default()
+                },
+            ),
+        ]);
+        let tx_env = TxEnv {
+            to: callee,
+            value: 3.into(),
+            ..caller_tx()
+        };
+
+        let result = session.call_with_overrides(tx_env, &overrides).unwrap();
+        assert!(result.success, "{:?}", result.outcome);
+        assert_eq!(result.stack, [U256::from(7)]);
+
+        assert_eq!(session.balance(caller).unwrap(), U256::zero());
+        assert_eq!(session.balance(callee).unwrap(), U256::zero());
+        assert_eq!(session.nonce(caller).unwrap(), 0);
+        assert_eq!(session.storage(callee, U256::zero()).unwrap(), U256::zero());
+        assert_eq!(session.storage(callee, 1.into()).unwrap(), 5.into());
+    }
+
+    #[test]
+    fn invalid_overrides_are_undone() {
+        let mut session = session();
+        let address = Address::from(0x2222);
+        let overrides = StateOverride::from([(
+            address,
+            AccountOverride {
+                balance: Some(1.into()),
+                state: Some(Default::default()),
+                state_diff: Some(Default::default()),
+                ..AccountOverride::default()
+            },
+        )]);
+
+        let error = session
+            .call_with_overrides(caller_tx(), &overrides)
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            TransactionError::Override(OverrideError::StateAndStateDiff(a)) if a == address
+        ));
+        assert_eq!(session.balance(address).unwrap(), U256::zero());
+    }
+
+    #[test]
+    fn registered_precompiles_are_called() {
+        fn answer(_: &[u8], _: u64) -> PrecompileResult {
+            Ok(PrecompileOutput::new(0, vec![0x2a]))
+        }
+        let mut session = session();
+        let precompile = Address::from(0x42);
+        let address = Address::from(0x2222);
+        session.precompiles_mut().insert(precompile, answer);
+        // CALL(0x42), then SSTORE(0, RETURNDATASIZE).
+        let code = [
+            0x5f, 0x5f, 0x5f, 0x5f, 0x5f, 0x60, 0x42, 0x61, 0xff, 0xff, 0xf1, 0x50, 0x3d, 0x5f,
+            0x55, 0x00,
+        ];
+        session.set_account(address, 0, U256::zero(), code).unwrap();
+        let tx_env = TxEnv {
+            to: address,
+            ..caller_tx()
+        };
+
+        assert!(session.call(tx_env).unwrap().success);
+        assert_eq!(session.storage(address, U256::zero()).unwrap(), U256::one());
+    }
+}
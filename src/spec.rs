@@ -0,0 +1,30 @@
+/// Ethereum hard forks, in activation order.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SpecId {
+    Frontier,
+    Homestead,
+    TangerineWhistle,
+    SpuriousDragon,
+    Byzantium,
+    Constantinople,
+    Petersburg,
+    Istanbul,
+    Berlin,
+    London,
+    /// The Paris upgrade, switching to proof-of-stake.
+    Merge,
+    Shanghai,
+    Cancun,
+    #[default]
+    Prague,
+    Osaka,
+    /// The EVM Object Format (EIP-7692), enabled on top of the rules of Osaka.
+    PragueEof,
+}
+
+impl SpecId {
+    /// Returns true if the rules of `fork` apply to this spec.
+    pub fn is_enabled_in(self, fork: SpecId) -> bool {
+        self >= fork
+    }
+}
@@ -1,112 +1,451 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fmt,
+    sync::Arc,
+};
 
 use primitive_types::U256;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-use crate::errors::ExecutionError;
+use crate::{
+    db::{DatabaseError, EmptyDB, SharedDatabase, KECCAK_EMPTY},
+    primitives::{Address, B256},
+    serde_hex::hex_bytes,
+};
 
-// State
-#[derive(Debug, Clone)]
+/// The accounts of the execution, read through from a [`Database`](crate::db::Database) and cached along with
+/// their changes.
+///
+/// The changes made while a checkpoint is open are journaled, so that they can be undone by
+/// reverting to it without copying the state.
+#[derive(Clone)]
 pub struct State {
+    /// The accounts loaded from the database or created by the execution.
     pub entries: Vec<StateData>,
+    /// The accounts deleted by the execution, which are not read from the database again.
+    destroyed: HashSet<Address>,
+    db: SharedDatabase,
+    /// The changes made since the oldest open checkpoint, the oldest first.
+    journal: Vec<StateChange>,
+    /// The accounts accessed by the transaction, whose access is warm (EIP-2929).
+    warm: HashSet<Address>,
+    /// The length of the journal when each open checkpoint was taken.
+    checkpoints: Vec<usize>,
+    /// Transfers create the value their sender lacks instead of failing, as code predating
+    /// balance checks expects.
+    pub(crate) mint_missing_value: bool,
+}
+
+/// A point in the changes of a [`State`] or a [`Storage`](crate::storage::Storage), which
+/// the changes made after it can be reverted to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint(pub(crate) usize);
+
+/// A change of the state, holding what is needed to undo it.
+#[derive(Debug, Clone)]
+enum StateChange {
+    /// An account about to change.
+    Account {
+        address: Address,
+        /// The account before the change, `None` if it didn't exist.
+        before: Option<AddressData>,
+        /// The account was deleted before the change.
+        destroyed: bool,
+    },
+    /// An account was accessed for the first time in the transaction.
+    Warmed(Address),
 }
 
 impl State {
-    pub fn new(state_data: HashMap<Vec<u8>, (usize, Vec<u8>, Vec<u8>)>) -> State {
-        let mut entries = vec![];
-        for (address, data) in state_data {
-            let state_data = StateData {
-                address: U256::from_big_endian(&address),
-                data: AddressData {
-                    nonce: data.0,
-                    balance: U256::from_big_endian(&data.1),
-                    code: data.2,
-                },
-            };
-            entries.push(state_data);
+    pub fn new(db: SharedDatabase) -> State {
+        State {
+            entries: vec![],
+            destroyed: HashSet::new(),
+            db,
+            journal: vec![],
+            warm: HashSet::new(),
+            checkpoints: vec![],
+            mint_missing_value: false,
         }
-        State { entries }
     }
 
-    pub fn get_balance(&self, address: U256) -> U256 {
-        self.entries
-            .iter()
-            .find(|s| s.address == address)
-            .map(|s| s.data.balance.clone())
-            .unwrap_or_default()
+    /// Moves the state out, leaving an empty one reading the same database in its place, for
+    /// a frame to run on it and give it back.
+    pub(crate) fn take(&mut self) -> State {
+        let mut empty = State::new(self.db.clone());
+        empty.mint_missing_value = self.mint_missing_value;
+        std::mem::replace(self, empty)
     }
 
-    pub fn get_code(&self, address: U256) -> Vec<u8> {
-        self.entries
-            .iter()
-            .find(|s| s.address == address)
-            .map(|s| s.data.code.clone())
-            .unwrap_or_default()
+    /// Opens a checkpoint, journaling the changes made from now on until it is closed.
+    pub(crate) fn checkpoint(&mut self) -> Checkpoint {
+        self.checkpoints.push(self.journal.len());
+        Checkpoint(self.checkpoints.len() - 1)
     }
 
-    pub fn get_nonce(&self, address: U256) -> usize {
-        self.entries
-            .iter()
-            .find(|s| s.address == address)
-            .map(|s| s.data.nonce.clone())
-            .unwrap_or_default()
+    /// Closes the checkpoint and the ones opened after it, keeping their changes. The journal
+    /// is dropped once no checkpoint is open.
+    pub(crate) fn commit(&mut self, checkpoint: Checkpoint) {
+        self.checkpoints.truncate(checkpoint.0);
+        if self.checkpoints.is_empty() {
+            self.journal.clear();
+        }
     }
 
-    pub fn save_code(
-        &mut self,
-        address: U256,
-        code: Vec<u8>,
-        value_transferred: U256,
-    ) -> Result<(), ExecutionError> {
-        match self.entries.iter().find(|s| s.address == address) {
-            Some(_state) => Err(ExecutionError::ContractAddressCollision),
-            None => {
-                let address_data = AddressData {
-                    nonce: 0,
-                    balance: value_transferred,
-                    code,
-                };
+    /// Undoes the changes made since the checkpoint, closing it and the ones opened after it.
+    pub(crate) fn revert(&mut self, checkpoint: Checkpoint) {
+        let Some(&length) = self.checkpoints.get(checkpoint.0) else {
+            return;
+        };
+        self.checkpoints.truncate(checkpoint.0);
 
-                let state_data = StateData {
+        // the changes are undone from the newest, accounts that didn't exist being dropped
+        // from the cache rather than marked as deleted.
+        for change in self.journal.drain(length..).rev() {
+            let (address, before, destroyed) = match change {
+                StateChange::Account {
                     address,
-                    data: address_data,
-                };
+                    before,
+                    destroyed,
+                } => (address, before, destroyed),
+                StateChange::Warmed(address) => {
+                    self.warm.remove(&address);
+                    continue;
+                }
+            };
+            self.entries.retain(|s| s.address != address);
+            if let Some(data) = before {
+                self.entries.push(StateData { address, data });
+            }
+            if destroyed {
+                self.destroyed.insert(address);
+            } else {
+                self.destroyed.remove(&address);
+            }
+        }
+    }
 
-                self.entries.push(state_data);
-                Ok(())
+    /// Returns the accounts changed since the checkpoint, with their value when it was taken.
+    pub(crate) fn changes(&self, checkpoint: Checkpoint) -> BTreeMap<Address, Option<AddressData>> {
+        let length = self
+            .checkpoints
+            .get(checkpoint.0)
+            .copied()
+            .unwrap_or(self.journal.len());
+        let mut changes = BTreeMap::new();
+        for change in &self.journal[length..] {
+            if let StateChange::Account {
+                address, before, ..
+            } = change
+            {
+                changes.entry(*address).or_insert_with(|| before.clone());
             }
         }
+        changes
+    }
+
+    /// Forgets the accounts accessed by the previous transaction, their access being cold again.
+    pub(crate) fn start_transaction(&mut self) {
+        self.warm.clear();
+    }
+
+    /// Marks the account at `address` as accessed by the transaction, returning true if it
+    /// wasn't: the first access of an account is cold, and costs more (EIP-2929).
+    pub(crate) fn access(&mut self, address: Address) -> bool {
+        if !self.warm.insert(address) {
+            return false;
+        }
+        if !self.checkpoints.is_empty() {
+            self.journal.push(StateChange::Warmed(address));
+        }
+        true
+    }
+
+    /// Journals the account at `address` before changing it, while a checkpoint is open.
+    fn journal(&mut self, address: Address) -> Result<(), DatabaseError> {
+        if self.checkpoints.is_empty() {
+            return Ok(());
+        }
+        let before = self.load(address)?.map(|s| s.data.clone());
+        let destroyed = self.destroyed.contains(&address);
+        self.journal.push(StateChange::Account {
+            address,
+            before,
+            destroyed,
+        });
+        Ok(())
+    }
+
+    /// Returns the account at `address`, loading it from the database on first access.
+    fn load(&mut self, address: Address) -> Result<Option<&mut StateData>, DatabaseError> {
+        let index = match self.entries.iter().position(|s| s.address == address) {
+            Some(index) => index,
+            None => {
+                if self.destroyed.contains(&address) {
+                    return Ok(None);
+                }
+                let Some(info) = self.db.basic(address)? else {
+                    return Ok(None);
+                };
+                let code = if info.code_hash == KECCAK_EMPTY {
+                    vec![]
+                } else {
+                    self.db.code_by_hash(info.code_hash)?
+                };
+                self.entries.push(StateData {
+                    address,
+                    data: AddressData {
+                        nonce: info.nonce,
+                        balance: info.balance,
+                        code,
+                    },
+                });
+                self.entries.len() - 1
+            }
+        };
+        Ok(self.entries.get_mut(index))
     }
 
-    pub fn transfer_balance(&mut self, balance: U256, dest: U256) {
-        if let Some(account) = self.entries.iter_mut().find(|s| s.address == dest) {
-            account.data.balance += balance;
-        } else {
-            let new_account = StateData {
-                address: dest,
+    /// Returns the account at `address`, creating an empty one if there is none.
+    fn load_or_create(&mut self, address: Address) -> Result<&mut StateData, DatabaseError> {
+        if self.load(address)?.is_none() {
+            self.destroyed.remove(&address);
+            self.entries.push(StateData {
+                address,
                 data: AddressData {
                     nonce: 0,
-                    balance,
+                    balance: U256::zero(),
                     code: vec![],
                 },
-            };
-            self.entries.push(new_account);
+            });
+        }
+        Ok(self.load(address)?.expect("the account was just created"))
+    }
+
+    pub fn get_balance(&mut self, address: Address) -> Result<U256, DatabaseError> {
+        Ok(self
+            .load(address)?
+            .map(|s| s.data.balance)
+            .unwrap_or_default())
+    }
+
+    pub fn get_code(&mut self, address: Address) -> Result<Vec<u8>, DatabaseError> {
+        Ok(self
+            .load(address)?
+            .map(|s| s.data.code.clone())
+            .unwrap_or_default())
+    }
+
+    pub fn get_nonce(&mut self, address: Address) -> Result<usize, DatabaseError> {
+        Ok(self
+            .load(address)?
+            .map(|s| s.data.nonce)
+            .unwrap_or_default())
+    }
+
+    /// Returns the account at `address`, if it exists.
+    pub fn account(&mut self, address: Address) -> Result<Option<AddressData>, DatabaseError> {
+        Ok(self.load(address)?.map(|s| s.data.clone()))
+    }
+
+    /// Returns the hash of the block `number` from the database.
+    pub fn block_hash(&self, number: u64) -> Result<B256, DatabaseError> {
+        self.db.block_hash(number)
+    }
+
+    /// Returns true if a contract can't be deployed at `address`, an account there having code
+    /// or a nonce (EIP-684).
+    pub fn collides(&mut self, address: Address) -> Result<bool, DatabaseError> {
+        Ok(self
+            .load(address)?
+            .is_some_and(|s| s.data.nonce != 0 || !s.data.code.is_empty()))
+    }
+
+    /// Replaces the code of an account, creating it if needed.
+    pub fn set_code(&mut self, address: Address, code: Vec<u8>) -> Result<(), DatabaseError> {
+        self.journal(address)?;
+        self.load_or_create(address)?.data.code = code;
+        Ok(())
+    }
+
+    /// Replaces an account, ignoring the one in the database.
+    pub fn set_account(
+        &mut self,
+        address: Address,
+        data: AddressData,
+    ) -> Result<(), DatabaseError> {
+        self.journal(address)?;
+        self.load_or_create(address)?.data = data;
+        Ok(())
+    }
+
+    /// Increments the nonce of an account, creating it if needed. Returns false, changing
+    /// nothing, if the nonce is already at its maximum.
+    pub fn increment_nonce(&mut self, address: Address) -> Result<bool, DatabaseError> {
+        let Some(nonce) = self.get_nonce(address)?.checked_add(1) else {
+            return Ok(false);
+        };
+        self.journal(address)?;
+        self.load_or_create(address)?.data.nonce = nonce;
+        Ok(true)
+    }
+
+    /// Moves `value` from the balance of `from` to the one of `to`, creating `to` if needed.
+    /// Returns false, changing nothing, if `from` holds less than `value` or the balance of `to`
+    /// would overflow, which only balances set past the total supply can do.
+    ///
+    /// When minting the missing value, `from` sends all it holds and the rest is created.
+    pub fn transfer(
+        &mut self,
+        from: Address,
+        to: Address,
+        value: U256,
+    ) -> Result<bool, DatabaseError> {
+        if value.is_zero() || from == to {
+            return Ok(self.mint_missing_value || self.get_balance(from)? >= value);
         }
+        let from_balance = match self.get_balance(from)?.checked_sub(value) {
+            Some(from_balance) => from_balance,
+            None if self.mint_missing_value => U256::zero(),
+            None => return Ok(false),
+        };
+        let Some(to_balance) = self.get_balance(to)?.checked_add(value) else {
+            return Ok(false);
+        };
+
+        self.journal(from)?;
+        self.load_or_create(from)?.data.balance = from_balance;
+        self.journal(to)?;
+        self.load_or_create(to)?.data.balance = to_balance;
+        Ok(true)
     }
 
-    pub fn delete_account(&mut self, address: U256) {
+    pub fn delete_account(&mut self, address: Address) -> Result<(), DatabaseError> {
+        self.journal(address)?;
         self.entries.retain(|account| account.address != address);
+        self.destroyed.insert(address);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("State")
+            .field("entries", &self.entries)
+            .field("destroyed", &self.destroyed)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The serialized form of a [`State`], its accounts sorted by address.
+#[derive(Serialize, Deserialize)]
+struct SerializedState {
+    accounts: BTreeMap<Address, AddressData>,
+    #[serde(default)]
+    destroyed: BTreeSet<Address>,
+}
+
+/// Serializes the accounts loaded, created or deleted by the execution, not the rest of the
+/// database.
+impl Serialize for State {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SerializedState {
+            accounts: self
+                .entries
+                .iter()
+                .map(|s| (s.address, s.data.clone()))
+                .collect(),
+            destroyed: self.destroyed.iter().copied().collect(),
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Deserializes a state detached from the database of the serialized one: it reads no database,
+/// the accounts it holds being all the accounts.
+impl<'de> Deserialize<'de> for State {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<State, D::Error> {
+        let state = SerializedState::deserialize(deserializer)?;
+        Ok(State {
+            entries: state
+                .accounts
+                .into_iter()
+                .map(|(address, data)| StateData { address, data })
+                .collect(),
+            destroyed: state.destroyed.into_iter().collect(),
+            db: Arc::new(EmptyDB),
+            journal: vec![],
+            warm: HashSet::new(),
+            checkpoints: vec![],
+            mint_missing_value: false,
+        })
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct StateData {
-    pub address: U256,
+    pub address: Address,
     pub data: AddressData,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AddressData {
     pub nonce: usize,
     pub balance: U256,
+    #[serde(with = "hex_bytes")]
     pub code: Vec<u8>,
 }
+
+impl AddressData {
+    /// Returns true if the account has no code, nonce nor balance, and is then treated as not
+    /// existing (EIP-161).
+    pub fn is_empty(&self) -> bool {
+        self.nonce == 0 && self.balance.is_zero() && self.code.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::InMemoryDB;
+
+    #[test]
+    fn serde_round_trip() {
+        let mut db = InMemoryDB::new();
+        db.insert_account(Address::from(1), 1, 2.into(), vec![0x00]);
+        let mut state = State::new(Arc::new(db));
+        state.increment_nonce(Address::from(1)).unwrap();
+        state
+            .set_account(Address::from(2), AddressData::default())
+            .unwrap();
+        state.delete_account(Address::from(2)).unwrap();
+
+        let json = serde_json::to_string(&state).unwrap();
+        let mut state: State = serde_json::from_str(&json).unwrap();
+
+        let account = state.account(Address::from(1)).unwrap().unwrap();
+        assert_eq!(
+            (account.nonce, account.balance, account.code),
+            (2, 2.into(), vec![0x00])
+        );
+        assert_eq!(state.account(Address::from(2)).unwrap(), None);
+        assert_eq!(serde_json::to_string(&state).unwrap(), json);
+    }
+
+    #[test]
+    fn revert_restores_deleted_accounts() {
+        let mut db = InMemoryDB::new();
+        db.insert_account(Address::from(1), 1, 2.into(), vec![]);
+        let mut state = State::new(Arc::new(db));
+
+        let checkpoint = state.checkpoint();
+        state.delete_account(Address::from(1)).unwrap();
+        state.set_code(Address::from(3), vec![0x00]).unwrap();
+        state.revert(checkpoint);
+
+        assert_eq!(state.get_nonce(Address::from(1)).unwrap(), 1);
+        assert_eq!(state.account(Address::from(3)).unwrap(), None);
+        let json = serde_json::to_value(&state).unwrap();
+        assert_eq!(json["destroyed"], serde_json::json!([]));
+    }
+}
@@ -1,36 +1,286 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::Arc,
+};
 
 use primitive_types::U256;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-/// Storage of a contract.
+use crate::{
+    db::{DatabaseError, EmptyDB, SharedDatabase},
+    primitives::Address,
+    state::Checkpoint,
+};
+
+/// Storage of the contracts, read through from a [`Database`](crate::db::Database) and cached along with its
+/// changes.
+///
+/// Like the [`State`](crate::state::State), the changes made while a checkpoint is open are
+/// journaled to be undone by reverting to it.
 #[derive(Clone)]
 pub struct Storage {
     /// The mapping between the contract address and its storage.
-    pub store: HashMap<U256, StorageData>,
+    pub store: HashMap<Address, StorageData>,
+    db: SharedDatabase,
+    /// The changes made since the oldest open checkpoint, the oldest first.
+    journal: Vec<StorageChange>,
+    /// The length of the journal when each open checkpoint was taken.
+    checkpoints: Vec<usize>,
+    /// The slots accessed by the transaction, whose access is warm (EIP-2929).
+    warm: HashSet<(Address, U256)>,
+    /// The value of the slots stored to by the transaction when it started (EIP-2200).
+    original: HashMap<(Address, U256), U256>,
+}
+
+/// A change of the storage, holding what is needed to undo it.
+#[derive(Clone)]
+enum StorageChange {
+    /// A slot was set, `before` being its value loaded before.
+    Slot {
+        address: Address,
+        slot: U256,
+        before: U256,
+    },
+    /// The whole storage of a contract was replaced, `before` being the cached one.
+    Replaced {
+        address: Address,
+        before: Option<StorageData>,
+    },
+    /// A slot was accessed for the first time in the transaction.
+    Warmed { address: Address, slot: U256 },
 }
 
 impl Storage {
-    pub fn new() -> Storage {
+    pub fn new(db: SharedDatabase) -> Storage {
         Storage {
             store: HashMap::default(),
+            db,
+            journal: vec![],
+            checkpoints: vec![],
+            warm: HashSet::new(),
+            original: HashMap::new(),
+        }
+    }
+
+    /// Moves the storage out, leaving an empty one reading the same database in its place, for
+    /// a frame to run on it and give it back.
+    pub(crate) fn take(&mut self) -> Storage {
+        let empty = Storage::new(self.db.clone());
+        std::mem::replace(self, empty)
+    }
+
+    /// Opens a checkpoint, journaling the changes made from now on until it is closed.
+    pub(crate) fn checkpoint(&mut self) -> Checkpoint {
+        self.checkpoints.push(self.journal.len());
+        Checkpoint(self.checkpoints.len() - 1)
+    }
+
+    /// Closes the checkpoint and the ones opened after it, keeping their changes.
+    pub(crate) fn commit(&mut self, checkpoint: Checkpoint) {
+        self.checkpoints.truncate(checkpoint.0);
+        if self.checkpoints.is_empty() {
+            self.journal.clear();
+        }
+    }
+
+    /// Undoes the changes made since the checkpoint, closing it and the ones opened after it.
+    pub(crate) fn revert(&mut self, checkpoint: Checkpoint) {
+        let Some(&length) = self.checkpoints.get(checkpoint.0) else {
+            return;
+        };
+        self.checkpoints.truncate(checkpoint.0);
+
+        for change in self.journal.drain(length..).rev() {
+            match change {
+                StorageChange::Slot {
+                    address,
+                    slot,
+                    before,
+                } => self
+                    .store
+                    .entry(address)
+                    .or_default()
+                    .set_value(slot, before),
+                StorageChange::Replaced {
+                    address,
+                    before: Some(before),
+                } => {
+                    self.store.insert(address, before);
+                }
+                StorageChange::Replaced {
+                    address,
+                    before: None,
+                } => {
+                    self.store.remove(&address);
+                }
+                StorageChange::Warmed { address, slot } => {
+                    self.warm.remove(&(address, slot));
+                }
+            }
+        }
+    }
+
+    /// Returns the slots set since the checkpoint, with their value when it was taken.
+    pub(crate) fn changes(&self, checkpoint: Checkpoint) -> BTreeMap<(Address, U256), U256> {
+        let length = self
+            .checkpoints
+            .get(checkpoint.0)
+            .copied()
+            .unwrap_or(self.journal.len());
+        let mut changes = BTreeMap::new();
+        for change in &self.journal[length..] {
+            if let StorageChange::Slot {
+                address,
+                slot,
+                before,
+            } = change
+            {
+                changes.entry((*address, *slot)).or_insert(*before);
+            }
+        }
+        changes
+    }
+
+    /// Forgets the slots accessed and stored to by the previous transaction.
+    pub(crate) fn start_transaction(&mut self) {
+        self.warm.clear();
+        self.original.clear();
+    }
+
+    /// Marks the slot as accessed by the transaction, returning true if it wasn't: the first
+    /// access of a slot is cold, and costs more (EIP-2929).
+    pub(crate) fn access(&mut self, address: Address, slot: U256) -> bool {
+        if !self.warm.insert((address, slot)) {
+            return false;
+        }
+        if !self.checkpoints.is_empty() {
+            self.journal.push(StorageChange::Warmed { address, slot });
+        }
+        true
+    }
+
+    /// Returns the value of the slot when the transaction started, which prices its stores
+    /// (EIP-2200). It is recorded on the first call, which must come before the transaction
+    /// first stores to the slot.
+    pub(crate) fn original_value(
+        &mut self,
+        address: Address,
+        slot: U256,
+    ) -> Result<U256, DatabaseError> {
+        if let Some(value) = self.original.get(&(address, slot)) {
+            return Ok(*value);
         }
+        let value = self.load_slot(address, slot)?;
+        self.original.insert((address, slot), value);
+        Ok(value)
     }
 
-    pub fn set_constract_slot(&mut self, address: U256, slot: U256, value: U256) {
-        let contract_storage = self
-            .store
-            .entry(address)
-            .or_insert_with(|| StorageData::default());
+    pub fn set_constract_slot(
+        &mut self,
+        address: Address,
+        slot: U256,
+        value: U256,
+    ) -> Result<(), DatabaseError> {
+        if !self.checkpoints.is_empty() {
+            let before = self.load_slot(address, slot)?;
+            self.journal.push(StorageChange::Slot {
+                address,
+                slot,
+                before,
+            });
+        }
+        let contract_storage = self.store.entry(address).or_default();
+
+        contract_storage.set_value(slot, value);
+        Ok(())
+    }
+
+    /// Replaces the whole storage of a contract, ignoring the one in the database.
+    pub fn replace_storage(&mut self, address: Address, slots: HashMap<U256, U256>) {
+        let contract_storage = StorageData {
+            data: slots,
+            replaced: true,
+        };
+        let before = self.store.insert(address, contract_storage);
+        if !self.checkpoints.is_empty() {
+            self.journal
+                .push(StorageChange::Replaced { address, before });
+        }
+    }
 
+    /// Returns the value of a slot, loading it from the database on first access.
+    pub fn load_slot(&mut self, address: Address, slot: U256) -> Result<U256, DatabaseError> {
+        let contract_storage = self.store.entry(address).or_default();
+        if contract_storage.replaced || contract_storage.data.contains_key(&slot) {
+            return Ok(contract_storage.get_value(slot));
+        }
+
+        let value = self.db.storage(address, slot)?;
         contract_storage.set_value(slot, value);
+        Ok(value)
     }
+}
 
-    pub fn load_slot(&self, address: U256, slot: U256) -> U256 {
-        if let Some(contract_address) = self.store.get(&address) {
-            contract_address.get_value(slot)
-        } else {
-            U256::zero()
+/// The serialized form of a [`Storage`], its slots sorted by address and slot.
+#[derive(Serialize, Deserialize)]
+struct SerializedStorage {
+    slots: BTreeMap<Address, BTreeMap<U256, U256>>,
+    /// The contracts whose whole storage was replaced, not reading the database.
+    #[serde(default)]
+    replaced: BTreeSet<Address>,
+}
+
+/// Serializes the slots loaded or changed by the execution, along with the contracts whose
+/// storage was replaced, not the rest of the database.
+impl Serialize for Storage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SerializedStorage {
+            slots: self
+                .store
+                .iter()
+                .map(|(address, slots)| {
+                    (*address, slots.data.iter().map(|(k, v)| (*k, *v)).collect())
+                })
+                .collect(),
+            replaced: self
+                .store
+                .iter()
+                .filter(|(_, slots)| slots.replaced)
+                .map(|(address, _)| *address)
+                .collect(),
         }
+        .serialize(serializer)
+    }
+}
+
+/// Deserializes a storage detached from the database of the serialized one: it reads no
+/// database, the slots missing being zero.
+impl<'de> Deserialize<'de> for Storage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Storage, D::Error> {
+        let storage = SerializedStorage::deserialize(deserializer)?;
+        let mut store: HashMap<Address, StorageData> = storage
+            .slots
+            .into_iter()
+            .map(|(address, slots)| {
+                let slots = StorageData {
+                    data: slots.into_iter().collect(),
+                    replaced: false,
+                };
+                (address, slots)
+            })
+            .collect();
+        for address in storage.replaced {
+            store.entry(address).or_default().replaced = true;
+        }
+
+        Ok(Storage {
+            store,
+            db: Arc::new(EmptyDB),
+            journal: vec![],
+            checkpoints: vec![],
+            warm: HashSet::new(),
+            original: HashMap::new(),
+        })
     }
 }
 
@@ -39,6 +289,9 @@ impl Storage {
 pub struct StorageData {
     /// Mapping between storage slot and value.
     pub data: HashMap<U256, U256>,
+    /// The storage was replaced, the slots missing from `data` being zero instead of read from
+    /// the database.
+    pub replaced: bool,
 }
 
 impl StorageData {
@@ -50,3 +303,51 @@ impl StorageData {
         *self.data.get(&slot).unwrap_or(&0.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::InMemoryDB;
+
+    #[test]
+    fn serde_round_trip_keeps_replaced_storage() {
+        let address = Address::from(1);
+        let mut db = InMemoryDB::new();
+        db.insert_storage(address, 1.into(), 5.into());
+        db.insert_storage(Address::from(2), 1.into(), 6.into());
+        let mut storage = Storage::new(Arc::new(db));
+        storage.replace_storage(address, HashMap::from([(U256::zero(), 7.into())]));
+        storage.load_slot(Address::from(2), 1.into()).unwrap();
+
+        let json = serde_json::to_string(&storage).unwrap();
+        let mut storage: Storage = serde_json::from_str(&json).unwrap();
+
+        assert!(storage.store[&address].replaced);
+        assert!(!storage.store[&Address::from(2)].replaced);
+        assert_eq!(storage.load_slot(address, U256::zero()).unwrap(), 7.into());
+        assert_eq!(
+            storage.load_slot(Address::from(2), 1.into()).unwrap(),
+            6.into()
+        );
+        assert_eq!(serde_json::to_string(&storage).unwrap(), json);
+    }
+
+    #[test]
+    fn revert_restores_replaced_storage() {
+        let address = Address::from(1);
+        let mut db = InMemoryDB::new();
+        db.insert_storage(address, 1.into(), 5.into());
+        let mut storage = Storage::new(Arc::new(db));
+
+        let checkpoint = storage.checkpoint();
+        storage.replace_storage(address, HashMap::new());
+        storage
+            .set_constract_slot(address, 2.into(), 3.into())
+            .unwrap();
+        assert_eq!(storage.load_slot(address, 1.into()).unwrap(), U256::zero());
+        storage.revert(checkpoint);
+
+        assert_eq!(storage.load_slot(address, 1.into()).unwrap(), 5.into());
+        assert_eq!(storage.load_slot(address, 2.into()).unwrap(), U256::zero());
+    }
+}
@@ -0,0 +1,368 @@
+use hex_literal::hex;
+use thiserror::Error;
+
+use crate::{
+    block::BlockEnv,
+    db::DatabaseError,
+    errors::ExecutionError,
+    evm::{checkpoint, execute_frame, settle, Evm, ExecutionResult},
+    gas::Gas,
+    outcome::Outcome,
+    precompile::Precompiles,
+    primitives::{Address, B256},
+    spec::SpecId,
+    state::State,
+    storage::Storage,
+    tx::TxEnv,
+};
+
+/// Sender of the system calls, which is not an account and pays no gas.
+pub const SYSTEM_ADDRESS: Address = Address(hex!("fffffffffffffffffffffffffffffffffffffffe"));
+
+/// Address of the beacon roots contract (EIP-4788).
+pub const BEACON_ROOTS_ADDRESS: Address = Address(hex!("000f3df6d732807ef1319fb7b8bb8522d0beac02"));
+
+/// Runtime code of the beacon roots contract, keeping the roots of the last 8191 beacon blocks
+/// indexed by timestamp.
+pub const BEACON_ROOTS_CODE: &[u8] = &hex!(
+    "3373fffffffffffffffffffffffffffffffffffffffe14604d57602036146024575f5ffd5b5f35801560495762"
+    "001fff810690815414603c575f5ffd5b62001fff01545f5260205ff35b5f5ffd5b62001fff42064281555f3590"
+    "62001fff015500"
+);
+
+/// Address of the block hash history contract (EIP-2935).
+pub const HISTORY_STORAGE_ADDRESS: Address =
+    Address(hex!("0000f90827f1c53a10cb7a02335b175320002935"));
+
+/// Runtime code of the block hash history contract, keeping the hashes of the last 8191
+/// blocks indexed by number.
+pub const HISTORY_STORAGE_CODE: &[u8] = &hex!(
+    "3373fffffffffffffffffffffffffffffffffffffffe14604657602036036042575f35600143038111604257"
+    "611fff81430311604257611fff9006545f5260205ff35b5f5ffd5b5f35611fff60014303065500"
+);
+
+/// Inputs of the system calls made before the transactions of a block.
+#[derive(Debug, Clone, Default)]
+pub struct SystemCalls {
+    /// The root of the parent beacon block, stored since Cancun.
+    pub parent_beacon_block_root: Option<B256>,
+    /// The hash of the parent block, stored since Prague.
+    pub parent_hash: Option<B256>,
+    /// Deploys the contracts with their canonical code when the state doesn't hold them.
+    /// Otherwise, the calls to missing contracts are skipped, leaving the state unchanged.
+    pub deploy_missing: bool,
+}
+
+/// What a system call did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemCallStatus {
+    /// The contract ran and stored its input.
+    Stored,
+    /// The state holds no code at the address of the contract, the call was skipped.
+    MissingContract,
+}
+
+/// The status of the system calls starting a block, `None` for the calls not run, their input
+/// not being given or their fork not being enabled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SystemCallsReport {
+    pub parent_beacon_block_root: Option<SystemCallStatus>,
+    pub parent_hash: Option<SystemCallStatus>,
+}
+
+/// An error of a system call, which makes the block it starts invalid (EIP-4788 and EIP-2935).
+#[derive(Debug, Error)]
+pub enum SystemCallError {
+    #[error("the system call to {address} failed: {outcome:?}")]
+    Failed { address: Address, outcome: Outcome },
+    #[error(transparent)]
+    Database(#[from] DatabaseError),
+}
+
+/// Runs the system calls starting a block, storing the parent beacon root and the parent
+/// block hash in their contracts, and reports what each call did.
+///
+/// The contracts are only deployed with their canonical code if the state doesn't hold them
+/// and [`SystemCalls::deploy_missing`] is set, the calls to missing contracts being reported
+/// as [`SystemCallStatus::MissingContract`]. A call reverting or halting makes the block
+/// invalid: its changes are reverted and it is returned as [`SystemCallError::Failed`], the
+/// calls after it not being run.
+pub fn apply_system_calls(
+    state: &mut State,
+    storage: &mut Storage,
+    block_env: &BlockEnv,
+    system_calls: &SystemCalls,
+    spec: SpecId,
+    precompiles: &Precompiles,
+) -> Result<SystemCallsReport, SystemCallError> {
+    let mut report = SystemCallsReport::default();
+    if let Some(root) = system_calls.parent_beacon_block_root {
+        if spec.is_enabled_in(SpecId::Cancun) {
+            if system_calls.deploy_missing {
+                deploy_if_missing(state, BEACON_ROOTS_ADDRESS, BEACON_ROOTS_CODE)?;
+            }
+            report.parent_beacon_block_root = Some(system_call(
+                state,
+                storage,
+                block_env,
+                BEACON_ROOTS_ADDRESS,
+                &root.0,
+                spec,
+                precompiles,
+            )?);
+        }
+    }
+
+    if let Some(hash) = system_calls.parent_hash {
+        if spec.is_enabled_in(SpecId::Prague) {
+            if system_calls.deploy_missing {
+                deploy_if_missing(state, HISTORY_STORAGE_ADDRESS, HISTORY_STORAGE_CODE)?;
+            }
+            report.parent_hash = Some(system_call(
+                state,
+                storage,
+                block_env,
+                HISTORY_STORAGE_ADDRESS,
+                &hash.0,
+                spec,
+                precompiles,
+            )?);
+        }
+    }
+    Ok(report)
+}
+
+fn deploy_if_missing(
+    state: &mut State,
+    address: Address,
+    code: &[u8],
+) -> Result<(), DatabaseError> {
+    if state.get_code(address)?.is_empty() {
+        state.set_code(address, code.to_vec())?;
+    }
+    Ok(())
+}
+
+/// Calls a system contract from the system address, without gas limit. A failing call, or a
+/// call to an address without code, leaves the state unchanged.
+fn system_call(
+    state: &mut State,
+    storage: &mut Storage,
+    block_env: &BlockEnv,
+    address: Address,
+    calldata: &[u8],
+    spec: SpecId,
+    precompiles: &Precompiles,
+) -> Result<SystemCallStatus, SystemCallError> {
+    let tx_env = TxEnv {
+        to: address,
+        caller: SYSTEM_ADDRESS,
+        origin: SYSTEM_ADDRESS,
+        data: calldata.to_vec(),
+        ..TxEnv::default()
+    };
+
+    let code = state.get_code(address)?;
+    if code.is_empty() {
+        return Ok(SystemCallStatus::MissingContract);
+    }
+
+    let checkpoint = checkpoint(state, storage);
+    let evm = Evm::new(
+        Box::from(code),
+        vec![],
+        tx_env,
+        block_env.clone(),
+        state.take(),
+        storage.take(),
+        vec![],
+        vec![],
+        vec![],
+        spec,
+        precompiles.clone(),
+        false,
+    );
+
+    // system calls are not charged, the gas spent is dropped.
+    let mut gas = Gas::new(u64::MAX);
+    let (result, output) = match execute_frame(evm, state, storage, &mut gas) {
+        Ok(frame) => frame,
+        Err(ExecutionError::Database(error)) => return Err(error.into()),
+        Err(_) => (ExecutionResult::Revert, vec![]),
+    };
+    settle(state, storage, checkpoint, &result);
+    match result {
+        ExecutionResult::Success(_) => Ok(SystemCallStatus::Stored),
+        result => Err(SystemCallError::Failed {
+            address,
+            outcome: Outcome::new(result, output),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use primitive_types::U256;
+
+    use super::*;
+    use crate::db::EmptyDB;
+
+    const ROOT: B256 = B256([0xaa; 32]);
+    const PARENT_HASH: B256 = B256([0xbb; 32]);
+    const HISTORY_BUFFER_LENGTH: u64 = 8191;
+
+    fn block_env() -> BlockEnv {
+        BlockEnv {
+            timestamp: 20000,
+            number: 10000,
+            ..BlockEnv::default()
+        }
+    }
+
+    fn apply(
+        spec: SpecId,
+        state: &mut State,
+        storage: &mut Storage,
+    ) -> Result<SystemCallsReport, SystemCallError> {
+        let system_calls = SystemCalls {
+            parent_beacon_block_root: Some(ROOT),
+            parent_hash: Some(PARENT_HASH),
+            deploy_missing: true,
+        };
+        let precompiles = Precompiles::new(spec);
+        apply_system_calls(
+            state,
+            storage,
+            &block_env(),
+            &system_calls,
+            spec,
+            &precompiles,
+        )
+    }
+
+    fn state() -> (State, Storage) {
+        let db = Arc::new(EmptyDB);
+        (State::new(db.clone()), Storage::new(db))
+    }
+
+    #[test]
+    fn stores_the_beacon_root_by_timestamp() {
+        let (mut state, mut storage) = state();
+        let report = apply(SpecId::Cancun, &mut state, &mut storage).unwrap();
+
+        assert_eq!(
+            report,
+            SystemCallsReport {
+                parent_beacon_block_root: Some(SystemCallStatus::Stored),
+                parent_hash: None,
+            }
+        );
+        let slot = U256::from(20000 % HISTORY_BUFFER_LENGTH);
+        assert_eq!(
+            storage.load_slot(BEACON_ROOTS_ADDRESS, slot).unwrap(),
+            20000.into()
+        );
+        assert_eq!(
+            storage
+                .load_slot(BEACON_ROOTS_ADDRESS, slot + HISTORY_BUFFER_LENGTH)
+                .unwrap(),
+            ROOT.to_word()
+        );
+        assert_eq!(
+            state.get_code(BEACON_ROOTS_ADDRESS).unwrap(),
+            BEACON_ROOTS_CODE
+        );
+        // the parent hash is only stored since Prague.
+        assert!(state.get_code(HISTORY_STORAGE_ADDRESS).unwrap().is_empty());
+        // the system address is not an account.
+        assert_eq!(state.get_nonce(SYSTEM_ADDRESS).unwrap(), 0);
+    }
+
+    #[test]
+    fn stores_the_parent_hash_by_number() {
+        let (mut state, mut storage) = state();
+        apply(SpecId::Prague, &mut state, &mut storage).unwrap();
+
+        let slot = U256::from((10000 - 1) % HISTORY_BUFFER_LENGTH);
+        assert_eq!(
+            storage.load_slot(HISTORY_STORAGE_ADDRESS, slot).unwrap(),
+            PARENT_HASH.to_word()
+        );
+        assert_eq!(
+            state.get_code(HISTORY_STORAGE_ADDRESS).unwrap(),
+            HISTORY_STORAGE_CODE
+        );
+    }
+
+    #[test]
+    fn nothing_is_stored_before_cancun() {
+        let (mut state, mut storage) = state();
+        let report = apply(SpecId::Shanghai, &mut state, &mut storage).unwrap();
+        assert_eq!(report, SystemCallsReport::default());
+        assert!(state.get_code(BEACON_ROOTS_ADDRESS).unwrap().is_empty());
+        assert!(state.get_code(HISTORY_STORAGE_ADDRESS).unwrap().is_empty());
+    }
+
+    #[test]
+    fn missing_contracts_are_only_deployed_on_request() {
+        let (mut state, mut storage) = state();
+        let system_calls = SystemCalls {
+            parent_beacon_block_root: Some(ROOT),
+            parent_hash: Some(PARENT_HASH),
+            deploy_missing: false,
+        };
+        let spec = SpecId::Prague;
+        let report = apply_system_calls(
+            &mut state,
+            &mut storage,
+            &block_env(),
+            &system_calls,
+            spec,
+            &Precompiles::new(spec),
+        )
+        .unwrap();
+
+        assert_eq!(
+            report,
+            SystemCallsReport {
+                parent_beacon_block_root: Some(SystemCallStatus::MissingContract),
+                parent_hash: Some(SystemCallStatus::MissingContract),
+            }
+        );
+
+        assert_eq!(state.account(BEACON_ROOTS_ADDRESS).unwrap(), None);
+        assert_eq!(state.account(HISTORY_STORAGE_ADDRESS).unwrap(), None);
+        let slot = U256::from((10000 - 1) % HISTORY_BUFFER_LENGTH);
+        assert_eq!(
+            storage.load_slot(HISTORY_STORAGE_ADDRESS, slot).unwrap(),
+            U256::zero()
+        );
+    }
+
+    #[test]
+    fn failing_system_call_is_reported_and_leaves_the_state_unchanged() {
+        let (mut state, mut storage) = state();
+        // SSTORE(1, 1) then REVERT, in place of the beacon roots contract.
+        let code = vec![0x60, 0x01, 0x60, 0x01, 0x55, 0x5f, 0x5f, 0xfd];
+        state.set_code(BEACON_ROOTS_ADDRESS, code.clone()).unwrap();
+        let result = apply(SpecId::Prague, &mut state, &mut storage);
+
+        assert!(matches!(
+            result,
+            Err(SystemCallError::Failed {
+                address: BEACON_ROOTS_ADDRESS,
+                outcome: Outcome::Revert { .. },
+            })
+        ));
+        // the parent hash is not stored by an invalid block.
+        assert!(state.get_code(HISTORY_STORAGE_ADDRESS).unwrap().is_empty());
+
+        assert_eq!(state.get_code(BEACON_ROOTS_ADDRESS).unwrap(), code);
+        assert_eq!(
+            storage.load_slot(BEACON_ROOTS_ADDRESS, 1.into()).unwrap(),
+            U256::zero()
+        );
+    }
+}
@@ -1,27 +1,71 @@
-/// Tx data.
-#[derive(Default)]
-pub struct TxData {
-    pub to: Vec<u8>,
-    pub from: Vec<u8>,
-    pub origin: Vec<u8>,
-    pub gasprice: Vec<u8>,
-    pub value: Vec<u8>,
+use primitive_types::U256;
+
+use crate::{
+    builder::{word, BuilderError},
+    primitives::Address,
+    utils::saturating_u64,
+};
+
+/// The transaction environment of a frame.
+#[derive(Debug, Clone)]
+pub struct TxEnv {
+    /// The account executing the code.
+    pub to: Address,
+    /// The sender of the message.
+    pub caller: Address,
+    /// The sender of the transaction.
+    pub origin: Address,
+    pub gas_price: U256,
+    pub value: U256,
     pub data: Vec<u8>,
+    pub gas_limit: u64,
 }
 
-impl TxData {
-    pub fn new(tx_data: Vec<Vec<u8>>) -> TxData {
-        if !tx_data.is_empty() {
-            return Self {
-                to: tx_data[0].clone(),
-                from: tx_data[1].clone(),
-                origin: tx_data[2].clone(),
-                gasprice: tx_data[3].clone(),
-                value: tx_data[4].clone(),
-                data: tx_data[5].clone(),
-            };
+impl Default for TxEnv {
+    fn default() -> TxEnv {
+        TxEnv {
+            to: Address::ZERO,
+            caller: Address::ZERO,
+            origin: Address::ZERO,
+            gas_price: U256::zero(),
+            value: U256::zero(),
+            data: vec![],
+            gas_limit: u64::MAX,
         }
+    }
+}
+
+impl TxEnv {
+    /// Reads the positional fields `[to, from, origin, gasprice, value, data, gas]` as
+    /// big-endian words, the missing ones being left to their default. Addresses keep their
+    /// low 20 bytes. Words longer than 32 bytes are rejected.
+    pub fn new(tx_data: Vec<Vec<u8>>) -> Result<TxEnv, BuilderError> {
+        let mut fields = tx_data.into_iter();
+        let mut next = || fields.next().unwrap_or_default();
+        let mut address = |field| word(field, &next()).map(Address::from_word);
+
+        let to = address("to")?;
+        let caller = address("from")?;
+        let origin = address("origin")?;
+        let gas_price = word("gasprice", &next())?;
+        let value = word("value", &next())?;
+        let data = next();
+        let gas = next();
+        // an empty gas limit is unlimited.
+        let gas_limit = if gas.is_empty() {
+            u64::MAX
+        } else {
+            saturating_u64(word("gas", &gas)?)
+        };
 
-        Self::default()
+        Ok(TxEnv {
+            to,
+            caller,
+            origin,
+            gas_price,
+            value,
+            data,
+            gas_limit,
+        })
     }
 }
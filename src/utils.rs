@@ -1,15 +1,47 @@
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+use primitive_types::U256;
 use sha3::{Digest, Keccak256};
 
 pub fn sha3_hash(data: &[u8]) -> [u8; 32] {
-    if data.is_empty() {
-        let result = [0; 32];
-        result
-    } else {
-        // create hash
-        let mut hasher = Keccak256::new();
-        hasher.update(data);
-        let result = hasher.finalize();
+    // create hash
+    let mut hasher = Keccak256::new();
+    hasher.update(data);
+    let result = hasher.finalize();
+
+    result.into()
+}
+
+/// Recovers the address that signed the `hash`, as done by `ecrecover`.
+pub fn recover_address(
+    hash: &[u8; 32],
+    y_parity: u8,
+    r: &[u8; 32],
+    s: &[u8; 32],
+) -> Option<[u8; 20]> {
+    let mut signature = Signature::from_scalars(*r, *s).ok()?;
+    let mut recovery_id = RecoveryId::from_byte(y_parity)?;
+
+    // signatures with a high `s` are valid, but not accepted by `k256` as is.
+    if let Some(normalized) = signature.normalize_s() {
+        signature = normalized;
+        recovery_id = RecoveryId::from_byte(recovery_id.to_byte() ^ 1)?;
+    }
+
+    let key = VerifyingKey::recover_from_prehash(hash, &signature, recovery_id).ok()?;
+    let public_key = key.to_encoded_point(false);
 
-        result.into()
+    // the address is the last 20 bytes of the hash of the uncompressed public key.
+    let hash = sha3_hash(&public_key.as_bytes()[1..]);
+    let mut address = [0u8; 20];
+    address.copy_from_slice(&hash[12..]);
+    Some(address)
+}
+
+/// Converts a word to a `u64`, saturating at `u64::MAX`.
+pub fn saturating_u64(value: U256) -> u64 {
+    if value > U256::from(u64::MAX) {
+        u64::MAX
+    } else {
+        value.as_u64()
     }
 }